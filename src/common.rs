@@ -18,19 +18,94 @@ pub(crate) fn random_cpf() -> u64 {
     cpf
 }
 
-pub(crate) fn random_rg() -> u64 {
-    // returns a valid RG
+// RG issued by `state`, formatted as printed on the card. SP uses eight digits and a
+// mod-11 check digit ("X" for 10), MG prefixes its number with the UF, RJ issues eight
+// digits and the other states seven, neither with a check digit we model.
+pub(crate) fn random_rg(state: &str) -> String {
     let mut rng = rand::thread_rng();
-    let rg: u64 = format!(
-        "{:03}{:03}{:03}{:02}",
-        rng.gen_range(0..999),
-        rng.gen_range(0..999),
-        rng.gen_range(0..999),
-        rng.gen_range(0..99)
-    )
-    .parse()
-    .unwrap();
-    rg
+    match state {
+        "SP" => {
+            let digits: Vec<u32> = (0..8).map(|_| rng.gen_range(0..10)).collect();
+            let number: String = digits
+                .iter()
+                .map(|digit| char::from_digit(*digit, 10).unwrap())
+                .collect();
+            format!(
+                "{}.{}.{}-{}",
+                &number[..2],
+                &number[2..5],
+                &number[5..],
+                sp_rg_check_digit(&digits)
+            )
+        }
+        "MG" => format!(
+            "MG-{:02}.{:03}.{:03}",
+            rng.gen_range(1..100),
+            rng.gen_range(0..1000),
+            rng.gen_range(0..1000)
+        ),
+        "RJ" => format!(
+            "{:02}.{:03}.{:03}",
+            rng.gen_range(1..100),
+            rng.gen_range(0..1000),
+            rng.gen_range(0..1000)
+        ),
+        _ => format!(
+            "{}.{:03}.{:03}",
+            rng.gen_range(1..10),
+            rng.gen_range(0..1000),
+            rng.gen_range(0..1000)
+        ),
+    }
+}
+
+// Check digit of an SP RG: the digits are weighted 2 to 9 from the left and the digit
+// is 11 minus the sum modulo 11, "X" for 10 and "0" for 11
+fn sp_rg_check_digit(digits: &[u32]) -> char {
+    let sum: u32 = digits
+        .iter()
+        .zip(2..)
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    match 11 - sum % 11 {
+        10 => 'X',
+        11 => '0',
+        digit => char::from_digit(digit, 10).unwrap(),
+    }
+}
+
+// Body issuing the RG of `state`, e.g. "SSP/SP"
+pub(crate) fn rg_issuer(state: &str) -> String {
+    match state {
+        "RJ" => format!("DETRAN/{}", state),
+        _ => format!("SSP/{}", state),
+    }
+}
+
+// CNH (driving licence) registration number: nine digits followed by two check digits
+pub(crate) fn random_cnh() -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let digits: Vec<u32> = (0..9).map(|_| rng.gen_range(0..10)).collect();
+        if digits.iter().all(|digit| *digit == digits[0]) {
+            continue;
+        }
+        // Bases whose first remainder is 10 are skipped, as issuers disagree on how
+        // that case shifts the second digit
+        let first = (0..9).map(|i| digits[i] * (9 - i as u32)).sum::<u32>() % 11;
+        if first >= 10 {
+            continue;
+        }
+        let second = match (0..9).map(|i| digits[i] * (i as u32 + 1)).sum::<u32>() % 11 {
+            10 => 0,
+            remainder => remainder,
+        };
+        return digits
+            .iter()
+            .chain([first, second].iter())
+            .map(|digit| char::from_digit(*digit, 10).unwrap())
+            .collect();
+    }
 }
 
 pub(crate) fn random_cnpj() -> String {
@@ -47,22 +122,38 @@ pub(crate) fn random_cnpj() -> String {
     cnpj
 }
 
-pub(crate) fn random_br_phone() -> u64 {
+// Subscriber number without DDD: mobiles have nine digits starting with 9, landlines
+// eight digits starting with 2 to 5 (Anatel numbering plan)
+pub(crate) fn random_br_phone(mobile: bool) -> u64 {
     let mut rng = rand::thread_rng();
-
-    // Generate a number between 9000_0000 and 9999_9999
-    let phone_number: u64 = rng.gen_range(9_000_0000..=9_999_9999);
-
-    phone_number
+    if mobile {
+        rng.gen_range(960_000_000..=999_999_999)
+    } else {
+        rng.gen_range(20_000_000..=59_999_999)
+    }
 }
 
-pub(crate) fn random_cep() -> u64 {
-    // returns a valid BR CEP
-    let mut rng = rand::thread_rng();
-    let cep: u64 = format!("{:05}{:03}", rng.gen_range(0..99999), rng.gen_range(0..999))
-        .parse()
-        .unwrap();
-    cep
+// Replaces the accented letters of Portuguese with their base letter, keeping the case
+pub(crate) fn unaccent(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            'Á' | 'À' | 'Â' | 'Ã' | 'Ä' => 'A',
+            'É' | 'È' | 'Ê' | 'Ë' => 'E',
+            'Í' | 'Ì' | 'Î' | 'Ï' => 'I',
+            'Ó' | 'Ò' | 'Ô' | 'Õ' | 'Ö' => 'O',
+            'Ú' | 'Ù' | 'Û' | 'Ü' => 'U',
+            'Ç' => 'C',
+            'Ñ' => 'N',
+            other => other,
+        })
+        .collect()
 }
 
 pub(crate) async fn fetch_data<T: for<'a> serde::de::Deserialize<'a>>(
@@ -80,6 +171,7 @@ pub(crate) async fn _fetch_with_exponential_backoff<T: for<'a> serde::de::Deseri
     let mut delay = 5; // Start with a 5-second delay
 
     loop {
+        #[allow(clippy::needless_borrow)]
         match fetch_data::<T>(&client, &url).await {
             Ok(result) => return Ok(result),
             Err(error) => {
@@ -167,11 +259,20 @@ impl ProgressBarHelper {
 }
 
 pub(crate) fn current_timestamp() -> String {
-    let mut curent_date = chrono::Local::now().to_string(); // 2023-11-02 11:27:08.122216600 -03:00
-    curent_date = curent_date[..19].to_string();
+    to_date(chrono::Local::now().naive_local())
+}
 
+pub(crate) fn to_date(date: chrono::NaiveDateTime) -> String {
     // TO_DATE('2023-11-02 11:27:08', 'YYYY-MM-DD HH24:MI:SS')
-    format!("TO_DATE('{}', 'YYYY-MM-DD HH24:MI:SS')", curent_date)
+    format!(
+        "TO_DATE('{}', 'YYYY-MM-DD HH24:MI:SS')",
+        date.format("%Y-%m-%d %H:%M:%S")
+    )
+}
+
+pub(crate) fn parse_to_date(literal: &str) -> Option<chrono::NaiveDateTime> {
+    let date = literal.strip_prefix("TO_DATE('")?.get(..19)?;
+    chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok()
 }
 
 pub(crate) fn create_data_dir() {
@@ -214,6 +315,7 @@ use std::ops::{Bound, RangeBounds};
 
 pub(crate) trait StringUtils {
     fn substring(&self, start: usize, len: usize) -> &str;
+    #[allow(dead_code)]
     fn slice(&self, range: impl RangeBounds<usize>) -> &str;
 }
 
@@ -261,3 +363,49 @@ impl StringUtils for str {
         self.substring(start, len)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An SP RG is valid when the weighted sum plus 100 times its check digit ("X" is 10)
+    // is a multiple of 11
+    fn valid_sp_rg(rg: &str) -> bool {
+        let (number, check) = rg.split_once('-').unwrap();
+        let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+        let check = if check == "X" {
+            10
+        } else {
+            check.parse::<u32>().unwrap()
+        };
+        let sum: u32 = digits
+            .iter()
+            .zip(2..)
+            .map(|(digit, weight)| digit * weight)
+            .sum();
+        digits.len() == 8 && (sum + 100 * check).is_multiple_of(11)
+    }
+
+    #[test]
+    fn sp_rg_check_digit_matches_known_numbers() {
+        for rg in [
+            "12.345.678-2",
+            "60.000.000-X",
+            "10.000.001-0",
+            "11.000.000-6",
+        ] {
+            assert!(valid_sp_rg(rg), "{}", rg);
+            let digits: Vec<u32> = rg[..10].chars().filter_map(|c| c.to_digit(10)).collect();
+            assert_eq!(sp_rg_check_digit(&digits).to_string(), &rg[11..]);
+        }
+        assert!(!valid_sp_rg("12.345.678-3"));
+    }
+
+    #[test]
+    fn generated_sp_rgs_are_valid() {
+        for _ in 0..1000 {
+            let rg = random_rg("SP");
+            assert!(valid_sp_rg(&rg), "{}", rg);
+        }
+    }
+}
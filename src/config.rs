@@ -0,0 +1,491 @@
+use anyhow::{bail, Context};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path, sync::OnceLock};
+
+use crate::common::to_date;
+
+const DEFAULT_PROFILE: &str = include_str!("../profiles/default.toml");
+
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+// Generation profile: table sizes, value distributions and date windows
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    pub(crate) total_rows: u64,
+    admissions: Admissions,
+    exams: Exams,
+    employees: Employees,
+    addresses: Addresses,
+    phones: Phones,
+    emails: Emails,
+    references: BTreeMap<String, ReferenceProfile>,
+    tables: BTreeMap<String, TableProfile>,
+}
+
+// Bed occupancy and length of stay of inpatient admissions
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Admissions {
+    occupancy: f64,
+    mean_stay_days: f64,
+    // Occupancy overrides keyed by ID_UNID_HOSPITAL
+    #[serde(default)]
+    by_hospital: BTreeMap<String, f64>,
+}
+
+// Abnormal share of laboratory results and spread of a patient's repeated measurements
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Exams {
+    pub(crate) out_of_range: f64,
+    pub(crate) variation: f64,
+}
+
+// Shape of each hospital's org chart, doctors' allocations and specialties
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Employees {
+    pub(crate) span_of_control: usize,
+    pub(crate) max_depth: usize,
+    pub(crate) second_hospital: f64,
+    pub(crate) extra_specialty: f64,
+}
+
+// Rendering of generated addresses
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Addresses {
+    pub(crate) formatted_cep: bool,
+}
+
+// Country calling code of generated phone numbers
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Phones {
+    pub(crate) ddi: u64,
+}
+
+// Providers of personal email addresses, weighted by their share of mailboxes
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Emails {
+    pub(crate) providers: Enumeration,
+}
+
+// Version of the bundled list a reference table is filled from, and the entries
+// flagged as no longer in use
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ReferenceProfile {
+    pub(crate) version: u32,
+    #[serde(default)]
+    pub(crate) inactive: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TableProfile {
+    rows: Option<u64>,
+    ratio: Option<f64>,
+    per: Option<String>,
+    cardinality: Option<Cardinality>,
+    #[serde(default)]
+    enums: BTreeMap<String, Enumeration>,
+    #[serde(default)]
+    dates: BTreeMap<String, DateWindow>,
+}
+
+// Weighted set of values, e.g. `"Solteiro" = 3.0`
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "BTreeMap<String, f64>")]
+pub(crate) struct Enumeration {
+    values: Vec<String>,
+    weights: WeightedIndex<f64>,
+}
+
+// Weighted number of rows for each row of the parent table, e.g. `"2" = 0.35`
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "BTreeMap<String, f64>")]
+pub(crate) struct Cardinality {
+    counts: Vec<usize>,
+    weights: WeightedIndex<f64>,
+    mean: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DateWindow {
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+impl TryFrom<BTreeMap<String, f64>> for Enumeration {
+    type Error = anyhow::Error;
+
+    fn try_from(map: BTreeMap<String, f64>) -> Result<Self, Self::Error> {
+        let (values, weights): (Vec<String>, Vec<f64>) = map.into_iter().unzip();
+        let weights = WeightedIndex::new(&weights)
+            .with_context(|| format!("invalid weights for {:?}", values))?;
+        Ok(Enumeration { values, weights })
+    }
+}
+
+impl TryFrom<BTreeMap<String, f64>> for Cardinality {
+    type Error = anyhow::Error;
+
+    fn try_from(map: BTreeMap<String, f64>) -> Result<Self, Self::Error> {
+        let mut counts = Vec::with_capacity(map.len());
+        for count in map.keys() {
+            counts.push(
+                count
+                    .parse::<usize>()
+                    .with_context(|| format!("{:?} is not a number of rows", count))?,
+            );
+        }
+        let weights = WeightedIndex::new(map.values())
+            .with_context(|| format!("invalid weights for {:?}", counts))?;
+        let total: f64 = map.values().sum();
+        let mean = counts
+            .iter()
+            .zip(map.values())
+            .map(|(count, weight)| *count as f64 * weight)
+            .sum::<f64>()
+            / total;
+        Ok(Cardinality {
+            counts,
+            weights,
+            mean,
+        })
+    }
+}
+
+impl Cardinality {
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        self.counts[self.weights.sample(rng)]
+    }
+}
+
+impl Enumeration {
+    pub(crate) fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> &str {
+        &self.values[self.weights.sample(rng)]
+    }
+}
+
+impl DateWindow {
+    // First and last instant of the window
+    pub(crate) fn bounds(&self) -> (NaiveDateTime, NaiveDateTime) {
+        (
+            self.start.and_hms_opt(0, 0, 0).unwrap(),
+            self.end.and_hms_opt(23, 59, 59).unwrap(),
+        )
+    }
+
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> NaiveDateTime {
+        let seconds = (self.end - self.start).num_seconds();
+        self.start.and_hms_opt(0, 0, 0).unwrap() + Duration::seconds(rng.gen_range(0..=seconds))
+    }
+}
+
+impl Admissions {
+    // Share of time the beds of `hospital` are occupied
+    pub(crate) fn occupancy(&self, hospital: u64) -> f64 {
+        self.by_hospital
+            .get(&hospital.to_string())
+            .copied()
+            .unwrap_or(self.occupancy)
+    }
+
+    pub(crate) fn mean_stay(&self) -> Duration {
+        Duration::seconds((self.mean_stay_days * 86_400.0) as i64)
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let rates = std::iter::once(("default", self.occupancy)).chain(
+            self.by_hospital
+                .iter()
+                .map(|(id, rate)| (id.as_str(), *rate)),
+        );
+        for (hospital, rate) in rates {
+            if !(0.0..1.0).contains(&rate) {
+                bail!("admissions: occupancy of {} must be in [0, 1)", hospital);
+            }
+            if hospital != "default" && hospital.parse::<u64>().is_err() {
+                bail!("admissions: {} is not a hospital id", hospital);
+            }
+        }
+        if !self.mean_stay_days.is_finite() || self.mean_stay_days <= 0.0 {
+            bail!("admissions: mean_stay_days must be positive");
+        }
+        Ok(())
+    }
+}
+
+impl Exams {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(0.0..=1.0).contains(&self.out_of_range) {
+            bail!("exams: out_of_range must be in [0, 1]");
+        }
+        if !self.variation.is_finite() || self.variation < 0.0 {
+            bail!("exams: variation must be a non-negative number");
+        }
+        Ok(())
+    }
+}
+
+impl Employees {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.span_of_control == 0 {
+            bail!("employees: span_of_control must be positive");
+        }
+        if self.max_depth < 2 {
+            bail!("employees: max_depth must be at least 2");
+        }
+        if !(0.0..=1.0).contains(&self.second_hospital) {
+            bail!("employees: second_hospital must be in [0, 1]");
+        }
+        if !(0.0..1.0).contains(&self.extra_specialty) {
+            bail!("employees: extra_specialty must be in [0, 1)");
+        }
+        Ok(())
+    }
+}
+
+impl TableProfile {
+    fn sized(&self) -> bool {
+        self.rows.is_some() || self.ratio.is_some() || self.cardinality.is_some()
+    }
+
+    // Rows drawn for each row of the `per` table, when the table sets a cardinality
+    pub(crate) fn cardinality(&self) -> Option<&Cardinality> {
+        self.cardinality.as_ref()
+    }
+
+    pub(crate) fn enumeration(&self, column: &str) -> &Enumeration {
+        self.enums
+            .get(column)
+            .unwrap_or_else(|| panic!("no enumeration for {}", column))
+    }
+
+    pub(crate) fn date_window(&self, column: &str) -> &DateWindow {
+        self.dates
+            .get(column)
+            .unwrap_or_else(|| panic!("no date window for {}", column))
+    }
+
+    // Picks a weighted value of `column`
+    pub(crate) fn pick<R: Rng + ?Sized>(&self, column: &str, rng: &mut R) -> String {
+        self.enumeration(column).choose(rng).to_string()
+    }
+
+    // Random timestamp of `column` inside its window, as a TO_DATE literal
+    pub(crate) fn date<R: Rng + ?Sized>(&self, column: &str, rng: &mut R) -> String {
+        to_date(self.date_window(column).sample(rng))
+    }
+}
+
+impl Profile {
+    pub(crate) fn load(path: Option<&Path>) -> Result<Profile, anyhow::Error> {
+        let default: Profile = toml::from_str(DEFAULT_PROFILE).context("default profile")?;
+        let profile: Profile = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("reading profile {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("parsing profile {}", path.display()))?
+            }
+            None => toml::from_str(DEFAULT_PROFILE)?,
+        };
+        profile.validate(&default)?;
+        Ok(profile)
+    }
+
+    // Checks the profile is self-consistent and defines everything the default does
+    fn validate(&self, default: &Profile) -> Result<(), anyhow::Error> {
+        if self.total_rows == 0 {
+            bail!("total_rows must be positive");
+        }
+        self.admissions.validate()?;
+        self.exams.validate()?;
+        self.employees.validate()?;
+
+        for name in default.references.keys() {
+            match self.references.get(name) {
+                None => bail!("missing reference {}", name),
+                Some(reference) if reference.version == 0 => {
+                    bail!("references.{}: versions start at 1", name)
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (name, expected) in &default.tables {
+            let Some(table) = self.tables.get(name) else {
+                bail!("missing table {}", name);
+            };
+            if expected.sized() && !table.sized() {
+                bail!("{}: missing `rows` or `ratio`", name);
+            }
+            for column in expected.enums.keys() {
+                if !table.enums.contains_key(column) {
+                    bail!("{}: missing enumeration {}", name, column);
+                }
+            }
+            for column in expected.dates.keys() {
+                if !table.dates.contains_key(column) {
+                    bail!("{}: missing date window {}", name, column);
+                }
+            }
+        }
+
+        let known: Vec<&str> = crate::tables::schema()
+            .iter()
+            .map(|table| table.name)
+            .collect();
+        for (name, table) in &self.tables {
+            if !known.contains(&name.as_str()) {
+                bail!("unknown table {}", name);
+            }
+            if table.rows.is_some() && (table.ratio.is_some() || table.per.is_some()) {
+                bail!("{}: `rows` cannot be combined with `ratio` or `per`", name);
+            }
+            if table.cardinality.is_some() && (table.rows.is_some() || table.ratio.is_some()) {
+                bail!(
+                    "{}: `cardinality` cannot be combined with `rows` or `ratio`",
+                    name
+                );
+            }
+            if table.per.is_some() && table.ratio.is_none() && table.cardinality.is_none() {
+                bail!("{}: `per` requires a `ratio` or a `cardinality`", name);
+            }
+            if table.cardinality.is_some() && table.per.is_none() {
+                bail!("{}: `cardinality` requires `per`", name);
+            }
+            if let Some(ratio) = table.ratio {
+                if !ratio.is_finite() || ratio < 0.0 {
+                    bail!("{}: ratio must be a non-negative number", name);
+                }
+            }
+            for (column, window) in &table.dates {
+                if window.start > window.end {
+                    bail!("{}.{}: date window starts after it ends", name, column);
+                }
+            }
+            if table.sized() {
+                self.try_rows(name, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn admissions(&self) -> &Admissions {
+        &self.admissions
+    }
+
+    pub(crate) fn exams(&self) -> &Exams {
+        &self.exams
+    }
+
+    pub(crate) fn employees(&self) -> &Employees {
+        &self.employees
+    }
+
+    pub(crate) fn addresses(&self) -> &Addresses {
+        &self.addresses
+    }
+
+    pub(crate) fn phones(&self) -> &Phones {
+        &self.phones
+    }
+
+    pub(crate) fn emails(&self) -> &Emails {
+        &self.emails
+    }
+
+    pub(crate) fn reference(&self, name: &str) -> &ReferenceProfile {
+        self.references
+            .get(name)
+            .unwrap_or_else(|| panic!("reference {} not in profile", name))
+    }
+
+    pub(crate) fn table(&self, name: &str) -> &TableProfile {
+        self.tables
+            .get(name)
+            .unwrap_or_else(|| panic!("table {} not in profile", name))
+    }
+
+    // Latest instant of any date window, the "today" the generated data is seen from
+    pub(crate) fn as_of(&self) -> NaiveDateTime {
+        self.tables
+            .values()
+            .flat_map(|table| table.dates.values())
+            .map(|window| window.bounds().1)
+            .max()
+            .unwrap_or_else(|| Local::now().naive_local())
+    }
+
+    // Rows planned for every table the profile sizes, but `excluded`
+    pub(crate) fn planned_rows(&self, excluded: &[&str]) -> usize {
+        self.tables
+            .iter()
+            .filter(|(name, table)| table.sized() && !excluded.contains(&name.as_str()))
+            .map(|(name, _)| self.rows(name))
+            .sum()
+    }
+
+    // Number of rows to generate for `name`, on average when it sets a cardinality
+    pub(crate) fn rows(&self, name: &str) -> usize {
+        self.try_rows(name, 0).unwrap() as usize
+    }
+
+    fn try_rows(&self, name: &str, depth: usize) -> Result<u64, anyhow::Error> {
+        if depth > self.tables.len() {
+            bail!("{}: `per` references form a cycle", name);
+        }
+        let Some(table) = self.tables.get(name) else {
+            bail!("unknown table {}", name);
+        };
+        if let (Some(cardinality), Some(parent)) = (&table.cardinality, &table.per) {
+            return Ok((self.try_rows(parent, depth + 1)? as f64 * cardinality.mean) as u64);
+        }
+        match (table.rows, table.ratio, &table.per) {
+            (Some(rows), _, _) => Ok(rows),
+            (None, Some(ratio), Some(parent)) => {
+                Ok((self.try_rows(parent, depth + 1)? as f64 * ratio) as u64)
+            }
+            (None, Some(ratio), None) => Ok((self.total_rows as f64 * ratio) as u64),
+            _ => bail!("{}: no row count configured", name),
+        }
+    }
+}
+
+pub(crate) fn init(path: Option<&Path>) -> Result<&'static Profile, anyhow::Error> {
+    let profile = Profile::load(path)?;
+    Ok(PROFILE.get_or_init(|| profile))
+}
+
+pub(crate) fn profile() -> &'static Profile {
+    PROFILE.get().expect("profile not loaded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unknown_tables() {
+        let default: Profile = toml::from_str(DEFAULT_PROFILE).unwrap();
+        assert!(default.validate(&default).is_ok());
+
+        let mut extra: Profile = toml::from_str(DEFAULT_PROFILE).unwrap();
+        let patients: TableProfile = toml::from_str("rows = 10").unwrap();
+        extra.tables.insert("T_RHSTU_PACIENT".to_string(), patients);
+        assert_eq!(
+            extra.validate(&default).unwrap_err().to_string(),
+            "unknown table T_RHSTU_PACIENT"
+        );
+    }
+}
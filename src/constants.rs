@@ -1,24 +0,0 @@
-pub(crate) const TOTAL_ROWS: u64 = 10_000_000;
-
-pub(crate) const T_RHSTU_MEDICAMENTO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.00081) as u64;
-pub(crate) const _T_RHSTU_BAIRRO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.001) as u64;
-pub(crate) const _T_RHSTU_CIDADE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0005) as u64;
-pub(crate) const _T_RHSTU_ESTADO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0000027) as u64;
-pub(crate) const T_RHSTU_LOGRADOURO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.055) as u64;
-pub(crate) const T_RHSTU_UNID_HOSPITALAR_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0001) as u64;
-pub(crate) const T_RHSTU_ENDERECO_UNIDHOSP_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0001) as u64;
-pub(crate) const T_RHSTU_FUNCIONARIO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.05) as u64;
-pub(crate) const T_RHSTU_MEDICO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.005) as u64;
-pub(crate) const T_RHSTU_MOTORISTA_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0025) as u64;
-pub(crate) const T_RHSTU_PACIENTE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_TIPO_CONTATO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0000006) as u64;
-pub(crate) const T_RHSTU_CONTATO_PACIENTE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_EMAIL_PACIENTE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_TELEFONE_PACIENTE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_ENDERECO_PACIENTE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_PLANO_SAUDE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.000001) as u64;
-pub(crate) const T_RHSTU_PACIENTE_PLANO_SAUDE_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0816) as u64;
-pub(crate) const T_RHSTU_CONSULTA_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_CONSULTA_FORMA_PAGTO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.102) as u64;
-pub(crate) const T_RHSTU_FORMA_PAGAMENTO_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0000006) as u64;
-pub(crate) const T_RHSTU_PRESCRICAO_MEDICA_ROWS: u64 = (TOTAL_ROWS as f64 * 0.0918) as u64;
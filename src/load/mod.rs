@@ -0,0 +1,379 @@
+use anyhow::{anyhow, bail};
+use chrono::Local;
+use clap::builder::RangedU64ValueParser;
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use crate::sql_generator::{TableRow, Value};
+use crate::tables::{schema, TableInfo};
+
+mod postgres;
+mod sqlite;
+
+// Table recording, per run, how many rows of each table are already committed
+pub(crate) const CHECKPOINT_TABLE: &str = "T_MOCKDATA_CHECKPOINT";
+
+// Times a run reconnects to resume a table from its checkpoint after a failed batch
+const RECONNECTS: usize = 3;
+
+// Generated tables waiting for the loader thread, beyond which `send` blocks
+const QUEUED_TABLES: usize = 2;
+
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct LoadOptions {
+    /// Database `--format load` streams the rows into, e.g. `sqlite://data/hospital.db`
+    /// or `postgres://user@localhost/hospital`
+    #[arg(long)]
+    pub(crate) database: Option<String>,
+
+    /// Rows committed per transaction by `--format load`
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub(crate) batch_size: usize,
+
+    /// Run `--format load` resumes from its checkpoints, by default the one the database
+    /// already holds; a new run when it holds none
+    #[arg(long)]
+    pub(crate) run: Option<String>,
+}
+
+// A target database able to bulk-load generated tables
+pub(crate) trait Driver: Send {
+    fn create_table(&mut self, table: &TableInfo) -> Result<(), anyhow::Error>;
+
+    // Runs with rows committed to this database
+    fn runs(&mut self) -> Result<Vec<String>, anyhow::Error>;
+
+    // Rows of `table` committed by `run`
+    fn checkpoint(&mut self, run: &str, table: &str) -> Result<u64, anyhow::Error>;
+
+    // Inserts `rows` and moves the checkpoint of `run` to `loaded` in one transaction
+    fn load_batch(
+        &mut self,
+        table: &TableInfo,
+        rows: &[Vec<Value>],
+        run: &str,
+        loaded: u64,
+    ) -> Result<(), anyhow::Error>;
+}
+
+pub(crate) fn connect(database: &str) -> Result<Box<dyn Driver>, anyhow::Error> {
+    if let Some(path) = database.strip_prefix("sqlite://") {
+        Ok(Box::new(sqlite::SqliteDriver::open(path)?))
+    } else if database.starts_with("postgres://") || database.starts_with("postgresql://") {
+        Ok(Box::new(postgres::PostgresDriver::connect(database)?))
+    } else {
+        bail!("unsupported database url {}", database)
+    }
+}
+
+// Rows of one generated table on their way to the database
+struct Table {
+    name: &'static str,
+    rows: Vec<Vec<Value>>,
+}
+
+// Background thread owning the connection, fed the tables as they're generated
+struct Loader {
+    sender: SyncSender<Table>,
+    thread: JoinHandle<Result<(), anyhow::Error>>,
+}
+
+static LOADER: Mutex<Option<Loader>> = Mutex::new(None);
+
+// Connects, creates every table in dependency order and starts the loader thread.
+// The run resumed is the one asked for or the only one the database holds, as the
+// IDs of any other would clash with the ones generated now.
+pub(crate) fn start(options: &LoadOptions) -> Result<(), anyhow::Error> {
+    let database = options
+        .database
+        .clone()
+        .ok_or_else(|| anyhow!("--format load needs --database"))?;
+    let batch_size = options.batch_size;
+
+    let mut driver = connect(&database)?;
+    let run = resolve_run(&database, options.run.as_deref(), &driver.runs()?)?;
+    for table in schema() {
+        driver.create_table(&table)?;
+    }
+
+    let (sender, receiver) = sync_channel::<Table>(QUEUED_TABLES);
+    let thread = std::thread::spawn(move || {
+        load_in_order(receiver, |table, rows| {
+            let mut reconnects = 0;
+            while let Err(error) = load_rows(driver.as_mut(), &run, table, rows, batch_size) {
+                if reconnects == RECONNECTS {
+                    return Err(error.context(format!("loading {} (run {})", table.name, run)));
+                }
+                reconnects += 1;
+                driver = connect(&database)?;
+            }
+            Ok(())
+        })
+    });
+
+    *LOADER.lock().unwrap() = Some(Loader { sender, thread });
+    Ok(())
+}
+
+// Run to load into `database`, given the runs its checkpoints record
+fn resolve_run(
+    database: &str,
+    requested: Option<&str>,
+    runs: &[String],
+) -> Result<String, anyhow::Error> {
+    match (requested, runs) {
+        (Some(run), []) => Ok(run.to_string()),
+        (Some(run), runs) if runs.iter().any(|other| other == run) => Ok(run.to_string()),
+        (Some(run), runs) => bail!(
+            "{} holds the rows of run {}, not {}: resume that run or load into an empty \
+             database",
+            database,
+            runs.join(", "),
+            run
+        ),
+        (None, []) => Ok(Local::now().format("%Y%m%d%H%M%S%3f").to_string()),
+        (None, [run]) => Ok(run.clone()),
+        (None, runs) => bail!(
+            "{} holds the rows of runs {}, pick the one to resume with --run",
+            database,
+            runs.join(", ")
+        ),
+    }
+}
+
+// Loads the tables received in schema order, holding back any whose parents haven't
+// arrived. Tables left waiting on one this run never sends load at the end.
+fn load_in_order(
+    receiver: Receiver<Table>,
+    mut load: impl FnMut(&TableInfo, &[Vec<Value>]) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let tables = schema();
+    let mut pending: HashMap<&'static str, Vec<Vec<Value>>> = HashMap::new();
+    let mut next = 0;
+
+    for table in receiver {
+        if tables[..next]
+            .iter()
+            .any(|loaded| loaded.name == table.name)
+            || pending.contains_key(table.name)
+        {
+            bail!("{} was sent twice", table.name);
+        }
+        pending.insert(table.name, table.rows);
+        while let Some(rows) = tables
+            .get(next)
+            .and_then(|table| pending.remove(table.name))
+        {
+            load(&tables[next], &rows)?;
+            next += 1;
+        }
+    }
+
+    for table in &tables[next..] {
+        if let Some(rows) = pending.remove(table.name) {
+            load(table, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+// Hands the rows of `T` to the loader thread
+pub(crate) fn send<T: TableRow>(rows: &[T]) {
+    // Cloned so a full queue doesn't hold the lock while waiting
+    let sender = LOADER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|loader| loader.sender.clone());
+    if let Some(sender) = sender {
+        // A closed channel means the thread failed, which `finish` reports
+        let _ = sender.send(Table {
+            name: T::TABLE,
+            rows: rows.iter().map(|row| row.values()).collect(),
+        });
+    }
+}
+
+// Waits for every table sent to be committed
+pub(crate) fn finish() -> Result<(), anyhow::Error> {
+    let Some(Loader { sender, thread }) = LOADER.lock().unwrap().take() else {
+        return Ok(());
+    };
+    drop(sender);
+    thread
+        .join()
+        .map_err(|_| anyhow!("the loader thread panicked"))?
+}
+
+// Inserts the rows of `table` past the run's checkpoint in batches of `batch_size`
+fn load_rows(
+    driver: &mut dyn Driver,
+    run: &str,
+    table: &TableInfo,
+    rows: &[Vec<Value>],
+    batch_size: usize,
+) -> Result<(), anyhow::Error> {
+    let done = usize::try_from(driver.checkpoint(run, table.name)?)?;
+    let mut loaded = done;
+    for batch in rows.get(done..).unwrap_or_default().chunks(batch_size) {
+        loaded += batch.len();
+        driver.load_batch(table, batch, run, u64::try_from(loaded)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::consultation::{T_RHSTU_CONSULTA, T_RHSTU_FORMA_PAGAMENTO};
+    use crate::tables::geography::T_RHSTU_ESTADO;
+    use crate::tables::hospital::T_RHSTU_FUNCIONARIO;
+
+    // Fails every batch once `batches` of them are committed, like a dropped connection
+    struct Interrupted {
+        driver: sqlite::SqliteDriver,
+        batches: usize,
+    }
+
+    impl Driver for Interrupted {
+        fn create_table(&mut self, table: &TableInfo) -> Result<(), anyhow::Error> {
+            self.driver.create_table(table)
+        }
+
+        fn runs(&mut self) -> Result<Vec<String>, anyhow::Error> {
+            self.driver.runs()
+        }
+
+        fn checkpoint(&mut self, run: &str, table: &str) -> Result<u64, anyhow::Error> {
+            self.driver.checkpoint(run, table)
+        }
+
+        fn load_batch(
+            &mut self,
+            table: &TableInfo,
+            rows: &[Vec<Value>],
+            run: &str,
+            loaded: u64,
+        ) -> Result<(), anyhow::Error> {
+            if self.batches == 0 {
+                bail!("connection lost");
+            }
+            self.batches -= 1;
+            self.driver.load_batch(table, rows, run, loaded)
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_checkpoint() {
+        let path = std::env::temp_dir().join(format!("load-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let table = schema()
+            .into_iter()
+            .find(|table| table.name == T_RHSTU_FORMA_PAGAMENTO::TABLE)
+            .unwrap();
+        let rows: Vec<Vec<Value>> = (1..=25)
+            .map(|id| {
+                table
+                    .columns
+                    .iter()
+                    .map(|column| match column.name {
+                        "ID_FORMA_PAGTO" => Value::Integer(id),
+                        _ => Value::Text(format!("{}-{}", column.name, id)),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut interrupted = Interrupted {
+            driver: sqlite::SqliteDriver::open(path).unwrap(),
+            batches: 2,
+        };
+        interrupted.create_table(&table).unwrap();
+        assert!(load_rows(&mut interrupted, "run", &table, &rows, 10).is_err());
+        assert_eq!(interrupted.checkpoint("run", table.name).unwrap(), 20);
+        drop(interrupted);
+
+        let mut driver = sqlite::SqliteDriver::open(path).unwrap();
+        assert_eq!(driver.runs().unwrap(), ["run"]);
+        assert_eq!(driver.checkpoint("other", table.name).unwrap(), 0);
+        load_rows(&mut driver, "run", &table, &rows, 10).unwrap();
+        assert_eq!(driver.checkpoint("run", table.name).unwrap(), 25);
+
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let (count, distinct): (i64, i64) = connection
+            .query_row(
+                "SELECT COUNT(*), COUNT(DISTINCT ID_FORMA_PAGTO) FROM T_RHSTU_FORMA_PAGAMENTO",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((count, distinct), (25, 25));
+
+        drop(connection);
+        drop(driver);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[test]
+    fn loads_parents_first() {
+        let (sender, receiver) = sync_channel::<Table>(QUEUED_TABLES);
+        let sent = std::thread::spawn(move || {
+            for name in [
+                T_RHSTU_CONSULTA::TABLE,
+                T_RHSTU_FUNCIONARIO::TABLE,
+                T_RHSTU_ESTADO::TABLE,
+                T_RHSTU_FORMA_PAGAMENTO::TABLE,
+            ] {
+                sender.send(Table { name, rows: vec![] }).unwrap();
+            }
+        });
+
+        let mut loaded = Vec::new();
+        load_in_order(receiver, |table, _| {
+            loaded.push(table.name);
+            Ok(())
+        })
+        .unwrap();
+        sent.join().unwrap();
+
+        assert_eq!(
+            loaded,
+            [
+                T_RHSTU_ESTADO::TABLE,
+                T_RHSTU_FUNCIONARIO::TABLE,
+                T_RHSTU_FORMA_PAGAMENTO::TABLE,
+                T_RHSTU_CONSULTA::TABLE,
+            ]
+        );
+    }
+
+    #[test]
+    fn resumes_the_run_the_database_holds() {
+        let database = "sqlite://hospital.db";
+        let runs = ["20240101".to_string()];
+        assert_eq!(resolve_run(database, None, &runs).unwrap(), "20240101");
+        assert_eq!(
+            resolve_run(database, Some("20240101"), &runs).unwrap(),
+            "20240101"
+        );
+        assert!(resolve_run(database, Some("20240102"), &runs).is_err());
+        assert_eq!(resolve_run(database, Some("first"), &[]).unwrap(), "first");
+        assert!(!resolve_run(database, None, &[]).unwrap().is_empty());
+
+        let runs = ["20240101".to_string(), "20240102".to_string()];
+        assert!(resolve_run(database, None, &runs).is_err());
+        assert_eq!(
+            resolve_run(database, Some("20240102"), &runs).unwrap(),
+            "20240102"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use postgres::{Client, NoTls};
+use std::io::Write;
+
+use super::{Driver, CHECKPOINT_TABLE};
+use crate::output::copy::create_table_sql;
+use crate::sql_generator::Value;
+use crate::tables::TableInfo;
+
+pub(crate) struct PostgresDriver {
+    client: Client,
+}
+
+impl PostgresDriver {
+    pub(crate) fn connect(url: &str) -> Result<Self, anyhow::Error> {
+        let mut client = Client::connect(url, NoTls)?;
+        client.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (ID_EXECUCAO TEXT NOT NULL, NM_TABELA TEXT NOT NULL,
+                 NR_LINHAS BIGINT NOT NULL, PRIMARY KEY (ID_EXECUCAO, NM_TABELA));",
+            CHECKPOINT_TABLE
+        ))?;
+        Ok(PostgresDriver { client })
+    }
+}
+
+impl Driver for PostgresDriver {
+    fn create_table(&mut self, table: &TableInfo) -> Result<(), anyhow::Error> {
+        self.client.batch_execute(&create_table_sql(table))?;
+        Ok(())
+    }
+
+    fn runs(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        let rows = self.client.query(
+            &format!(
+                "SELECT DISTINCT ID_EXECUCAO FROM {} ORDER BY ID_EXECUCAO",
+                CHECKPOINT_TABLE
+            ),
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn checkpoint(&mut self, run: &str, table: &str) -> Result<u64, anyhow::Error> {
+        let row = self.client.query_opt(
+            &format!(
+                "SELECT NR_LINHAS FROM {} WHERE ID_EXECUCAO = $1 AND NM_TABELA = $2",
+                CHECKPOINT_TABLE
+            ),
+            &[&run, &table],
+        )?;
+        Ok(u64::try_from(
+            row.map(|row| row.get::<_, i64>(0)).unwrap_or(0),
+        )?)
+    }
+
+    // Streams the batch through `COPY ... FROM STDIN` in CSV format
+    fn load_batch(
+        &mut self,
+        table: &TableInfo,
+        rows: &[Vec<Value>],
+        run: &str,
+        loaded: u64,
+    ) -> Result<(), anyhow::Error> {
+        let names: Vec<&str> = table.columns.iter().map(|column| column.name).collect();
+
+        let mut transaction = self.client.transaction()?;
+        let mut writer = transaction.copy_in(&format!(
+            "COPY {} ({}) FROM STDIN (FORMAT csv, NULL '\\N')",
+            table.name,
+            names.join(", ")
+        ))?;
+        {
+            let mut csv = csv::WriterBuilder::new().from_writer(&mut writer);
+            for row in rows {
+                csv.write_record(row.iter().map(|value| match value {
+                    Value::Null => "\\N".to_string(),
+                    value => value.to_text(),
+                }))?;
+            }
+            csv.flush()?;
+        }
+        writer.flush()?;
+        writer.finish()?;
+
+        transaction.execute(
+            &format!(
+                "INSERT INTO {} (ID_EXECUCAO, NM_TABELA, NR_LINHAS) VALUES ($1, $2, $3)
+                 ON CONFLICT (ID_EXECUCAO, NM_TABELA) DO UPDATE SET NR_LINHAS = excluded.NR_LINHAS",
+                CHECKPOINT_TABLE
+            ),
+            &[&run, &table.name, &i64::try_from(loaded)?],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use rusqlite::{params, types::ToSqlOutput, Connection, ToSql};
+
+use super::{Driver, CHECKPOINT_TABLE};
+use crate::sql_generator::{ColumnKind, Value, DATE_FORMAT};
+use crate::tables::TableInfo;
+
+pub(crate) struct SqliteDriver {
+    connection: Connection,
+}
+
+impl SqliteDriver {
+    pub(crate) fn open(path: &str) -> Result<Self, anyhow::Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(&format!(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS {} (ID_EXECUCAO TEXT NOT NULL, NM_TABELA TEXT NOT NULL,
+                 NR_LINHAS INTEGER NOT NULL, PRIMARY KEY (ID_EXECUCAO, NM_TABELA));",
+            CHECKPOINT_TABLE
+        ))?;
+        Ok(SqliteDriver { connection })
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Null => ToSqlOutput::from(rusqlite::types::Null),
+            Value::Integer(number) => ToSqlOutput::from(
+                i64::try_from(*number)
+                    .map_err(|error| rusqlite::Error::ToSqlConversionFailure(Box::new(error)))?,
+            ),
+            Value::Text(text) => ToSqlOutput::from(text.as_str()),
+            Value::Date(date) => ToSqlOutput::from(date.format(DATE_FORMAT).to_string()),
+        })
+    }
+}
+
+impl Driver for SqliteDriver {
+    fn create_table(&mut self, table: &TableInfo) -> Result<(), anyhow::Error> {
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| {
+                let kind = match column.kind {
+                    ColumnKind::Integer => "INTEGER",
+                    ColumnKind::Text | ColumnKind::Date => "TEXT",
+                };
+                format!("{} {}", column.name, kind)
+            })
+            .collect();
+
+        self.connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({});",
+            table.name,
+            columns.join(", ")
+        ))?;
+        Ok(())
+    }
+
+    fn runs(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT DISTINCT ID_EXECUCAO FROM {} ORDER BY ID_EXECUCAO",
+            CHECKPOINT_TABLE
+        ))?;
+        let runs = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(runs)
+    }
+
+    fn checkpoint(&mut self, run: &str, table: &str) -> Result<u64, anyhow::Error> {
+        let loaded = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT NR_LINHAS FROM {} WHERE ID_EXECUCAO = ?1 AND NM_TABELA = ?2",
+                    CHECKPOINT_TABLE
+                ),
+                params![run, table],
+                |row| row.get::<_, i64>(0),
+            )
+            .or_else(|error| match error {
+                rusqlite::Error::QueryReturnedNoRows => Ok(0),
+                error => Err(error),
+            })?;
+        Ok(u64::try_from(loaded)?)
+    }
+
+    fn load_batch(
+        &mut self,
+        table: &TableInfo,
+        rows: &[Vec<Value>],
+        run: &str,
+        loaded: u64,
+    ) -> Result<(), anyhow::Error> {
+        let names: Vec<&str> = table.columns.iter().map(|column| column.name).collect();
+        let placeholders = vec!["?"; names.len()].join(", ");
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(&format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                table.name,
+                names.join(", "),
+                placeholders
+            ))?;
+            for row in rows {
+                insert.execute(rusqlite::params_from_iter(row))?;
+            }
+        }
+        transaction.execute(
+            &format!(
+                "INSERT INTO {} (ID_EXECUCAO, NM_TABELA, NR_LINHAS) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (ID_EXECUCAO, NM_TABELA) DO UPDATE SET NR_LINHAS = excluded.NR_LINHAS",
+                CHECKPOINT_TABLE
+            ),
+            params![run, table.name, i64::try_from(loaded)?],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
@@ -10,7 +10,7 @@
 // - T_RHSTU_BAIRRO - "ID_BAIRRO","ID_CIDADE","NM_BAIRRO","NM_ZONA_BAIRRO","DT_CADASTRO","NM_USUARIO"
 // - T_RHSTU_LOGRADOURO - "ID_LOGRADOURO","ID_BAIRRO","NM_LOGRADOURO","NR_CEP","DT_CADASTRO","NM_USUARIO"
 
-// - T_RHSTU_UNID_HOSPITALAR - "ID_UNID_HOSPITAL","NM_UNID_HOSPITALAR","NM_RAZAO_SOCIAL_UNID_HOSP","DT_FUNDACAO","NR_LOGRADOURO","DS_COMPLEMENTO_NUMERO","DS_PONTO_REFERENCIA","DT_INICIO","DT_TERMINO","DT_CADASTRO","NM_USUARIO"
+// - T_RHSTU_UNID_HOSPITALAR - "ID_UNID_HOSPITAL","NM_UNID_HOSPITALAR","NM_RAZAO_SOCIAL_UNID_HOSP","NR_CNES","DT_FUNDACAO","NR_LOGRADOURO","DS_COMPLEMENTO_NUMERO","DS_PONTO_REFERENCIA","DT_INICIO","DT_TERMINO","DT_CADASTRO","NM_USUARIO"
 
 // - T_RHSTU_FUNCIONARIO - "ID_FUNC","ID_SUPERIOR","NM_FUNC","DS_CARGO","DT_NASCIMENTO","VL_SALARIO","NR_RG","NR_CPF","ST_FUNC","DT_CADASTRO","NM_USUARIO"
 // - T_RHSTU_MEDICO - "ID_FUNC","NR_CRM","DS_ESPECIALIDADE","DT_CADASTRO","NM_USUARIO"
@@ -23,7 +23,7 @@
 // - T_RHSTU_TELEFONE_PACIENTE - "ID_PACIENTE","ID_TELEFONE","NR_DDI","NR_DDD","NR_TELEFONE","TP_TELEFONE","ST_TELEFONE","DT_CADASTRO","NM_USUARIO"
 // - T_RHSTU_ENDERECO_PACIENTE - "ID_ENDERECO","ID_PACIENTE","ID_LOGRADOURO","NR_LOGRADOURO","DS_COMPLEMENTO_NUMERO","DS_PONTO_REFERENCIA","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
 
-// - T_RHSTU_PLANO_SAUDE - "ID_PLANO_SAUDE","DS_RAZAO_SOCIAL","NM_FANTASIA_PLANO_SAUDE","DS_PLANO_SAUDE","NR_CNPJ","NM_CONTATO","DS_TELEFONE","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
+// - T_RHSTU_PLANO_SAUDE - "ID_PLANO_SAUDE","DS_RAZAO_SOCIAL","NM_FANTASIA_PLANO_SAUDE","DS_PLANO_SAUDE","NR_REGISTRO_ANS","DS_SEGMENTACAO","DS_ACOMODACAO","NR_CNPJ","NM_CONTATO","DS_TELEFONE","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
 // - T_RHSTU_PACIENTE_PLANO_SAUDE - "ID_PACIENTE_PS","ID_PACIENTE","ID_PLANO_SAUDE","NR_CARTEIRA_PS","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
 
 // - T_RHSTU_CONSULTA - "ID_UNID_HOSPITAL","ID_CONSULTA","ID_PACIENTE","ID_FUNC","DT_HR_CONSULTA","NR_CONSULTORIO","DT_CADASTRO","NM_USUARIO"
@@ -46,36 +46,84 @@
 
 mod bulario;
 mod common;
-mod constants;
+mod config;
+mod load;
+mod output;
 mod sql_generator;
 mod tables;
 
 use crate::tables::geography::{
-    generate_address, generate_cities, generate_neighborhoods, generate_states,
+    generate_address, generate_cities, generate_neighborhoods, generate_states, street_ddds,
+    street_states,
 };
 use crate::tables::medicine::{generate_medical_prescription, get_medicines};
 use crate::tables::patient::generate_patients;
 use anyhow::Result;
+use clap::{Parser, Subcommand};
 use common::{create_data_dir, format_number, format_time};
-use constants::{
-    T_RHSTU_CONSULTA_FORMA_PAGTO_ROWS, T_RHSTU_CONSULTA_ROWS, T_RHSTU_CONTATO_PACIENTE_ROWS,
-    T_RHSTU_EMAIL_PACIENTE_ROWS, T_RHSTU_ENDERECO_PACIENTE_ROWS, T_RHSTU_ENDERECO_UNIDHOSP_ROWS,
-    T_RHSTU_FUNCIONARIO_ROWS, T_RHSTU_LOGRADOURO_ROWS, T_RHSTU_MEDICO_ROWS, T_RHSTU_MOTORISTA_ROWS,
-    T_RHSTU_PACIENTE_PLANO_SAUDE_ROWS, T_RHSTU_PACIENTE_ROWS, T_RHSTU_PLANO_SAUDE_ROWS,
-    T_RHSTU_PRESCRICAO_MEDICA_ROWS, T_RHSTU_TELEFONE_PACIENTE_ROWS, T_RHSTU_UNID_HOSPITALAR_ROWS,
-};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use load::LoadOptions;
+use output::{parquet::ParquetOptions, Format};
 use reqwest::Client;
-use std::sync::{Arc, Mutex};
-use tables::hospital::generate_employee; // Use anyhow for better error handling
+use std::path::PathBuf;
+use std::sync::Arc;
+use tables::hospital::Staff;
+
+#[derive(Parser, Debug)]
+#[command(about = "Generates a fictional hospital dataset")]
+struct Args {
+    /// TOML profile with table sizes, value distributions and date windows
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Output formats written for every table
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sql")]
+    format: Vec<Format>,
 
-const TOTAL_ENTRIES: usize = 10_000_000;
+    #[command(flatten)]
+    parquet: ParquetOptions,
+
+    #[command(flatten)]
+    load: LoadOptions,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replays the HL7 messages of a previous `--format hl7` run over an MLLP listener
+    ServeHl7 {
+        /// Address the MLLP listener binds to
+        #[arg(long, default_value = "127.0.0.1:2575")]
+        listen: String,
+
+        /// Sends the next message without waiting for an ACK
+        #[arg(long)]
+        no_ack: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+
+    match args.command {
+        Some(Command::ServeHl7 { listen, no_ack }) => {
+            let m = Arc::new(MultiProgress::new());
+            return tokio::task::spawn_blocking(move || output::hl7::serve(&listen, !no_ack, m))
+                .await?;
+        }
+        None => {}
+    }
+
+    let profile = config::init(args.profile.as_deref())?;
+    output::init(args.format, args.parquet, &args.load)?;
+    let total_entries = profile.total_rows as usize;
+
     create_data_dir();
     let m = Arc::new(MultiProgress::new());
-    let pb = Arc::new(m.add(ProgressBar::new(TOTAL_ENTRIES as u64)));
+    let pb = Arc::new(m.add(ProgressBar::new(total_entries as u64)));
     pb.set_prefix("Total:");
     pb.set_style(
         ProgressStyle::default_bar()
@@ -98,180 +146,294 @@ async fn main() -> Result<(), anyhow::Error> {
     ));
     let medicines_task = tokio::spawn(get_medicines(m.clone(), pb.clone()));
 
-    let neighborhoods = tokio::try_join!(neighborhoods_task)?;
+    let (neighborhoods, states, cities) =
+        tokio::try_join!(neighborhoods_task, states_task, cities_task)?;
 
-    let neighborhoods = neighborhoods.0?;
+    let (neighborhoods, states, cities) = (neighborhoods?, states?, cities?);
 
     let address: Vec<tables::geography::T_RHSTU_LOGRADOURO> = generate_address(
         &neighborhoods,
-        T_RHSTU_LOGRADOURO_ROWS as usize,
+        &cities,
+        &states,
+        profile.rows("T_RHSTU_LOGRADOURO"),
         m.clone(),
         pb.clone(),
     )?;
 
-    // Patient-related tasks
+    let street_states = street_states(&address, &neighborhoods, &cities, &states);
 
-    let patients_task = tokio::spawn(generate_patients(
-        T_RHSTU_PACIENTE_ROWS as usize,
-        m.clone(),
-        pb.clone(),
-    ));
-    let patient_address_task = tokio::spawn(tables::patient::generate_patients_addresses(
-        T_RHSTU_ENDERECO_PACIENTE_ROWS as usize,
+    // Patient-related tasks
+    let patient_addresses = tables::patient::generate_patients_addresses(
+        profile.rows("T_RHSTU_PACIENTE"),
         address.clone(),
         m.clone(),
         pb.clone(),
-    ));
-    let contact_types_task = tokio::spawn(tables::patient::generate_contact_types(
-        6,
+    )
+    .await;
+    let patients_task = tokio::spawn(generate_patients(
+        profile.rows("T_RHSTU_PACIENTE"),
+        tables::patient::by_patient(
+            profile.rows("T_RHSTU_PACIENTE"),
+            &patient_addresses,
+            &street_states,
+        ),
+        tables::patient::moved_in(profile.rows("T_RHSTU_PACIENTE"), &patient_addresses),
         m.clone(),
         pb.clone(),
     ));
-    let contact_types = tokio::try_join!(contact_types_task)?;
+    let contact_types = tables::patient::generate_contact_types(m.clone(), pb.clone())?;
 
+    let patient_ddds = tables::patient::by_patient(
+        profile.rows("T_RHSTU_PACIENTE"),
+        &patient_addresses,
+        &street_ddds(&address, &neighborhoods, &cities),
+    );
+    let patients = Arc::new(patients_task.await?);
     let patient_contact = tokio::spawn(tables::patient::generate_patient_contacts(
-        T_RHSTU_CONTATO_PACIENTE_ROWS as usize,
-        contact_types.0,
+        patients.clone(),
+        contact_types,
+        patient_ddds.clone(),
         m.clone(),
         pb.clone(),
     ));
     let patient_email_task = tokio::spawn(tables::patient::generate_emails(
-        T_RHSTU_EMAIL_PACIENTE_ROWS as usize,
+        patients.clone(),
         m.clone(),
         pb.clone(),
     ));
 
     let patient_telefone_task = tokio::spawn(tables::patient::generate_telephones(
-        T_RHSTU_TELEFONE_PACIENTE_ROWS as usize,
+        profile.rows("T_RHSTU_PACIENTE"),
+        patient_ddds,
         m.clone(),
         pb.clone(),
     ));
 
     // Hospital-related tasks
+    let hospital_addresses = tables::hospital::generate_hospital_address(
+        profile.rows("T_RHSTU_ENDERECO_UNIDHOSP"),
+        address.clone(),
+        m.clone(),
+        pb.clone(),
+    )
+    .await;
     let hospitals_task = tokio::spawn(tables::hospital::generate_hospital(
-        T_RHSTU_UNID_HOSPITALAR_ROWS as usize,
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        hospital_addresses,
         m.clone(),
         pb.clone(),
     ));
 
-    let hospital_address_taks = tokio::spawn(tables::hospital::generate_hospital_address(
-        T_RHSTU_ENDERECO_UNIDHOSP_ROWS as usize,
-        address,
+    // Employees, each allocated to a hospital, also make up whatever the other tables
+    // leave out of the total: the IBGE geography fetched above and the rows the profile
+    // plans. Reference catalogs aren't known yet, so the final count may still differ.
+    let planned = states.len()
+        + cities.len()
+        + neighborhoods.len()
+        + profile.planned_rows(&["T_RHSTU_FUNCIONARIO", "T_RHSTU_FUNCIONARIO_UNIDHOSP"]);
+    let total_employees = profile
+        .rows("T_RHSTU_FUNCIONARIO")
+        .max(total_entries.saturating_sub(planned) / 2);
+    let employees = tables::hospital::generate_employee(
+        total_employees,
+        profile.rows("T_RHSTU_MEDICO"),
+        profile.rows("T_RHSTU_MOTORISTA"),
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        &street_states,
+        m.clone(),
+        pb.clone(),
+    )
+    .await;
+    let allocations = tables::hospital::generate_employee_allocations(
+        &employees,
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        m.clone(),
+        pb.clone(),
+    )
+    .await;
+    let doctors = Staff::new(&employees, &allocations, tables::hospital::DOCTOR);
+    let with_title = |title: &str| -> Vec<u64> {
+        employees
+            .iter()
+            .filter(|employee| employee.DS_CARGO == title)
+            .map(|employee| employee.ID_FUNC)
+            .collect()
+    };
+
+    let specialties = tables::specialty::generate_specialties(m.clone(), pb.clone())?;
+    let doctors_task = tokio::spawn(tables::hospital::generate_doctor(
+        with_title(tables::hospital::DOCTOR),
+        street_states.clone(),
+        specialties.clone(),
         m.clone(),
         pb.clone(),
     ));
 
-    let employees_task = tokio::spawn(tables::hospital::generate_employee(
-        T_RHSTU_FUNCIONARIO_ROWS as usize,
+    let drivers_task = tokio::spawn(tables::hospital::generate_driver(
+        employees
+            .iter()
+            .filter(|employee| employee.DS_CARGO == tables::hospital::DRIVER)
+            .cloned()
+            .collect(),
         m.clone(),
         pb.clone(),
     ));
 
-    let employee_ids: Arc<Mutex<Vec<u64>>> = Arc::new(Mutex::new(employees_task.await?));
+    // Inpatient-related tasks
+    let wards = tables::inpatient::generate_wards(
+        profile.rows("T_RHSTU_ALA"),
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        m.clone(),
+        pb.clone(),
+    );
+    let beds = tables::inpatient::generate_beds(
+        profile.rows("T_RHSTU_LEITO"),
+        &wards,
+        m.clone(),
+        pb.clone(),
+    );
+    // Ambulance-related tasks
+    let ambulances = tables::ambulance::generate_ambulances(
+        profile.rows("T_RHSTU_AMBULANCIA"),
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        m.clone(),
+        pb.clone(),
+    );
+    let drivers = drivers_task.await?;
 
-    let doctors_task = tokio::spawn(tables::hospital::generate_doctor(
-        employee_ids.clone(),
-        T_RHSTU_MEDICO_ROWS as usize,
+    let doctor_specialties_task = tokio::spawn(tables::specialty::generate_doctor_specialties(
+        doctors_task.await?,
+        specialties,
         m.clone(),
         pb.clone(),
     ));
 
-    let drivers_task = tokio::spawn(tables::hospital::generate_driver(
-        employee_ids,
-        T_RHSTU_MOTORISTA_ROWS as usize,
+    let ambulance_trips_task = tokio::spawn(tables::ambulance::generate_ambulance_trips(
+        profile.rows("T_RHSTU_VIAGEM_AMBULANCIA"),
+        ambulances,
+        drivers,
+        address,
+        neighborhoods,
         m.clone(),
         pb.clone(),
     ));
 
-    // Consultation-related tasks
-    let consultations_task = tokio::spawn(tables::consultation::generate_consultations(
-        T_RHSTU_CONSULTA_ROWS as usize,
-        T_RHSTU_UNID_HOSPITALAR_ROWS as usize,
-        T_RHSTU_PACIENTE_ROWS as usize,
+    let admissions_task = tokio::spawn(tables::inpatient::generate_admissions(
+        beds,
+        wards,
+        doctors.clone(),
+        patients.clone(),
         m.clone(),
         pb.clone(),
     ));
-    let payment_methods_task = tokio::spawn(tables::consultation::generate_payment_methods(
-        6,
+
+    // Health plan-related tasks
+    let health_plans = tables::health_plan::generate_health_plans(m.clone(), pb.clone())?;
+
+    let health_plan_patient_task =
+        tokio::spawn(tables::health_plan::generate_patient_health_plans(
+            patients.clone(),
+            health_plans,
+            m.clone(),
+            pb.clone(),
+        ));
+
+    // Consultation-related tasks, none after a patient's death in hospital
+    let deaths = Arc::new(admissions_task.await?);
+    let consultations_task = tokio::spawn(tables::consultation::generate_consultations(
+        profile.rows("T_RHSTU_CONSULTA"),
+        profile.rows("T_RHSTU_UNID_HOSPITALAR"),
+        profile.rows("T_RHSTU_PACIENTE"),
+        doctors,
+        deaths.clone(),
         m.clone(),
         pb.clone(),
     ));
-    let (consultations, payment_methods) =
-        tokio::try_join!(consultations_task, payment_methods_task)?;
+    let payment_methods = tables::consultation::generate_payment_methods(m.clone(), pb.clone())?;
+    let consultations = consultations_task.await?;
+    let coverage = tables::health_plan::Coverage::new(&health_plan_patient_task.await??);
 
     let consultation_payment_methods_task =
         tokio::spawn(tables::consultation::generate_consultation_payment_methods(
-            T_RHSTU_CONSULTA_FORMA_PAGTO_ROWS as usize,
+            profile.rows("T_RHSTU_CONSULTA_FORMA_PAGTO"),
             payment_methods,
-            consultations,
+            consultations.clone(),
+            coverage,
             m.clone(),
             pb.clone(),
         ));
 
-    // Health plan-related tasks
-    let health_plans = tables::health_plan::generate_health_plans(
-        T_RHSTU_PLANO_SAUDE_ROWS as usize,
+    // Diagnosis-related tasks
+    let cid10 = tables::diagnosis::generate_cid10(m.clone(), pb.clone())?;
+    let diagnoses = tables::diagnosis::generate_diagnoses(
+        profile.rows("T_RHSTU_DIAGNOSTICO"),
+        cid10.clone(),
+        consultations.clone(),
+        patients.clone(),
+        m.clone(),
+        pb.clone(),
+    )
+    .await;
+
+    // Exam-related tasks
+    let exams = tables::exam::generate_exams(m.clone(), pb.clone())?;
+    let exam_orders = tables::exam::generate_exam_orders(
+        profile.rows("T_RHSTU_PEDIDO_EXAME"),
+        &exams,
+        &consultations,
+        &patients,
         m.clone(),
         pb.clone(),
     );
-
-    let health_plan_patient_task =
-        tokio::spawn(tables::health_plan::generate_patient_health_plans(
-            T_RHSTU_PACIENTE_PLANO_SAUDE_ROWS as usize,
-            health_plans,
-            T_RHSTU_PACIENTE_ROWS as usize,
-            m.clone(),
-            pb.clone(),
-        ));
+    let exam_results_task = tokio::spawn(tables::exam::generate_exam_results(
+        exam_orders,
+        exams,
+        deaths,
+        m.clone(),
+        pb.clone(),
+    ));
 
     // Medicine-related tasks
     let medicines = medicines_task.await?;
 
     let generate_medical_prescription_task = tokio::spawn(generate_medical_prescription(
-        T_RHSTU_PRESCRICAO_MEDICA_ROWS as usize,
+        profile.rows("T_RHSTU_PRESCRICAO_MEDICA"),
         medicines,
+        consultations,
+        cid10.primary_chapters(&diagnoses),
+        tables::medicine::Posology::load()?,
         m.clone(),
         pb.clone(),
     ));
 
     // await all tasks
     let _ = tokio::try_join!(
-        cities_task,
-        states_task,
-        patients_task,
-        patient_address_task,
         patient_contact,
         patient_email_task,
         patient_telefone_task,
         hospitals_task,
-        hospital_address_taks,
-        doctors_task,
-        drivers_task,
+        doctor_specialties_task,
+        ambulance_trips_task,
+        exam_results_task,
         consultation_payment_methods_task,
-        health_plan_patient_task,
         generate_medical_prescription_task
     )?;
 
-    let mut generated_entries = pb.position() as i32;
-    let mut discrepancy = TOTAL_ENTRIES as i32 - generated_entries;
-
-    // Here, you can adjust one of the categories to make up for the discrepancy. For simplicity, let's adjust the number of patients:
-    if discrepancy > 0 {
-        generate_employee(discrepancy as usize, m.clone(), pb.clone()).await;
-    }
+    output::finish()?;
 
-    generated_entries = pb.position() as i32;
-    discrepancy = TOTAL_ENTRIES as i32 - generated_entries;
+    let generated_entries = pb.position() as i32;
+    let discrepancy = total_entries as i32 - generated_entries;
     let pb_final_time = pb.elapsed();
 
     // Final progress bar
+    #[allow(clippy::format_in_format_args)]
     let r#final = format!(
         "entries left: {} | entries generated: {} | percentage: {}% | time elapsed: {}",
         format!("{}", format_number(discrepancy.into())),
         format_number(generated_entries.into()),
         format_number(
             generated_entries
-                .checked_div(TOTAL_ENTRIES.try_into().unwrap())
+                .checked_div(total_entries.try_into().unwrap())
                 .unwrap_or(0)
                 .checked_mul(100)
                 .unwrap_or(0)
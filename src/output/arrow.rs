@@ -0,0 +1,97 @@
+use arrow::array::{ArrayRef, StringBuilder, TimestampSecondBuilder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use indicatif::ProgressBar;
+use std::fs::{self, File};
+use std::sync::Arc;
+
+use super::DATA_DIR;
+use crate::sql_generator::{Column, ColumnKind, TableRow, Value};
+
+// Rows per record batch
+const BATCH_SIZE: usize = 65_536;
+
+pub(crate) fn schema(columns: &[Column]) -> SchemaRef {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.kind {
+                ColumnKind::Integer => DataType::UInt64,
+                ColumnKind::Text => DataType::Utf8,
+                ColumnKind::Date => DataType::Timestamp(TimeUnit::Second, None),
+            };
+            Field::new(column.name, data_type, true)
+        })
+        .collect();
+
+    Arc::new(Schema::new(fields))
+}
+
+// Builds one record batch from `rows`, column by column
+pub(crate) fn record_batch<T: TableRow>(
+    schema: &SchemaRef,
+    rows: &[&T],
+) -> Result<RecordBatch, ArrowError> {
+    let values: Vec<Vec<Value>> = rows.iter().map(|row| row.values()).collect();
+
+    let arrays: Vec<ArrayRef> = T::columns()
+        .iter()
+        .enumerate()
+        .map(|(i, column)| -> ArrayRef {
+            match column.kind {
+                ColumnKind::Integer => {
+                    let mut builder = UInt64Builder::with_capacity(rows.len());
+                    for row in &values {
+                        match &row[i] {
+                            Value::Integer(number) => builder.append_value(*number),
+                            _ => builder.append_null(),
+                        }
+                    }
+                    Arc::new(builder.finish())
+                }
+                ColumnKind::Text => {
+                    let mut builder = StringBuilder::new();
+                    for row in &values {
+                        match &row[i] {
+                            Value::Null => builder.append_null(),
+                            value => builder.append_value(value.to_text()),
+                        }
+                    }
+                    Arc::new(builder.finish())
+                }
+                ColumnKind::Date => {
+                    let mut builder = TimestampSecondBuilder::with_capacity(rows.len());
+                    for row in &values {
+                        match &row[i] {
+                            Value::Date(date) => builder.append_value(date.and_utc().timestamp()),
+                            _ => builder.append_null(),
+                        }
+                    }
+                    Arc::new(builder.finish())
+                }
+            }
+        })
+        .collect();
+
+    RecordBatch::try_new(schema.clone(), arrays)
+}
+
+// Writes `data/arrow/<TABLE>.arrow` in the Arrow IPC file format
+pub(crate) fn write<T: TableRow>(rows: &[T], pb: &ProgressBar) -> Result<(), ArrowError> {
+    let dir = format!("{}/arrow", DATA_DIR);
+    fs::create_dir_all(&dir)?;
+    let file = File::create(format!("{}/{}.arrow", dir, T::TABLE))?;
+
+    let schema = schema(&T::columns());
+    let mut writer = FileWriter::try_new(file, &schema)?;
+    for chunk in rows.chunks(BATCH_SIZE) {
+        let chunk: Vec<&T> = chunk.iter().collect();
+        writer.write(&record_batch(&schema, &chunk)?)?;
+        pb.inc(chunk.len() as u64);
+    }
+    writer.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use super::{output, Format};
+use crate::sql_generator::{TableRow, Value};
+
+// Rows of one table kept in memory for the formats that join several tables
+pub(crate) struct Rows {
+    pub(crate) columns: Vec<&'static str>,
+    pub(crate) rows: Vec<Vec<Value>>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Row<'a> {
+    columns: &'a [&'static str],
+    values: &'a [Value],
+}
+
+pub(crate) type Tables = BTreeMap<&'static str, Rows>;
+
+static COLLECTED: Mutex<Tables> = Mutex::new(BTreeMap::new());
+
+impl Rows {
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Row<'_>> {
+        self.rows.iter().map(|values| Row {
+            columns: &self.columns,
+            values,
+        })
+    }
+}
+
+impl<'a> Row<'a> {
+    pub(crate) fn columns(&self) -> &'a [&'static str] {
+        self.columns
+    }
+
+    pub(crate) fn values(&self) -> &'a [Value] {
+        self.values
+    }
+
+    pub(crate) fn get(&self, column: &str) -> &'a Value {
+        let index = self
+            .columns
+            .iter()
+            .position(|name| *name == column)
+            .unwrap_or_else(|| panic!("no column {}", column));
+        &self.values[index]
+    }
+
+    pub(crate) fn u64(&self, column: &str) -> Option<u64> {
+        match self.get(column) {
+            Value::Integer(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn text(&self, column: &str) -> String {
+        self.get(column).to_text()
+    }
+
+    pub(crate) fn date(&self, column: &str) -> Option<NaiveDateTime> {
+        match self.get(column) {
+            Value::Date(date) => Some(*date),
+            _ => None,
+        }
+    }
+}
+
+// Tables each joining format needs collected
+fn tables(format: Format) -> &'static [&'static str] {
+    match format {
+        Format::PatientDocuments => &super::documents::TABLES,
+        Format::Fhir => &super::fhir::TABLES,
+        Format::Hl7 => &super::hl7::TABLES,
+        Format::Tiss => &super::tiss::TABLES,
+        _ => &[],
+    }
+}
+
+// Keeps the rows of `T` if any selected format needs them once generation ends
+pub(crate) fn collect<T: TableRow>(rows: &[T]) {
+    let wanted = output()
+        .formats
+        .iter()
+        .any(|format| tables(*format).contains(&T::TABLE));
+    if !wanted {
+        return;
+    }
+
+    let mut collected = COLLECTED.lock().unwrap();
+    let entry = collected.entry(T::TABLE).or_insert_with(|| Rows {
+        columns: T::columns().iter().map(|column| column.name).collect(),
+        rows: Vec::new(),
+    });
+    entry.rows.extend(rows.iter().map(|row| row.values()));
+}
+
+pub(crate) fn take() -> Tables {
+    std::mem::take(&mut *COLLECTED.lock().unwrap())
+}
+
+pub(crate) fn rows<'a>(tables: &'a Tables, table: &str) -> impl Iterator<Item = Row<'a>> {
+    tables.get(table).into_iter().flat_map(|rows| rows.iter())
+}
+
+// Rows of `table` by their unique `key`
+pub(crate) fn index<'a>(tables: &'a Tables, table: &str, key: &str) -> HashMap<u64, Row<'a>> {
+    rows(tables, table)
+        .filter_map(|row| row.u64(key).map(|id| (id, row)))
+        .collect()
+}
+
+// Rows of `table` grouped by the foreign `key`
+pub(crate) fn group<'a>(tables: &'a Tables, table: &str, key: &str) -> HashMap<u64, Vec<Row<'a>>> {
+    let mut groups: HashMap<u64, Vec<Row<'a>>> = HashMap::new();
+    for row in rows(tables, table) {
+        if let Some(id) = row.u64(key) {
+            groups.entry(id).or_default().push(row);
+        }
+    }
+    groups
+}
+
+// Address of a patient or hospital with its street, neighborhood, city and state names
+pub(crate) struct Address {
+    pub(crate) street: Option<String>,
+    pub(crate) number: String,
+    pub(crate) complement: String,
+    pub(crate) district: Option<String>,
+    pub(crate) city: Option<String>,
+    pub(crate) state: Option<String>,
+    // IBGE code of the state
+    pub(crate) state_code: Option<u64>,
+    pub(crate) postal_code: Option<String>,
+}
+
+// Street -> neighborhood -> city -> state lookups
+pub(crate) struct Geography<'a> {
+    streets: HashMap<u64, Row<'a>>,
+    neighborhoods: HashMap<u64, Row<'a>>,
+    cities: HashMap<u64, Row<'a>>,
+    states: HashMap<u64, Row<'a>>,
+}
+
+impl<'a> Geography<'a> {
+    pub(crate) fn new(tables: &'a Tables) -> Self {
+        Geography {
+            streets: index(tables, "T_RHSTU_LOGRADOURO", "ID_LOGRADOURO"),
+            neighborhoods: index(tables, "T_RHSTU_BAIRRO", "ID_BAIRRO"),
+            cities: index(tables, "T_RHSTU_CIDADE", "ID_CIDADE"),
+            states: index(tables, "T_RHSTU_ESTADO", "ID_ESTADO"),
+        }
+    }
+
+    // Resolves a row with ID_LOGRADOURO, NR_LOGRADOURO and DS_COMPLEMENTO_NUMERO
+    pub(crate) fn resolve(&self, row: Row) -> Address {
+        let street = row
+            .u64("ID_LOGRADOURO")
+            .and_then(|id| self.streets.get(&id));
+        let neighborhood = street
+            .and_then(|street| street.u64("ID_BAIRRO"))
+            .and_then(|id| self.neighborhoods.get(&id));
+        let city = neighborhood
+            .and_then(|neighborhood| neighborhood.u64("ID_CIDADE"))
+            .and_then(|id| self.cities.get(&id));
+        let state = city
+            .and_then(|city| city.u64("ID_ESTADO"))
+            .and_then(|id| self.states.get(&id));
+
+        Address {
+            street: street.map(|row| row.text("NM_LOGRADOURO")),
+            number: row.text("NR_LOGRADOURO"),
+            complement: row.text("DS_COMPLEMENTO_NUMERO"),
+            district: neighborhood.map(|row| row.text("NM_BAIRRO")),
+            city: city.map(|row| row.text("NM_CIDADE")),
+            state: state.map(|row| row.text("SG_ESTADO")),
+            state_code: state.and_then(|row| row.u64("ID_ESTADO")),
+            postal_code: street.map(|row| row.text("NR_CEP")),
+        }
+    }
+}
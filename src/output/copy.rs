@@ -0,0 +1,175 @@
+use indicatif::ProgressBar;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+use super::DATA_DIR;
+use crate::sql_generator::{ColumnKind, TableRow, Value};
+use crate::tables::{schema, TableInfo};
+
+const NULL: &str = "\\N";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CopyFormat {
+    Text,
+    Csv,
+}
+
+// Tables whose COPY file this run wrote, in each format
+static WRITTEN: Mutex<Vec<(&'static str, CopyFormat)>> = Mutex::new(Vec::new());
+
+impl CopyFormat {
+    // Suffix of the COPY files, distinct per format so both can be written in one run
+    fn suffix(self) -> &'static str {
+        match self {
+            CopyFormat::Text => "copy",
+            CopyFormat::Csv => "copy-csv",
+        }
+    }
+
+    fn wrapper(self) -> &'static str {
+        match self {
+            CopyFormat::Text => "load_postgres.sql",
+            CopyFormat::Csv => "load_postgres_csv.sql",
+        }
+    }
+}
+
+pub(crate) fn create_table_sql(table: &TableInfo) -> String {
+    let columns: Vec<String> = table
+        .columns
+        .iter()
+        .map(|column| {
+            let kind = match column.kind {
+                ColumnKind::Integer => "BIGINT",
+                ColumnKind::Text => "TEXT",
+                ColumnKind::Date => "TIMESTAMP",
+            };
+            format!("{} {}", column.name, kind)
+        })
+        .collect();
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({});",
+        table.name,
+        columns.join(", ")
+    )
+}
+
+// Escapes a field for COPY's text format
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Writes `data/<TABLE>.copy.sql` (`.copy-csv.sql` in CSV format), a
+// `COPY ... FROM stdin` block for psql
+pub(crate) fn write<T: TableRow>(
+    rows: &[T],
+    format: CopyFormat,
+    pb: &ProgressBar,
+) -> std::io::Result<()> {
+    fs::create_dir_all(DATA_DIR)?;
+    let file = File::create(format!("{}/{}.{}.sql", DATA_DIR, T::TABLE, format.suffix()))?;
+    let mut writer = BufWriter::new(file);
+
+    let names: Vec<&str> = T::columns().iter().map(|column| column.name).collect();
+    let options = match format {
+        CopyFormat::Text => String::new(),
+        CopyFormat::Csv => format!(" WITH (FORMAT csv, NULL '{}')", NULL),
+    };
+    writeln!(
+        writer,
+        "COPY {} ({}) FROM stdin{};",
+        T::TABLE,
+        names.join(", "),
+        options
+    )?;
+
+    match format {
+        CopyFormat::Text => {
+            for row in rows {
+                let fields: Vec<String> = row
+                    .values()
+                    .iter()
+                    .map(|value| match value {
+                        Value::Null => NULL.to_string(),
+                        value => escape_text(&value.to_text()),
+                    })
+                    .collect();
+                writeln!(writer, "{}", fields.join("\t"))?;
+                pb.inc(1);
+            }
+        }
+        CopyFormat::Csv => {
+            let mut csv = ::csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut writer);
+            for row in rows {
+                csv.write_record(row.values().iter().map(|value| match value {
+                    Value::Null => NULL.to_string(),
+                    value => value.to_text(),
+                }))?;
+                pb.inc(1);
+            }
+            csv.flush()?;
+        }
+    }
+
+    writeln!(writer, "\\.")?;
+    writer.flush()?;
+
+    WRITTEN.lock().unwrap().push((T::TABLE, format));
+    Ok(())
+}
+
+// Writes the psql script creating the tables this run wrote in `format` and loading
+// their COPY files in dependency order. Triggers are disabled during the load so none
+// the staging database defines fire per row. `DISABLE TRIGGER ALL` also covers the
+// internal triggers enforcing foreign keys, so the script must run as a superuser; it
+// creates no constraints of its own and re-enabling the triggers does not recheck the
+// loaded rows.
+pub(crate) fn write_wrapper(format: CopyFormat) -> std::io::Result<()> {
+    let written = WRITTEN.lock().unwrap();
+    let tables: Vec<TableInfo> = schema()
+        .into_iter()
+        .filter(|table| written.contains(&(table.name, format)))
+        .collect();
+
+    fs::create_dir_all(DATA_DIR)?;
+    let file = File::create(format!("{}/{}", DATA_DIR, format.wrapper()))?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "-- psql -f {}", format.wrapper())?;
+    writeln!(
+        writer,
+        "-- Run as a superuser: DISABLE TRIGGER ALL needs it for the foreign key triggers"
+    )?;
+    writeln!(writer, "\\set ON_ERROR_STOP on")?;
+    writeln!(writer, "BEGIN;")?;
+    for table in &tables {
+        writeln!(writer, "{}", create_table_sql(table))?;
+    }
+    for table in &tables {
+        writeln!(writer, "ALTER TABLE {} DISABLE TRIGGER ALL;", table.name)?;
+    }
+    for table in &tables {
+        writeln!(writer, "\\ir {}.{}.sql", table.name, format.suffix())?;
+    }
+    for table in &tables {
+        writeln!(writer, "ALTER TABLE {} ENABLE TRIGGER ALL;", table.name)?;
+    }
+    writeln!(writer, "COMMIT;")?;
+    writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use indicatif::ProgressBar;
+use std::fs;
+
+use super::DATA_DIR;
+use crate::sql_generator::TableRow;
+
+// Writes `data/<TABLE>.csv` with a header row and ISO dates
+pub(crate) fn write<T: TableRow>(rows: &[T], pb: &ProgressBar) -> std::io::Result<()> {
+    fs::create_dir_all(DATA_DIR)?;
+    let filename = format!("{}/{}.csv", DATA_DIR, T::TABLE);
+    let mut writer = ::csv::Writer::from_path(filename)?;
+
+    writer.write_record(T::columns().iter().map(|column| column.name))?;
+    for row in rows {
+        writer.write_record(row.values().iter().map(|value| value.to_text()))?;
+        pb.inc(1);
+    }
+    writer.flush()?;
+
+    Ok(())
+}
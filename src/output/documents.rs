@@ -0,0 +1,106 @@
+use serde_json::Map;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use super::collect::Tables;
+use super::jsonl::{to_json, to_object};
+use super::DATA_DIR;
+
+// Child tables nested into each patient: (table, key, document field)
+const PATIENT_CHILDREN: [(&str, &str, &str); 6] = [
+    ("T_RHSTU_EMAIL_PACIENTE", "ID_PACIENTE", "emails"),
+    ("T_RHSTU_TELEFONE_PACIENTE", "ID_PACIENTE", "telefones"),
+    ("T_RHSTU_ENDERECO_PACIENTE", "ID_PACIENTE", "enderecos"),
+    ("T_RHSTU_CONTATO_PACIENTE", "ID_PACIENTE", "contatos"),
+    (
+        "T_RHSTU_PACIENTE_PLANO_SAUDE",
+        "ID_PACIENTE",
+        "planos_saude",
+    ),
+    ("T_RHSTU_CONSULTA", "ID_PACIENTE", "consultas"),
+];
+const PATIENTS: &str = "T_RHSTU_PACIENTE";
+const PRESCRIPTIONS: &str = "T_RHSTU_PRESCRICAO_MEDICA";
+
+pub(crate) const TABLES: [&str; 8] = [
+    PATIENTS,
+    PRESCRIPTIONS,
+    PATIENT_CHILDREN[0].0,
+    PATIENT_CHILDREN[1].0,
+    PATIENT_CHILDREN[2].0,
+    PATIENT_CHILDREN[3].0,
+    PATIENT_CHILDREN[4].0,
+    PATIENT_CHILDREN[5].0,
+];
+
+type Groups = HashMap<u64, Vec<Map<String, serde_json::Value>>>;
+
+// Groups the rows of `table` by `key` as JSON objects, without the key itself
+fn group_by(tables: &Tables, table: &str, key: &str) -> Groups {
+    let mut groups = Groups::new();
+    let Some(rows) = tables.get(table) else {
+        return groups;
+    };
+
+    for row in rows.iter() {
+        let Some(id) = row.u64(key) else {
+            continue;
+        };
+        let object = row
+            .columns()
+            .iter()
+            .zip(row.values())
+            .filter(|(column, _)| **column != key)
+            .map(|(column, value)| (column.to_string(), to_json(value)))
+            .collect();
+        groups.entry(id).or_default().push(object);
+    }
+    groups
+}
+
+// Writes `data/jsonl/patient_documents.jsonl`, one nested document per patient
+pub(crate) fn write(tables: &Tables) -> std::io::Result<()> {
+    let dir = format!("{}/jsonl", DATA_DIR);
+    fs::create_dir_all(&dir)?;
+    let file = File::create(format!("{}/patient_documents.jsonl", dir))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut prescriptions = group_by(tables, PRESCRIPTIONS, "ID_CONSULTA");
+    let mut children: Vec<(&str, Groups)> = PATIENT_CHILDREN
+        .iter()
+        .map(|(table, key, field)| (*field, group_by(tables, table, key)))
+        .collect();
+
+    let Some(patients) = tables.get(PATIENTS) else {
+        return Ok(());
+    };
+
+    for row in patients.iter() {
+        let Some(id) = row.u64("ID_PACIENTE") else {
+            continue;
+        };
+        let mut document = to_object(row.columns(), row.values());
+
+        for (field, groups) in children.iter_mut() {
+            let mut objects = groups.remove(&id).unwrap_or_default();
+            if *field == "consultas" {
+                for consultation in objects.iter_mut() {
+                    let consultation_id =
+                        consultation.get("ID_CONSULTA").and_then(|id| id.as_u64());
+                    let nested = consultation_id
+                        .and_then(|id| prescriptions.remove(&id))
+                        .unwrap_or_default();
+                    consultation.insert("prescricoes".to_string(), nested.into());
+                }
+            }
+            document.insert(field.to_string(), objects.into());
+        }
+
+        serde_json::to_writer(&mut writer, &document)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
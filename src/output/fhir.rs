@@ -0,0 +1,463 @@
+use chrono::NaiveDateTime;
+use serde_json::{json, Value as Json};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use super::collect::{group, index, rows, Address, Geography, Tables};
+use super::DATA_DIR;
+
+pub(crate) const TABLES: [&str; 17] = [
+    "T_RHSTU_ESTADO",
+    "T_RHSTU_CIDADE",
+    "T_RHSTU_BAIRRO",
+    "T_RHSTU_LOGRADOURO",
+    "T_RHSTU_PACIENTE",
+    "T_RHSTU_EMAIL_PACIENTE",
+    "T_RHSTU_TELEFONE_PACIENTE",
+    "T_RHSTU_ENDERECO_PACIENTE",
+    "T_RHSTU_FUNCIONARIO",
+    "T_RHSTU_MEDICO",
+    "T_RHSTU_UNID_HOSPITALAR",
+    "T_RHSTU_ENDERECO_UNIDHOSP",
+    "T_RHSTU_CONSULTA",
+    "T_RHSTU_PRESCRICAO_MEDICA",
+    "T_RHSTU_MEDICAMENTO",
+    "T_RHSTU_PLANO_SAUDE",
+    "T_RHSTU_PACIENTE_PLANO_SAUDE",
+];
+
+const CPF_SYSTEM: &str = "http://rnds.saude.gov.br/fhir/r4/NamingSystem/cpf";
+const CRM_SYSTEM: &str = "https://portal.cfm.org.br/crm";
+const CNPJ_SYSTEM: &str = "http://rnds.saude.gov.br/fhir/r4/NamingSystem/cnpj";
+const ACT_CODE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ActCode";
+
+// Entries per transaction bundle, one bundle per line
+const BUNDLE_SIZE: usize = 500;
+
+fn date(date: Option<NaiveDateTime>) -> Json {
+    date.map(|date| json!(date.format("%Y-%m-%d").to_string()))
+        .unwrap_or(Json::Null)
+}
+
+// Generated timestamps are local to Brasília
+fn date_time(date: Option<NaiveDateTime>) -> Json {
+    date.map(|date| json!(date.format("%Y-%m-%dT%H:%M:%S-03:00").to_string()))
+        .unwrap_or(Json::Null)
+}
+
+fn reference(resource_type: &str, id: impl std::fmt::Display) -> Json {
+    json!({ "reference": format!("{}/{}", resource_type, id) })
+}
+
+// Drops null members, and the objects and arrays they leave empty, at every depth so
+// optional elements are omitted rather than written as null
+fn compact(resource: Json) -> Json {
+    match resource {
+        Json::Object(object) => Json::Object(
+            object
+                .into_iter()
+                .map(|(name, value)| (name, compact(value)))
+                .filter(|(_, value)| !is_empty(value))
+                .collect(),
+        ),
+        Json::Array(items) => Json::Array(
+            items
+                .into_iter()
+                .map(compact)
+                .filter(|value| !is_empty(value))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn is_empty(value: &Json) -> bool {
+    match value {
+        Json::Null => true,
+        Json::Object(object) => object.is_empty(),
+        Json::Array(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+// Writes one resource type as NDJSON transaction bundles of PUT requests
+struct BundleWriter {
+    writer: BufWriter<File>,
+    entries: Vec<Json>,
+}
+
+impl BundleWriter {
+    fn create(dir: &str, resource_type: &str) -> std::io::Result<Self> {
+        let file = File::create(format!("{}/{}.ndjson", dir, resource_type))?;
+        Ok(BundleWriter {
+            writer: BufWriter::new(file),
+            entries: Vec::with_capacity(BUNDLE_SIZE),
+        })
+    }
+
+    fn push(&mut self, resource: Json) -> std::io::Result<()> {
+        let url = format!(
+            "{}/{}",
+            resource["resourceType"].as_str().unwrap_or_default(),
+            resource["id"].as_str().unwrap_or_default()
+        );
+        self.entries.push(json!({
+            "fullUrl": url,
+            "resource": compact(resource),
+            "request": { "method": "PUT", "url": url },
+        }));
+        if self.entries.len() == BUNDLE_SIZE {
+            self.flush_bundle()?;
+        }
+        Ok(())
+    }
+
+    fn flush_bundle(&mut self) -> std::io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": std::mem::take(&mut self.entries),
+        });
+        serde_json::to_writer(&mut self.writer, &bundle)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        self.flush_bundle()?;
+        self.writer.flush()
+    }
+}
+
+// FHIR address of a row resolved through `Geography`
+fn to_address(address: &Address, period: Json) -> Json {
+    let mut line = Vec::new();
+    if let Some(street) = &address.street {
+        line.push(format!("{}, {}", street, address.number));
+    }
+    line.push(address.complement.clone());
+
+    compact(json!({
+        "use": "home",
+        "line": line,
+        "district": address.district,
+        "city": address.city,
+        "state": address.state,
+        "postalCode": address.postal_code,
+        "country": "BR",
+        "period": period,
+    }))
+}
+
+fn patients(tables: &Tables, geography: &Geography, dir: &str) -> std::io::Result<()> {
+    let emails = group(tables, "T_RHSTU_EMAIL_PACIENTE", "ID_PACIENTE");
+    let phones = group(tables, "T_RHSTU_TELEFONE_PACIENTE", "ID_PACIENTE");
+    let addresses = group(tables, "T_RHSTU_ENDERECO_PACIENTE", "ID_PACIENTE");
+    let mut writer = BundleWriter::create(dir, "Patient")?;
+
+    for patient in rows(tables, "T_RHSTU_PACIENTE") {
+        let Some(id) = patient.u64("ID_PACIENTE") else {
+            continue;
+        };
+
+        let mut telecom: Vec<Json> = Vec::new();
+        for email in emails.get(&id).into_iter().flatten() {
+            telecom.push(json!({
+                "system": "email",
+                "value": email.text("DS_EMAIL"),
+                "use": if email.text("TP_EMAIL") == "Trabalho" { "work" } else { "home" },
+            }));
+        }
+        for phone in phones.get(&id).into_iter().flatten() {
+            let usage = match phone.text("TP_TELEFONE").as_str() {
+                "CELULAR" => "mobile",
+                "COMERCIAL" => "work",
+                "RESIDENCIAL" => "home",
+                _ => "temp",
+            };
+            telecom.push(json!({
+                "system": "phone",
+                "value": format!(
+                    "+{} {} {}",
+                    phone.text("NR_DDI"),
+                    phone.text("NR_DDD"),
+                    phone.text("NR_TELEFONE")
+                ),
+                "use": usage,
+            }));
+        }
+
+        let address: Vec<Json> = addresses
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|row| {
+                let period = json!({
+                    "start": date_time(row.date("DT_INICIO")),
+                    "end": date_time(row.date("DT_FIM")),
+                });
+                to_address(&geography.resolve(*row), period)
+            })
+            .collect();
+
+        let gender = match patient.text("FL_SEXO_BIOLOGICO").as_str() {
+            "M" => "male",
+            "F" => "female",
+            _ => "unknown",
+        };
+
+        writer.push(json!({
+            "resourceType": "Patient",
+            "id": id.to_string(),
+            "identifier": [{
+                "system": CPF_SYSTEM,
+                "value": format!("{:011}", patient.u64("NR_CPF").unwrap_or(0)),
+            }],
+            "name": [{ "use": "official", "text": patient.text("NM_PACIENTE") }],
+            "telecom": telecom,
+            "gender": gender,
+            "birthDate": date(patient.date("DT_NASCIMENTO")),
+            "address": address,
+        }))?;
+    }
+
+    writer.finish()
+}
+
+fn practitioners(tables: &Tables, dir: &str) -> std::io::Result<HashSet<u64>> {
+    let employees = index(tables, "T_RHSTU_FUNCIONARIO", "ID_FUNC");
+    let mut ids = HashSet::new();
+    let mut writer = BundleWriter::create(dir, "Practitioner")?;
+
+    for doctor in rows(tables, "T_RHSTU_MEDICO") {
+        let Some(id) = doctor.u64("ID_FUNC") else {
+            continue;
+        };
+        let employee = employees.get(&id);
+
+        writer.push(json!({
+            "resourceType": "Practitioner",
+            "id": id.to_string(),
+            "identifier": [{ "system": CRM_SYSTEM, "value": doctor.text("NR_CRM") }],
+            "name": employee.map(|employee| json!([{ "text": employee.text("NM_FUNC") }])),
+            "birthDate": employee.map(|employee| date(employee.date("DT_NASCIMENTO"))),
+            "qualification": [{ "code": { "text": doctor.text("DS_ESPECIALIDADE") } }],
+        }))?;
+        ids.insert(id);
+    }
+
+    writer.finish()?;
+    Ok(ids)
+}
+
+// Hospitals become an Organization plus the Location at their address
+fn hospitals(tables: &Tables, geography: &Geography, dir: &str) -> std::io::Result<HashSet<u64>> {
+    let addresses = group(tables, "T_RHSTU_ENDERECO_UNIDHOSP", "ID_UNID_HOSPITAL");
+    let mut ids = HashSet::new();
+    let mut organizations = BundleWriter::create(dir, "Organization")?;
+    let mut locations = BundleWriter::create(dir, "Location")?;
+
+    for hospital in rows(tables, "T_RHSTU_UNID_HOSPITALAR") {
+        let Some(id) = hospital.u64("ID_UNID_HOSPITAL") else {
+            continue;
+        };
+
+        organizations.push(json!({
+            "resourceType": "Organization",
+            "id": id.to_string(),
+            "active": true,
+            "type": [{ "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/organization-type",
+                "code": "prov",
+            }] }],
+            "name": hospital.text("NM_UNID_HOSPITALAR"),
+            "alias": [hospital.text("NM_RAZAO_SOCIAL_UNID_HOSP")],
+        }))?;
+
+        let address = addresses.get(&id).and_then(|addresses| addresses.first());
+        locations.push(json!({
+            "resourceType": "Location",
+            "id": id.to_string(),
+            "status": "active",
+            "name": hospital.text("NM_UNID_HOSPITALAR"),
+            "address": address.map(|row| to_address(&geography.resolve(*row), Json::Null)),
+            "managingOrganization": reference("Organization", id),
+        }))?;
+        ids.insert(id);
+    }
+
+    // Health plan operators are Organizations too, prefixed to keep ids apart
+    for plan in rows(tables, "T_RHSTU_PLANO_SAUDE") {
+        let Some(id) = plan.u64("ID_PLANO_SAUDE") else {
+            continue;
+        };
+        organizations.push(json!({
+            "resourceType": "Organization",
+            "id": format!("plano-{}", id),
+            "active": true,
+            "identifier": [{ "system": CNPJ_SYSTEM, "value": plan.text("NR_CNPJ") }],
+            "type": [{ "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/organization-type",
+                "code": "ins",
+            }] }],
+            "name": plan.text("NM_FANTASIA_PLANO_SAUDE"),
+            "alias": [plan.text("DS_RAZAO_SOCIAL")],
+        }))?;
+    }
+
+    organizations.finish()?;
+    locations.finish()?;
+    Ok(ids)
+}
+
+fn encounters(
+    tables: &Tables,
+    practitioners: &HashSet<u64>,
+    hospitals: &HashSet<u64>,
+    patients: &HashSet<u64>,
+    dir: &str,
+) -> std::io::Result<HashMap<u64, u64>> {
+    let mut consultation_patients = HashMap::new();
+    let mut writer = BundleWriter::create(dir, "Encounter")?;
+
+    for consultation in rows(tables, "T_RHSTU_CONSULTA") {
+        let (Some(id), Some(patient)) = (
+            consultation.u64("ID_CONSULTA"),
+            consultation.u64("ID_PACIENTE"),
+        ) else {
+            continue;
+        };
+        if !patients.contains(&patient) {
+            continue;
+        }
+        let doctor = consultation
+            .u64("ID_FUNC")
+            .filter(|id| practitioners.contains(id));
+        let hospital = consultation
+            .u64("ID_UNID_HOSPITAL")
+            .filter(|id| hospitals.contains(id));
+
+        writer.push(json!({
+            "resourceType": "Encounter",
+            "id": id.to_string(),
+            "status": "finished",
+            "class": { "system": ACT_CODE_SYSTEM, "code": "AMB", "display": "ambulatory" },
+            "subject": reference("Patient", patient),
+            "participant": doctor.map(|doctor| json!([{ "individual": reference("Practitioner", doctor) }])),
+            "period": { "start": date_time(consultation.date("DT_HR_CONSULTA")) },
+            "location": hospital.map(|hospital| json!([{ "location": reference("Location", hospital) }])),
+            "serviceProvider": hospital.map(|hospital| reference("Organization", hospital)),
+        }))?;
+        consultation_patients.insert(id, patient);
+    }
+
+    writer.finish()?;
+    Ok(consultation_patients)
+}
+
+fn medication_requests(
+    tables: &Tables,
+    consultation_patients: &HashMap<u64, u64>,
+    dir: &str,
+) -> std::io::Result<()> {
+    let medicines = index(tables, "T_RHSTU_MEDICAMENTO", "ID_MEDICAMENTO");
+    let mut writer = BundleWriter::create(dir, "MedicationRequest")?;
+
+    for prescription in rows(tables, "T_RHSTU_PRESCRICAO_MEDICA") {
+        let Some(id) = prescription.u64("ID_PRESCRICAO_MEDICA") else {
+            continue;
+        };
+        let Some((consultation, patient)) =
+            prescription.u64("ID_CONSULTA").and_then(|consultation| {
+                Some((consultation, *consultation_patients.get(&consultation)?))
+            })
+        else {
+            continue;
+        };
+        let medicine = prescription
+            .u64("ID_MEDICAMENTO")
+            .and_then(|id| medicines.get(&id));
+
+        writer.push(json!({
+            "resourceType": "MedicationRequest",
+            "id": id.to_string(),
+            "status": "completed",
+            "intent": "order",
+            "medicationCodeableConcept": {
+                "text": medicine.map(|medicine| medicine.text("NM_MEDICAMENTO")),
+            },
+            "subject": reference("Patient", patient),
+            "encounter": reference("Encounter", consultation),
+            "authoredOn": date_time(prescription.date("DT_CADASTRO")),
+            "dosageInstruction": [{
+                "text": prescription.text("DS_POSOLOGIA"),
+                "patientInstruction": prescription.text("DS_OBSERVACAO_USO"),
+                "route": { "text": prescription.text("DS_VIA") },
+            }],
+            "dispenseRequest": {
+                "quantity": { "value": prescription.u64("QT_MEDICAMENTO") },
+            },
+        }))?;
+    }
+
+    writer.finish()
+}
+
+fn coverages(tables: &Tables, patients: &HashSet<u64>, dir: &str) -> std::io::Result<()> {
+    let plans: HashSet<u64> = index(tables, "T_RHSTU_PLANO_SAUDE", "ID_PLANO_SAUDE")
+        .into_keys()
+        .collect();
+    let mut writer = BundleWriter::create(dir, "Coverage")?;
+
+    for enrollment in rows(tables, "T_RHSTU_PACIENTE_PLANO_SAUDE") {
+        let (Some(id), Some(patient), Some(plan)) = (
+            enrollment.u64("ID_PACIENTE_PS"),
+            enrollment.u64("ID_PACIENTE"),
+            enrollment.u64("ID_PLANO_SAUDE"),
+        ) else {
+            continue;
+        };
+        if !patients.contains(&patient) || !plans.contains(&plan) {
+            continue;
+        }
+
+        writer.push(json!({
+            "resourceType": "Coverage",
+            "id": id.to_string(),
+            "status": "active",
+            "subscriberId": enrollment.text("NR_CARTEIRA_PS"),
+            "beneficiary": reference("Patient", patient),
+            "period": {
+                "start": date_time(enrollment.date("DT_INICIO")),
+                "end": date_time(enrollment.date("DT_FIM")),
+            },
+            "payor": [reference("Organization", format!("plano-{}", plan))],
+        }))?;
+    }
+
+    writer.finish()
+}
+
+// Writes `data/fhir/<ResourceType>.ndjson`; references only point at resources written here
+pub(crate) fn write(tables: &Tables) -> std::io::Result<()> {
+    let dir = format!("{}/fhir", DATA_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let geography = Geography::new(tables);
+    let patient_ids: HashSet<u64> = index(tables, "T_RHSTU_PACIENTE", "ID_PACIENTE")
+        .into_keys()
+        .collect();
+
+    patients(tables, &geography, &dir)?;
+    let practitioner_ids = practitioners(tables, &dir)?;
+    let hospital_ids = hospitals(tables, &geography, &dir)?;
+    let consultation_patients =
+        encounters(tables, &practitioner_ids, &hospital_ids, &patient_ids, &dir)?;
+    medication_requests(tables, &consultation_patients, &dir)?;
+    coverages(tables, &patient_ids, &dir)?;
+
+    Ok(())
+}
@@ -0,0 +1,825 @@
+use anyhow::{bail, Context};
+use chrono::NaiveDateTime;
+use indicatif::MultiProgress;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::collect::{group, index, rows, Geography, Row, Tables};
+use super::DATA_DIR;
+use crate::common::ProgressBarHelper;
+
+pub(crate) const TABLES: [&str; 14] = [
+    "T_RHSTU_ESTADO",
+    "T_RHSTU_CIDADE",
+    "T_RHSTU_BAIRRO",
+    "T_RHSTU_LOGRADOURO",
+    "T_RHSTU_PACIENTE",
+    "T_RHSTU_EMAIL_PACIENTE",
+    "T_RHSTU_TELEFONE_PACIENTE",
+    "T_RHSTU_ENDERECO_PACIENTE",
+    "T_RHSTU_FUNCIONARIO",
+    "T_RHSTU_MEDICO",
+    "T_RHSTU_UNID_HOSPITALAR",
+    "T_RHSTU_CONSULTA",
+    "T_RHSTU_PRESCRICAO_MEDICA",
+    "T_RHSTU_MEDICAMENTO",
+];
+
+// MLLP framing: <VT> message <FS><CR>
+const START_BLOCK: u8 = 0x0b;
+const END_BLOCK: u8 = 0x1c;
+const CARRIAGE_RETURN: u8 = 0x0d;
+
+const VERSION: &str = "2.5";
+const SENDING_APPLICATION: &str = "MOCKDATA";
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) fn messages_path() -> PathBuf {
+    Path::new(DATA_DIR).join("hl7").join("messages.mllp")
+}
+
+// Escapes the HL7 delimiters inside a free-text value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\E\\"),
+            '|' => escaped.push_str("\\F\\"),
+            '^' => escaped.push_str("\\S\\"),
+            '&' => escaped.push_str("\\T\\"),
+            '~' => escaped.push_str("\\R\\"),
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Joins escaped components with `^`, dropping empty trailing ones
+fn components(parts: &[&str]) -> String {
+    let last = parts.iter().rposition(|part| !part.is_empty());
+    parts[..last.map_or(0, |last| last + 1)]
+        .iter()
+        .map(|part| escape(part))
+        .collect::<Vec<_>>()
+        .join("^")
+}
+
+// Generated timestamps are local to Brasília
+fn timestamp(date: Option<NaiveDateTime>) -> String {
+    date.map(|date| date.format("%Y%m%d%H%M%S-0300").to_string())
+        .unwrap_or_default()
+}
+
+// "Given Family" -> family, given
+fn split_name(name: &str) -> (&str, &str) {
+    match name.rsplit_once(' ') {
+        Some((given, family)) => (family, given),
+        None => (name, ""),
+    }
+}
+
+// One segment, with fields numbered as in the standard
+struct Segment {
+    fields: Vec<String>,
+}
+
+impl Segment {
+    fn new(name: &str) -> Self {
+        Segment {
+            fields: vec![name.to_string()],
+        }
+    }
+
+    // Sets field `field`; the value must already be escaped
+    fn set(mut self, field: usize, value: impl Into<String>) -> Self {
+        // MSH-1 is the field separator itself, so MSH fields sit one position earlier
+        let index = if self.fields[0] == "MSH" {
+            field - 1
+        } else {
+            field
+        };
+        if self.fields.len() <= index {
+            self.fields.resize(index + 1, String::new());
+        }
+        self.fields[index] = value.into();
+        self
+    }
+
+    fn encode(&self) -> String {
+        let last = self.fields.iter().rposition(|field| !field.is_empty());
+        self.fields[..=last.unwrap_or(0)].join("|")
+    }
+}
+
+// Patient, doctor and hospital lookups shared by every message
+struct Lookups<'a> {
+    geography: Geography<'a>,
+    patients: HashMap<u64, Row<'a>>,
+    emails: HashMap<u64, Vec<Row<'a>>>,
+    phones: HashMap<u64, Vec<Row<'a>>>,
+    addresses: HashMap<u64, Vec<Row<'a>>>,
+    employees: HashMap<u64, Row<'a>>,
+    doctors: HashMap<u64, Row<'a>>,
+    hospitals: HashMap<u64, Row<'a>>,
+    medicines: HashMap<u64, Row<'a>>,
+}
+
+impl<'a> Lookups<'a> {
+    fn new(tables: &'a Tables) -> Self {
+        Lookups {
+            geography: Geography::new(tables),
+            patients: index(tables, "T_RHSTU_PACIENTE", "ID_PACIENTE"),
+            emails: group(tables, "T_RHSTU_EMAIL_PACIENTE", "ID_PACIENTE"),
+            phones: group(tables, "T_RHSTU_TELEFONE_PACIENTE", "ID_PACIENTE"),
+            addresses: group(tables, "T_RHSTU_ENDERECO_PACIENTE", "ID_PACIENTE"),
+            employees: index(tables, "T_RHSTU_FUNCIONARIO", "ID_FUNC"),
+            doctors: index(tables, "T_RHSTU_MEDICO", "ID_FUNC"),
+            hospitals: index(tables, "T_RHSTU_UNID_HOSPITALAR", "ID_UNID_HOSPITAL"),
+            medicines: index(tables, "T_RHSTU_MEDICAMENTO", "ID_MEDICAMENTO"),
+        }
+    }
+
+    fn pid(&self, patient: Row) -> Segment {
+        let id = patient.u64("ID_PACIENTE").unwrap_or_default();
+        let name = patient.text("NM_PACIENTE");
+        let (family, given) = split_name(&name);
+        let cpf = format!("{:011}", patient.u64("NR_CPF").unwrap_or_default());
+
+        let addresses: Vec<String> = self
+            .addresses
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|row| {
+                let address = self.geography.resolve(*row);
+                let street = match &address.street {
+                    Some(street) => format!("{}, {}", street, address.number),
+                    None => address.number.clone(),
+                };
+                components(&[
+                    &street,
+                    &address.complement,
+                    address.city.as_deref().unwrap_or_default(),
+                    address.state.as_deref().unwrap_or_default(),
+                    address.postal_code.as_deref().unwrap_or_default(),
+                    "BRA",
+                    "H",
+                    address.district.as_deref().unwrap_or_default(),
+                ])
+            })
+            .collect();
+
+        let mut home = Vec::new();
+        let mut business = Vec::new();
+        for phone in self.phones.get(&id).into_iter().flatten() {
+            let (usage, equipment) = match phone.text("TP_TELEFONE").as_str() {
+                "CELULAR" => ("PRS", "CP"),
+                "COMERCIAL" => ("WPN", "PH"),
+                "RESIDENCIAL" => ("PRN", "PH"),
+                _ => ("ORN", "PH"),
+            };
+            let telecom = components(&[
+                "",
+                usage,
+                equipment,
+                "",
+                &phone.text("NR_DDI"),
+                &phone.text("NR_DDD"),
+                &phone.text("NR_TELEFONE"),
+            ]);
+            if usage == "WPN" {
+                business.push(telecom);
+            } else {
+                home.push(telecom);
+            }
+        }
+        for email in self.emails.get(&id).into_iter().flatten() {
+            let telecom = components(&["", "NET", "Internet", &email.text("DS_EMAIL")]);
+            if email.text("TP_EMAIL") == "Trabalho" {
+                business.push(telecom);
+            } else {
+                home.push(telecom);
+            }
+        }
+
+        let sex = match patient.text("FL_SEXO_BIOLOGICO").as_str() {
+            "M" => "M",
+            "F" => "F",
+            _ => "U",
+        };
+        let marital_status = match patient.text("DS_ESTADO_CIVIL").as_str() {
+            "Solteiro" => "S",
+            "Casado" => "M",
+            "Divorciado" => "D",
+            "Viúvo" => "W",
+            _ => "U",
+        };
+
+        Segment::new("PID")
+            .set(1, "1")
+            .set(
+                3,
+                [
+                    components(&[&id.to_string(), "", "", SENDING_APPLICATION, "MR"]),
+                    components(&[&cpf, "", "", "BRA", "NNBRA"]),
+                ]
+                .join("~"),
+            )
+            .set(5, components(&[family, given, "", "", "", "", "L"]))
+            .set(
+                7,
+                patient
+                    .date("DT_NASCIMENTO")
+                    .map(|date| date.format("%Y%m%d").to_string())
+                    .unwrap_or_default(),
+            )
+            .set(8, sex)
+            .set(11, addresses.join("~"))
+            .set(13, home.join("~"))
+            .set(14, business.join("~"))
+            .set(16, marital_status)
+    }
+
+    // XCN of an employee, identified by the CRM when they are a doctor
+    fn provider(&self, id: u64) -> String {
+        let name = self
+            .employees
+            .get(&id)
+            .map(|employee| employee.text("NM_FUNC"))
+            .unwrap_or_default();
+        let (family, given) = split_name(&name);
+        match self.doctors.get(&id) {
+            Some(doctor) => components(&[
+                &doctor.text("NR_CRM"),
+                family,
+                given,
+                "",
+                "",
+                "",
+                "",
+                "",
+                "CRM",
+            ]),
+            None => components(&[&id.to_string(), family, given]),
+        }
+    }
+
+    fn pv1(&self, consultation: Row) -> Segment {
+        let hospital = consultation.text("ID_UNID_HOSPITAL");
+        let mut segment = Segment::new("PV1")
+            .set(1, "1")
+            .set(2, "O")
+            .set(
+                3,
+                components(&["", &consultation.text("NR_CONSULTORIO"), "", &hospital]),
+            )
+            .set(
+                19,
+                components(&[
+                    &consultation.text("ID_CONSULTA"),
+                    "",
+                    "",
+                    SENDING_APPLICATION,
+                    "VN",
+                ]),
+            )
+            .set(44, timestamp(consultation.date("DT_HR_CONSULTA")));
+        if let Some(doctor) = consultation.u64("ID_FUNC") {
+            segment = segment.set(7, self.provider(doctor));
+        }
+        segment
+    }
+}
+
+// Writes MLLP frames with sequential message control ids
+struct MessageWriter<W: Write> {
+    writer: W,
+    control_id: u64,
+}
+
+impl<W: Write> MessageWriter<W> {
+    fn msh(&mut self, facility: &str, message_type: &str, time: &str) -> Segment {
+        self.control_id += 1;
+        Segment::new("MSH")
+            .set(2, "^~\\&")
+            .set(3, SENDING_APPLICATION)
+            .set(4, escape(facility))
+            .set(7, time)
+            .set(9, message_type)
+            .set(10, self.control_id.to_string())
+            .set(11, "P")
+            .set(12, VERSION)
+            .set(18, "UNICODE UTF-8")
+    }
+
+    fn write(&mut self, segments: &[Segment]) -> std::io::Result<()> {
+        let message = segments
+            .iter()
+            .map(Segment::encode)
+            .collect::<Vec<_>>()
+            .join("\r");
+        self.writer.write_all(&[START_BLOCK])?;
+        self.writer.write_all(message.as_bytes())?;
+        self.writer
+            .write_all(&[CARRIAGE_RETURN, END_BLOCK, CARRIAGE_RETURN])
+    }
+}
+
+// Writes `data/hl7/messages.mllp` in consultation order: an ADT^A04 on a patient's
+// first visit, ADT^A08 on return visits and one RDE^O11 per prescription
+pub(crate) fn write(tables: &Tables) -> std::io::Result<()> {
+    let path = messages_path();
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    write_messages(tables, BufWriter::new(File::create(&path)?))
+}
+
+fn write_messages(tables: &Tables, writer: impl Write) -> std::io::Result<()> {
+    let lookups = Lookups::new(tables);
+    let prescriptions = group(tables, "T_RHSTU_PRESCRICAO_MEDICA", "ID_CONSULTA");
+    let mut writer = MessageWriter {
+        writer,
+        control_id: 0,
+    };
+
+    let mut consultations: Vec<Row> = rows(tables, "T_RHSTU_CONSULTA").collect();
+    consultations.sort_by_key(|consultation| consultation.date("DT_HR_CONSULTA"));
+
+    let mut registered = HashSet::new();
+    for consultation in consultations {
+        let Some(patient) = consultation
+            .u64("ID_PACIENTE")
+            .and_then(|id| lookups.patients.get(&id))
+        else {
+            continue;
+        };
+        let time = timestamp(consultation.date("DT_HR_CONSULTA"));
+        let facility = consultation
+            .u64("ID_UNID_HOSPITAL")
+            .and_then(|id| lookups.hospitals.get(&id))
+            .map(|hospital| hospital.text("ID_UNID_HOSPITAL"))
+            .unwrap_or_default();
+
+        let event = if registered.insert(patient.u64("ID_PACIENTE")) {
+            "A04"
+        } else {
+            "A08"
+        };
+        let msh = writer.msh(&facility, &format!("ADT^{}^ADT_A01", event), &time);
+        writer.write(&[
+            msh,
+            Segment::new("EVN").set(1, event).set(2, time.clone()),
+            lookups.pid(*patient),
+            lookups.pv1(consultation),
+        ])?;
+
+        let id = consultation.u64("ID_CONSULTA").unwrap_or_default();
+        for prescription in prescriptions.get(&id).into_iter().flatten() {
+            let medicine = prescription
+                .u64("ID_MEDICAMENTO")
+                .and_then(|id| lookups.medicines.get(&id));
+            let quantity = prescription.text("QT_MEDICAMENTO");
+            let order = prescription.text("ID_PRESCRICAO_MEDICA");
+            let provider = consultation
+                .u64("ID_FUNC")
+                .map(|doctor| lookups.provider(doctor))
+                .unwrap_or_default();
+
+            let msh = writer.msh(&facility, "RDE^O11^RDE_O11", &time);
+            writer.write(&[
+                msh,
+                lookups.pid(*patient),
+                lookups.pv1(consultation),
+                Segment::new("ORC")
+                    .set(1, "NW")
+                    .set(2, components(&[&order, SENDING_APPLICATION]))
+                    .set(9, time.clone())
+                    .set(12, provider),
+                Segment::new("RXE")
+                    .set(1, components(&["", "", "", &time]))
+                    .set(
+                        2,
+                        components(&[
+                            &prescription.text("ID_MEDICAMENTO"),
+                            &medicine
+                                .map(|medicine| medicine.text("NM_MEDICAMENTO"))
+                                .unwrap_or_default(),
+                            SENDING_APPLICATION,
+                        ]),
+                    )
+                    .set(3, quantity.clone())
+                    .set(5, "UN")
+                    .set(7, components(&["", &prescription.text("DS_POSOLOGIA")]))
+                    .set(10, quantity)
+                    .set(11, "UN")
+                    .set(15, escape(&order))
+                    .set(
+                        21,
+                        components(&["", &prescription.text("DS_OBSERVACAO_USO")]),
+                    ),
+                Segment::new("RXR").set(1, components(&["", &prescription.text("DS_VIA")])),
+            ])?;
+        }
+    }
+
+    writer.writer.flush()
+}
+
+// Message bodies of an MLLP-framed file
+fn frames(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes
+        .split(|byte| *byte == START_BLOCK)
+        .skip(1)
+        .map(|frame| {
+            let end = frame
+                .iter()
+                .rposition(|byte| *byte == END_BLOCK)
+                .unwrap_or(frame.len());
+            &frame[..end]
+        })
+        .collect()
+}
+
+// Reads the next frame of `reader`, or None once the peer closes the connection.
+// The frame ends at <FS>: the <CR> after it is skipped with whatever else precedes
+// the next <VT>, so a peer that omits it isn't waited on.
+fn read_frame(reader: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut frame = Vec::new();
+    reader.read_until(END_BLOCK, &mut frame)?;
+    if frame.last() != Some(&END_BLOCK) {
+        // Closed, possibly after a trailing <CR> or a partial frame
+        return Ok(None);
+    }
+
+    let start = frame
+        .iter()
+        .position(|byte| *byte == START_BLOCK)
+        .map_or(0, |start| start + 1);
+    frame.truncate(frame.len() - 1);
+    Ok(Some(frame.split_off(start)))
+}
+
+// MSA-1 of an acknowledgement, e.g. `AA` or `AE`
+fn ack_code(ack: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(ack)
+        .split('\r')
+        .find(|segment| segment.starts_with("MSA|"))
+        .and_then(|segment| segment.split('|').nth(1))
+        .map(str::to_string)
+}
+
+// Sends every message to one client, waiting for its ACK when `wait_ack` is set
+fn replay(
+    stream: TcpStream,
+    messages: &[&[u8]],
+    wait_ack: bool,
+    m: &Arc<MultiProgress>,
+) -> Result<(), anyhow::Error> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let pb_helper = ProgressBarHelper::new(m.clone(), messages.len(), format!("{}:", peer));
+    let pb = &pb_helper.pb;
+
+    for message in messages {
+        writer.write_all(&[START_BLOCK])?;
+        writer.write_all(message)?;
+        writer.write_all(&[END_BLOCK, CARRIAGE_RETURN])?;
+        writer.flush()?;
+
+        if wait_ack {
+            let Some(ack) = read_frame(&mut reader)? else {
+                bail!("{} closed the connection before acknowledging", peer);
+            };
+            match ack_code(&ack).as_deref() {
+                Some("AA" | "CA") => {}
+                code => pb.println(format!(
+                    "{} rejected message {}: {}",
+                    peer,
+                    pb.position() + 1,
+                    code.unwrap_or("no MSA segment")
+                )),
+            }
+        }
+        pb.inc(1);
+    }
+
+    pb_helper.finish();
+    Ok(())
+}
+
+// Replays the messages of a `--format hl7` run to every client connecting to `listen`
+pub(crate) fn serve(
+    listen: &str,
+    wait_ack: bool,
+    m: Arc<MultiProgress>,
+) -> Result<(), anyhow::Error> {
+    let path = messages_path();
+    let bytes = fs::read(&path).with_context(|| {
+        format!(
+            "{} not found, generate it with `--format hl7` first",
+            path.display()
+        )
+    })?;
+    let messages = frames(&bytes);
+
+    let listener = TcpListener::bind(listen).with_context(|| format!("binding {}", listen))?;
+    m.println(format!(
+        "Serving {} HL7 messages over MLLP on {}",
+        messages.len(),
+        listener.local_addr()?
+    ))?;
+
+    for stream in listener.incoming() {
+        if let Err(error) = replay(stream?, &messages, wait_ack, &m) {
+            m.println(format!("Replay failed: {:#}", error))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::collect::Rows;
+    use crate::sql_generator::Value;
+    use chrono::NaiveDate;
+    use std::io::Cursor;
+
+    fn date(year: i32, month: u32, day: u32) -> Value {
+        Value::Date(
+            NaiveDate::from_ymd_opt(year, month, day)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+                .unwrap(),
+        )
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    fn table(
+        tables: &mut Tables,
+        name: &'static str,
+        columns: &[&'static str],
+        rows: Vec<Vec<Value>>,
+    ) {
+        tables.insert(
+            name,
+            Rows {
+                columns: columns.to_vec(),
+                rows,
+            },
+        );
+    }
+
+    // One patient seen twice by the same doctor, given one prescription on the return
+    fn fixture() -> Tables {
+        use Value::Integer;
+
+        let mut tables = Tables::new();
+        table(
+            &mut tables,
+            "T_RHSTU_PACIENTE",
+            &[
+                "ID_PACIENTE",
+                "NM_PACIENTE",
+                "NR_CPF",
+                "DT_NASCIMENTO",
+                "FL_SEXO_BIOLOGICO",
+                "DS_ESTADO_CIVIL",
+            ],
+            vec![vec![
+                Integer(1),
+                text("Maria|Ana Souza^Lima"),
+                Integer(12345678909),
+                date(1980, 5, 17),
+                text("F"),
+                text("Casado"),
+            ]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_TELEFONE_PACIENTE",
+            &[
+                "ID_PACIENTE",
+                "TP_TELEFONE",
+                "NR_DDI",
+                "NR_DDD",
+                "NR_TELEFONE",
+            ],
+            vec![vec![
+                Integer(1),
+                text("CELULAR"),
+                Integer(55),
+                Integer(11),
+                Integer(912345678),
+            ]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_FUNCIONARIO",
+            &["ID_FUNC", "NM_FUNC"],
+            vec![vec![Integer(7), text("João Pereira")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_MEDICO",
+            &["ID_FUNC", "NR_CRM"],
+            vec![vec![Integer(7), text("123456")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_UNID_HOSPITALAR",
+            &["ID_UNID_HOSPITAL"],
+            vec![vec![Integer(3)]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_CONSULTA",
+            &[
+                "ID_CONSULTA",
+                "ID_PACIENTE",
+                "ID_UNID_HOSPITAL",
+                "ID_FUNC",
+                "NR_CONSULTORIO",
+                "DT_HR_CONSULTA",
+            ],
+            vec![
+                vec![
+                    Integer(2),
+                    Integer(1),
+                    Integer(3),
+                    Integer(7),
+                    Integer(12),
+                    date(2023, 3, 2),
+                ],
+                vec![
+                    Integer(1),
+                    Integer(1),
+                    Integer(3),
+                    Integer(7),
+                    Integer(12),
+                    date(2023, 3, 1),
+                ],
+            ],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_MEDICAMENTO",
+            &["ID_MEDICAMENTO", "NM_MEDICAMENTO"],
+            vec![vec![Integer(4), text("Dipirona & Cafeína")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_PRESCRICAO_MEDICA",
+            &[
+                "ID_PRESCRICAO_MEDICA",
+                "ID_CONSULTA",
+                "ID_MEDICAMENTO",
+                "QT_MEDICAMENTO",
+                "DS_POSOLOGIA",
+                "DS_VIA",
+                "DS_OBSERVACAO_USO",
+            ],
+            vec![vec![
+                Integer(9),
+                Integer(2),
+                Integer(4),
+                Integer(20),
+                text("1 comprimido a cada 6 horas"),
+                text("Oral"),
+                text("Tomar após as refeições\r\nnão exceder 4 doses ~ dia"),
+            ]],
+        );
+        tables
+    }
+
+    fn unescape(text: &str) -> String {
+        text.replace("\\F\\", "|")
+            .replace("\\S\\", "^")
+            .replace("\\T\\", "&")
+            .replace("\\R\\", "~")
+            .replace("\\E\\", "\\")
+    }
+
+    // Segments of a message, each split into its fields
+    fn segments(message: &[u8]) -> Vec<Vec<String>> {
+        String::from_utf8(message.to_vec())
+            .unwrap()
+            .split('\r')
+            .map(|segment| segment.split('|').map(str::to_string).collect())
+            .collect()
+    }
+
+    fn field<'a>(segments: &'a [Vec<String>], name: &str, field: usize) -> &'a str {
+        let segment = segments
+            .iter()
+            .find(|segment| segment[0] == name)
+            .unwrap_or_else(|| panic!("no {} segment", name));
+        // MSH-1 is the separator between the name and MSH-2
+        let index = if name == "MSH" { field - 1 } else { field };
+        segment.get(index).map_or("", String::as_str)
+    }
+
+    #[test]
+    fn round_trips_admissions_and_orders() {
+        let mut bytes = Vec::new();
+        write_messages(&fixture(), &mut bytes).unwrap();
+
+        assert_eq!(bytes[0], START_BLOCK);
+        assert!(bytes.ends_with(&[CARRIAGE_RETURN, END_BLOCK, CARRIAGE_RETURN]));
+        let messages: Vec<Vec<Vec<String>>> = frames(&bytes)
+            .into_iter()
+            .map(|frame| segments(frame.strip_suffix(b"\r").unwrap()))
+            .collect();
+        assert_eq!(messages.len(), 3);
+
+        let types: Vec<&str> = messages
+            .iter()
+            .map(|message| field(message, "MSH", 9))
+            .collect();
+        assert_eq!(
+            types,
+            ["ADT^A04^ADT_A01", "ADT^A08^ADT_A01", "RDE^O11^RDE_O11"]
+        );
+        let ids: Vec<&str> = messages
+            .iter()
+            .map(|message| field(message, "MSH", 10))
+            .collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+
+        let first = &messages[0];
+        assert_eq!(field(first, "MSH", 2), "^~\\&");
+        assert_eq!(field(first, "MSH", 4), "3");
+        assert_eq!(field(first, "MSH", 12), VERSION);
+        assert_eq!(field(first, "EVN", 1), "A04");
+        assert_eq!(field(first, "EVN", 2), "20230301103000-0300");
+        assert_eq!(
+            field(first, "PID", 3),
+            "1^^^MOCKDATA^MR~12345678909^^^BRA^NNBRA"
+        );
+        assert_eq!(field(first, "PID", 5), "Souza\\S\\Lima^Maria\\F\\Ana^^^^^L");
+        let name: Vec<String> = field(first, "PID", 5).split('^').map(unescape).collect();
+        assert_eq!(name, ["Souza^Lima", "Maria|Ana", "", "", "", "", "L"]);
+        assert_eq!(field(first, "PID", 7), "19800517");
+        assert_eq!(field(first, "PID", 8), "F");
+        assert_eq!(field(first, "PID", 13), "^PRS^CP^^55^11^912345678");
+        assert_eq!(field(first, "PID", 16), "M");
+        assert_eq!(field(first, "PV1", 3), "^12^^3");
+        assert_eq!(field(first, "PV1", 7), "123456^Pereira^João^^^^^^CRM");
+        assert_eq!(field(first, "PV1", 19), "1^^^MOCKDATA^VN");
+
+        let order = &messages[2];
+        assert_eq!(field(order, "PV1", 19), "2^^^MOCKDATA^VN");
+        assert_eq!(field(order, "ORC", 1), "NW");
+        assert_eq!(field(order, "ORC", 2), "9^MOCKDATA");
+        assert_eq!(field(order, "ORC", 12), "123456^Pereira^João^^^^^^CRM");
+        let medicine: Vec<String> = field(order, "RXE", 2).split('^').map(unescape).collect();
+        assert_eq!(medicine, ["4", "Dipirona & Cafeína", "MOCKDATA"]);
+        assert_eq!(field(order, "RXE", 3), "20");
+        assert_eq!(field(order, "RXE", 7), "^1 comprimido a cada 6 horas");
+        assert_eq!(
+            unescape(field(order, "RXE", 21)),
+            "^Tomar após as refeições  não exceder 4 doses ~ dia"
+        );
+        assert_eq!(field(order, "RXR", 1), "^Oral");
+    }
+
+    #[test]
+    fn reads_frames_with_or_without_the_trailing_carriage_return() {
+        let mut reader = Cursor::new(
+            [
+                &[START_BLOCK][..],
+                b"MSH|^~\\&|A\rMSA|AA|1",
+                &[END_BLOCK, CARRIAGE_RETURN, START_BLOCK],
+                b"MSH|^~\\&|B\rMSA|AE|2",
+                &[END_BLOCK, START_BLOCK],
+                b"MSH|^~\\&|C",
+                &[END_BLOCK],
+            ]
+            .concat(),
+        );
+
+        let first = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(first, b"MSH|^~\\&|A\rMSA|AA|1");
+        assert_eq!(ack_code(&first).as_deref(), Some("AA"));
+        let second = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(ack_code(&second).as_deref(), Some("AE"));
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"MSH|^~\\&|C");
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        let mut truncated = Cursor::new([&[START_BLOCK][..], b"MSH|^~\\&|D"].concat());
+        assert_eq!(read_frame(&mut truncated).unwrap(), None);
+    }
+}
@@ -0,0 +1,44 @@
+use indicatif::ProgressBar;
+use serde_json::{Map, Number};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use super::DATA_DIR;
+use crate::sql_generator::{TableRow, Value};
+
+pub(crate) fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(number) => serde_json::Value::Number(Number::from(*number)),
+        Value::Text(text) => serde_json::Value::String(text.clone()),
+        Value::Date(date) => {
+            serde_json::Value::String(date.format("%Y-%m-%dT%H:%M:%S").to_string())
+        }
+    }
+}
+
+pub(crate) fn to_object(columns: &[&str], values: &[Value]) -> Map<String, serde_json::Value> {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| (column.to_string(), to_json(value)))
+        .collect()
+}
+
+// Writes `data/jsonl/<TABLE>.jsonl`, one object per row
+pub(crate) fn write<T: TableRow>(rows: &[T], pb: &ProgressBar) -> std::io::Result<()> {
+    let dir = format!("{}/jsonl", DATA_DIR);
+    fs::create_dir_all(&dir)?;
+    let file = File::create(format!("{}/{}.jsonl", dir, T::TABLE))?;
+    let mut writer = BufWriter::new(file);
+
+    let columns: Vec<&str> = T::columns().iter().map(|column| column.name).collect();
+    for row in rows {
+        serde_json::to_writer(&mut writer, &to_object(&columns, &row.values()))?;
+        writer.write_all(b"\n")?;
+        pb.inc(1);
+    }
+    writer.flush()?;
+
+    Ok(())
+}
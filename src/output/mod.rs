@@ -0,0 +1,99 @@
+use clap::ValueEnum;
+use std::sync::OnceLock;
+
+pub(crate) mod arrow;
+pub(crate) mod collect;
+pub(crate) mod copy;
+pub(crate) mod csv;
+pub(crate) mod documents;
+pub(crate) mod fhir;
+pub(crate) mod hl7;
+pub(crate) mod jsonl;
+pub(crate) mod parquet;
+pub(crate) mod sqlldr;
+pub(crate) mod tiss;
+
+use self::copy::CopyFormat;
+use self::parquet::ParquetOptions;
+use crate::load::LoadOptions;
+
+pub(crate) const DATA_DIR: &str = "data";
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+// Formats and writer options chosen on the command line
+pub(crate) struct Output {
+    pub(crate) formats: Vec<Format>,
+    pub(crate) parquet: ParquetOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// Oracle `INSERT ALL` scripts
+    Sql,
+    /// One CSV file per table
+    Csv,
+    /// Oracle SQL*Loader control (`.ctl`) and data (`.dat`) files
+    Sqlldr,
+    /// PostgreSQL `COPY ... FROM stdin` blocks in text format, plus a psql wrapper run as a superuser
+    Copy,
+    /// PostgreSQL `COPY ... FROM stdin` blocks in CSV format, plus a psql wrapper run as a superuser
+    CopyCsv,
+    /// Apache Parquet files, optionally partitioned by month
+    Parquet,
+    /// Arrow IPC files
+    Arrow,
+    /// JSON Lines, one object per row
+    Jsonl,
+    /// One nested JSON document per patient with its contacts, plans and consultations
+    PatientDocuments,
+    /// HL7 FHIR R4 transaction bundles, one NDJSON file per resource type
+    Fhir,
+    /// HL7 v2.5 ADT^A04/A08 and RDE^O11 messages in one MLLP-framed file
+    Hl7,
+    /// ANS TISS 3.05 consultation guide batches (`loteGuias`) per health plan
+    Tiss,
+    /// Streams every table into `--database` as it's generated
+    Load,
+}
+
+pub(crate) fn init(
+    formats: Vec<Format>,
+    parquet: ParquetOptions,
+    load: &LoadOptions,
+) -> std::io::Result<()> {
+    parquet.validate()?;
+    if formats.contains(&Format::Tiss) {
+        tiss::validate()?;
+    }
+    if formats.contains(&Format::Load) {
+        crate::load::start(load).map_err(std::io::Error::other)?;
+    }
+    let _ = OUTPUT.set(Output { formats, parquet });
+
+    Ok(())
+}
+
+// Writes the formats that join several tables, and the psql wrappers of the COPY files,
+// once every table is generated, then waits for the database load to commit
+pub(crate) fn finish() -> std::io::Result<()> {
+    let tables = collect::take();
+    for format in &output().formats {
+        match format {
+            Format::PatientDocuments => documents::write(&tables)?,
+            Format::Fhir => fhir::write(&tables)?,
+            Format::Hl7 => hl7::write(&tables)?,
+            Format::Tiss => tiss::write(&tables)?,
+            Format::Copy => copy::write_wrapper(CopyFormat::Text)?,
+            Format::CopyCsv => copy::write_wrapper(CopyFormat::Csv)?,
+            Format::Load => crate::load::finish().map_err(std::io::Error::other)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn output() -> &'static Output {
+    OUTPUT.get().expect("output not initialised")
+}
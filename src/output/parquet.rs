@@ -0,0 +1,180 @@
+use chrono::Datelike;
+use clap::{builder::RangedU64ValueParser, ValueEnum};
+use indicatif::ProgressBar;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+
+use super::{arrow, DATA_DIR};
+use crate::sql_generator::{ColumnKind, TableRow, Value};
+use crate::tables;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct ParquetOptions {
+    /// Maximum rows per Parquet row group
+    #[arg(
+        long,
+        default_value_t = 1_048_576,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub(crate) row_group_size: usize,
+
+    /// Parquet compression codec
+    #[arg(long, value_enum, default_value = "snappy")]
+    pub(crate) compression: ParquetCompression,
+
+    /// Partition a table by year and month of a date column, e.g. `T_RHSTU_CONSULTA=DT_HR_CONSULTA`
+    #[arg(long = "partition", value_parser = parse_partition)]
+    pub(crate) partitions: Vec<(String, String)>,
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn parse_partition(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(table, column)| (table.to_string(), column.to_string()))
+        .ok_or_else(|| format!("expected TABLE=COLUMN, found {}", arg))
+}
+
+impl ParquetOptions {
+    // Every partition must name a date column of a known table, checked before any
+    // row is generated
+    pub(crate) fn validate(&self) -> std::io::Result<()> {
+        let schema = tables::schema();
+        for (table, column) in &self.partitions {
+            let Some(info) = schema.iter().find(|info| info.name == table) else {
+                return Err(invalid(format!("--partition: unknown table {}", table)));
+            };
+            match info.columns.iter().find(|c| c.name == column) {
+                Some(c) if c.kind == ColumnKind::Date => {}
+                Some(_) => {
+                    return Err(invalid(format!(
+                        "--partition: {}.{} is not a date column",
+                        table, column
+                    )))
+                }
+                None => {
+                    return Err(invalid(format!(
+                        "--partition: {} has no column {}",
+                        table, column
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn properties(&self) -> WriterProperties {
+        let compression = match self.compression {
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+            ParquetCompression::Lz4 => Compression::LZ4_RAW,
+        };
+
+        WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size)
+            .set_compression(compression)
+            .build()
+    }
+
+    fn partition_column(&self, table: &str) -> Option<&str> {
+        self.partitions
+            .iter()
+            .find(|(name, _)| name == table)
+            .map(|(_, column)| column.as_str())
+    }
+}
+
+// Writes `data/parquet/<TABLE>.parquet`, or one
+// `data/parquet/<TABLE>/year=YYYY/month=MM/part-0.parquet` per month for partitioned tables
+pub(crate) fn write<T: TableRow>(
+    rows: &[T],
+    options: &ParquetOptions,
+    pb: &ProgressBar,
+) -> Result<(), ParquetError> {
+    let dir = format!("{}/parquet", DATA_DIR);
+    remove_previous(&dir, T::TABLE)?;
+
+    let Some(column) = options.partition_column(T::TABLE) else {
+        fs::create_dir_all(&dir)?;
+        let rows: Vec<&T> = rows.iter().collect();
+        return write_file(&format!("{}/{}.parquet", dir, T::TABLE), &rows, options, pb);
+    };
+
+    let index = T::columns()
+        .iter()
+        .position(|c| c.name == column)
+        .ok_or_else(|| ParquetError::General(format!("{} has no column {}", T::TABLE, column)))?;
+
+    let mut partitions: BTreeMap<Option<(i32, u32)>, Vec<&T>> = BTreeMap::new();
+    for row in rows {
+        let key = match &row.values()[index] {
+            Value::Date(date) => Some((date.year(), date.month())),
+            _ => None,
+        };
+        partitions.entry(key).or_default().push(row);
+    }
+
+    for (key, rows) in partitions {
+        let partition = match key {
+            Some((year, month)) => format!("year={}/month={:02}", year, month),
+            None => "year=unknown".to_string(),
+        };
+        let dir = format!("{}/{}/{}", dir, T::TABLE, partition);
+        fs::create_dir_all(&dir)?;
+        write_file(&format!("{}/part-0.parquet", dir), &rows, options, pb)?;
+    }
+
+    Ok(())
+}
+
+// Removes what an earlier run wrote for `table`, partitioned or not, so none of its
+// months mix into this run's dataset
+fn remove_previous(dir: &str, table: &str) -> std::io::Result<()> {
+    let removed = [
+        fs::remove_dir_all(format!("{}/{}", dir, table)),
+        fs::remove_file(format!("{}/{}.parquet", dir, table)),
+    ];
+    for result in removed {
+        match result {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_file<T: TableRow>(
+    path: &str,
+    rows: &[&T],
+    options: &ParquetOptions,
+    pb: &ProgressBar,
+) -> Result<(), ParquetError> {
+    let schema = arrow::schema(&T::columns());
+    let file = File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(options.properties()))?;
+
+    for chunk in rows.chunks(options.row_group_size) {
+        writer.write(&arrow::record_batch(&schema, chunk)?)?;
+        pb.inc(chunk.len() as u64);
+    }
+    writer.close()?;
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use indicatif::ProgressBar;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use super::DATA_DIR;
+use crate::sql_generator::{ColumnKind, TableRow};
+
+// Oracle mask matching `sql_generator::DATE_FORMAT`
+const DATE_MASK: &str = "YYYY-MM-DD HH24:MI:SS";
+
+// Records end with a record separator before the newline, so the newlines of long
+// texts such as the bulário's stay inside their field
+const RECORD_END: &str = "\x1e\n";
+const RECORD_END_HEX: &str = "1E0A";
+
+// Encloses fields holding the delimiter or a quote in quotes, doubling the quotes
+fn field(text: String) -> String {
+    if text.contains(['|', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+// Writes `data/<TABLE>.ctl` and the `|`-delimited `data/<TABLE>.dat` it loads.
+// Run `sqlldr control=<TABLE>.ctl` from the data directory.
+pub(crate) fn write<T: TableRow>(rows: &[T], pb: &ProgressBar) -> std::io::Result<()> {
+    fs::create_dir_all(DATA_DIR)?;
+
+    let fields: Vec<String> = T::columns()
+        .iter()
+        .map(|column| match column.kind {
+            ColumnKind::Integer => format!("  {} INTEGER EXTERNAL({})", column.name, column.length),
+            ColumnKind::Text => format!("  {} CHAR({})", column.name, column.length),
+            ColumnKind::Date => format!("  {} DATE \"{}\"", column.name, DATE_MASK),
+        })
+        .collect();
+
+    let mut control = BufWriter::new(File::create(format!("{}/{}.ctl", DATA_DIR, T::TABLE))?);
+    writeln!(control, "OPTIONS (DIRECT=TRUE)")?;
+    writeln!(control, "LOAD DATA")?;
+    writeln!(control, "CHARACTERSET UTF8")?;
+    writeln!(
+        control,
+        "INFILE '{}.dat' \"str X'{}'\"",
+        T::TABLE,
+        RECORD_END_HEX
+    )?;
+    writeln!(control, "APPEND")?;
+    writeln!(control, "INTO TABLE {}", T::TABLE)?;
+    writeln!(
+        control,
+        "FIELDS TERMINATED BY '|' OPTIONALLY ENCLOSED BY '\"'"
+    )?;
+    writeln!(control, "TRAILING NULLCOLS")?;
+    writeln!(control, "(\n{}\n)", fields.join(",\n"))?;
+    control.flush()?;
+
+    let mut data = BufWriter::new(File::create(format!("{}/{}.dat", DATA_DIR, T::TABLE))?);
+    for row in rows {
+        let fields: Vec<String> = row
+            .values()
+            .iter()
+            .map(|value| field(value.to_text()))
+            .collect();
+        write!(data, "{}{}", fields.join("|"), RECORD_END)?;
+        pb.inc(1);
+    }
+    data.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,669 @@
+use chrono::{Local, NaiveDateTime};
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use super::collect::{group, index, rows, Geography, Row, Tables};
+use super::DATA_DIR;
+use crate::tables::consultation::HEALTH_PLAN_PAYMENT;
+use crate::tables::reference;
+
+pub(crate) const TABLES: [&str; 14] = [
+    "T_RHSTU_ESTADO",
+    "T_RHSTU_CIDADE",
+    "T_RHSTU_BAIRRO",
+    "T_RHSTU_LOGRADOURO",
+    "T_RHSTU_PACIENTE",
+    "T_RHSTU_FUNCIONARIO",
+    "T_RHSTU_MEDICO",
+    "T_RHSTU_UNID_HOSPITALAR",
+    "T_RHSTU_ENDERECO_UNIDHOSP",
+    "T_RHSTU_PLANO_SAUDE",
+    "T_RHSTU_PACIENTE_PLANO_SAUDE",
+    "T_RHSTU_CONSULTA",
+    "T_RHSTU_FORMA_PAGAMENTO",
+    "T_RHSTU_CONSULTA_FORMA_PAGTO",
+];
+
+const NAMESPACE: &str = "http://www.ans.gov.br/padroes/tiss/schemas";
+const VERSION: &str = "3.05.00";
+
+// The TISS standard caps a batch at 100 guides
+const GUIDES_PER_BATCH: usize = 100;
+
+// Terminology codes: TUSS procedures table, CRM council, "clínico" CBO and "not an accident"
+const PROCEDURE_TABLE: &str = "22";
+const OFFICE_CONSULTATION: &str = "10101012";
+const CRM_COUNCIL: &str = "06";
+const CLINICIAN_CBO: &str = "225125";
+const NOT_AN_ACCIDENT: &str = "9";
+const FIRST_CONSULTATION: &str = "1";
+const RETURN_CONSULTATION: &str = "2";
+
+// Price the plan pays for an office consultation, between R$ 90 and R$ 250
+fn consultation_value(plan: u64) -> String {
+    format!("{}.00", 90 + plan.wrapping_mul(7919) % 161)
+}
+
+// Registration number of a CRM such as "123456/SP", without the state
+fn crm_number(crm: &str) -> &str {
+    crm.split('/').next().unwrap_or(crm)
+}
+
+fn truncate(text: &str, length: usize) -> String {
+    text.chars().take(length).collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Builds an `ans:`-prefixed document while collecting the element values the
+// epilogue hash covers
+struct Document {
+    xml: String,
+    values: String,
+    open: Vec<&'static str>,
+}
+
+impl Document {
+    fn new() -> Self {
+        Document {
+            xml: String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"),
+            values: String::new(),
+            open: Vec::new(),
+        }
+    }
+
+    fn indent(&mut self) {
+        self.xml.push_str(&"  ".repeat(self.open.len()));
+    }
+
+    fn start(&mut self, name: &'static str) -> &mut Self {
+        self.indent();
+        if self.open.is_empty() {
+            self.xml
+                .push_str(&format!("<ans:{} xmlns:ans=\"{}\">\n", name, NAMESPACE));
+        } else {
+            self.xml.push_str(&format!("<ans:{}>\n", name));
+        }
+        self.open.push(name);
+        self
+    }
+
+    fn end(&mut self) -> &mut Self {
+        let name = self.open.pop().expect("no element to close");
+        self.indent();
+        self.xml.push_str(&format!("</ans:{}>\n", name));
+        self
+    }
+
+    fn leaf(&mut self, name: &str, value: &str) -> &mut Self {
+        self.indent();
+        self.xml
+            .push_str(&format!("<ans:{0}>{1}</ans:{0}>\n", name, escape(value)));
+        self.values.push_str(value);
+        self
+    }
+
+    // MD5 of every element value in document order
+    fn hash(&self) -> String {
+        Md5::digest(self.values.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// A consultation billed to a health plan
+struct Guide<'a> {
+    consultation: Row<'a>,
+    enrollment: Row<'a>,
+    patient: Row<'a>,
+    doctor: Row<'a>,
+    employee: Row<'a>,
+    hospital: Row<'a>,
+    plan: u64,
+    state_code: u64,
+    returning: bool,
+}
+
+impl Guide<'_> {
+    fn write(&self, document: &mut Document, registry: &str) {
+        let date = self
+            .consultation
+            .date("DT_HR_CONSULTA")
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+
+        document
+            .start("guiaConsulta")
+            .start("cabecalhoConsulta")
+            .leaf("registroANS", registry)
+            .leaf(
+                "numeroGuiaPrestador",
+                &self.consultation.text("ID_CONSULTA"),
+            )
+            .end()
+            .start("dadosBeneficiario")
+            .leaf(
+                "numeroCarteira",
+                &truncate(&self.enrollment.text("NR_CARTEIRA_PS"), 20),
+            )
+            .leaf("atendimentoRN", "N")
+            .leaf(
+                "nomeBeneficiario",
+                &truncate(&self.patient.text("NM_PACIENTE"), 70),
+            )
+            .end()
+            .start("contratadoExecutante")
+            .leaf(
+                "codigoPrestadorNaOperadora",
+                &self.hospital.text("ID_UNID_HOSPITAL"),
+            )
+            .leaf(
+                "nomeContratado",
+                &truncate(&self.hospital.text("NM_UNID_HOSPITALAR"), 70),
+            )
+            .leaf("CNES", &self.hospital.text("NR_CNES"))
+            .end()
+            .start("profissionalExecutante")
+            .leaf(
+                "nomeProfissional",
+                &truncate(&self.employee.text("NM_FUNC"), 70),
+            )
+            .leaf("conselhoProfissional", CRM_COUNCIL)
+            .leaf(
+                "numeroConselhoProfissional",
+                crm_number(&self.doctor.text("NR_CRM")),
+            )
+            .leaf("UF", &format!("{:02}", self.state_code))
+            .leaf("CBOS", CLINICIAN_CBO)
+            .end()
+            .leaf("indicacaoAcidente", NOT_AN_ACCIDENT)
+            .start("dadosAtendimento")
+            .leaf("dataAtendimento", &date)
+            .leaf(
+                "tipoConsulta",
+                if self.returning {
+                    RETURN_CONSULTATION
+                } else {
+                    FIRST_CONSULTATION
+                },
+            )
+            .start("procedimento")
+            .leaf("codigoTabela", PROCEDURE_TABLE)
+            .leaf("codigoProcedimento", OFFICE_CONSULTATION)
+            .leaf("valorProcedimento", &consultation_value(self.plan))
+            .end()
+            .end()
+            .end();
+    }
+}
+
+// One `ENVIO_LOTE_GUIAS` message from a hospital to a plan operator
+fn batch(
+    guides: &[Guide],
+    hospital: u64,
+    registry: &str,
+    number: usize,
+    transaction: usize,
+    sent_at: NaiveDateTime,
+) -> String {
+    let mut document = Document::new();
+    document
+        .start("mensagemTISS")
+        .start("cabecalho")
+        .start("identificacaoTransacao")
+        .leaf("tipoTransacao", "ENVIO_LOTE_GUIAS")
+        .leaf("sequencialTransacao", &transaction.to_string())
+        .leaf(
+            "dataRegistroTransacao",
+            &sent_at.format("%Y-%m-%d").to_string(),
+        )
+        .leaf(
+            "horaRegistroTransacao",
+            &sent_at.format("%H:%M:%S").to_string(),
+        )
+        .end()
+        .start("origem")
+        .start("identificacaoPrestador")
+        .leaf("codigoPrestadorNaOperadora", &hospital.to_string())
+        .end()
+        .end()
+        .start("destino")
+        .leaf("registroANS", registry)
+        .end()
+        .leaf("Padrao", VERSION)
+        .end()
+        .start("prestadorParaOperadora")
+        .start("loteGuias")
+        .leaf("numeroLote", &number.to_string())
+        .start("guiasTISS");
+    for guide in guides {
+        guide.write(&mut document, registry);
+    }
+    document.end().end().end();
+
+    let hash = document.hash();
+    document.start("epilogo").leaf("hash", &hash).end().end();
+    document.xml
+}
+
+// Guides bill the consultations paid with the health plan, so the profile must keep
+// that payment method in use
+pub(crate) fn validate() -> std::io::Result<()> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let methods =
+        reference::load("T_RHSTU_FORMA_PAGAMENTO").map_err(|error| invalid(error.to_string()))?;
+    if !methods
+        .iter()
+        .any(|method| method.name == HEALTH_PLAN_PAYMENT && method.active)
+    {
+        return Err(invalid(format!(
+            "--format tiss: the payment method {:?} is inactive in the profile, so no \
+             consultation would be billed",
+            HEALTH_PLAN_PAYMENT
+        )));
+    }
+    Ok(())
+}
+
+// A rendered batch with the operator registry and hospital it's filed under
+struct Batch {
+    registry: String,
+    hospital: u64,
+    number: usize,
+    xml: String,
+}
+
+// Every consultation with a plan payment, billed as a guia de consulta to the operator
+// of the plan the payment's enrollment (ID_PACIENTE_PS) belongs to. Payments with no
+// enrollment of the consultation's patient, or at a hospital with no known state,
+// aren't billed.
+fn batches(tables: &Tables, sent_at: NaiveDateTime) -> Vec<Batch> {
+    let geography = Geography::new(tables);
+    let patients = index(tables, "T_RHSTU_PACIENTE", "ID_PACIENTE");
+    let employees = index(tables, "T_RHSTU_FUNCIONARIO", "ID_FUNC");
+    let doctors = index(tables, "T_RHSTU_MEDICO", "ID_FUNC");
+    let hospitals = index(tables, "T_RHSTU_UNID_HOSPITALAR", "ID_UNID_HOSPITAL");
+    let hospital_addresses = group(tables, "T_RHSTU_ENDERECO_UNIDHOSP", "ID_UNID_HOSPITAL");
+    let plans = index(tables, "T_RHSTU_PLANO_SAUDE", "ID_PLANO_SAUDE");
+    let enrollments = index(tables, "T_RHSTU_PACIENTE_PLANO_SAUDE", "ID_PACIENTE_PS");
+    let consultations = index(tables, "T_RHSTU_CONSULTA", "ID_CONSULTA");
+
+    // Only consultations paid by the patient's plan are billed to it
+    let plan_payment = rows(tables, "T_RHSTU_FORMA_PAGAMENTO")
+        .find(|method| method.text("NM_FORMA_PAGTO") == HEALTH_PLAN_PAYMENT)
+        .and_then(|method| method.u64("ID_FORMA_PAGTO"));
+    let mut billed: Vec<(Row, Row)> = Vec::new();
+    let mut seen = HashSet::new();
+    for payment in rows(tables, "T_RHSTU_CONSULTA_FORMA_PAGTO") {
+        if plan_payment.is_none() || payment.u64("ID_FORMA_PAGTO") != plan_payment {
+            continue;
+        }
+        let Some(consultation) = payment
+            .u64("ID_CONSULTA")
+            .and_then(|id| consultations.get(&id))
+        else {
+            continue;
+        };
+        let Some(enrollment) = payment
+            .u64("ID_PACIENTE_PS")
+            .and_then(|id| enrollments.get(&id))
+            .filter(|enrollment| enrollment.u64("ID_PACIENTE") == consultation.u64("ID_PACIENTE"))
+        else {
+            continue;
+        };
+        if seen.insert(payment.u64("ID_CONSULTA")) {
+            billed.push((*consultation, *enrollment));
+        }
+    }
+    billed.sort_by_key(|(consultation, _)| consultation.date("DT_HR_CONSULTA"));
+
+    // Guides by the ANS registry of the plan's operator, then by the hospital billing them
+    let mut guides: BTreeMap<String, BTreeMap<u64, Vec<Guide>>> = BTreeMap::new();
+    let mut visited = HashSet::new();
+    for (consultation, enrollment) in billed {
+        let patient_id = consultation.u64("ID_PACIENTE");
+        let doctor_id = consultation.u64("ID_FUNC");
+        let hospital_id = consultation.u64("ID_UNID_HOSPITAL");
+
+        let plan_id = enrollment.u64("ID_PLANO_SAUDE");
+        let (Some(patient), Some(doctor), Some(employee), Some(hospital), Some(plan)) = (
+            patient_id.and_then(|id| patients.get(&id)),
+            doctor_id.and_then(|id| doctors.get(&id)),
+            doctor_id.and_then(|id| employees.get(&id)),
+            hospital_id.and_then(|id| hospitals.get(&id)),
+            plan_id.and_then(|id| plans.get(&id)),
+        ) else {
+            continue;
+        };
+        let Some(state_code) = hospital_id
+            .and_then(|id| hospital_addresses.get(&id))
+            .and_then(|addresses| addresses.first())
+            .and_then(|address| geography.resolve(*address).state_code)
+        else {
+            continue;
+        };
+
+        guides
+            .entry(plan.text("NR_REGISTRO_ANS"))
+            .or_default()
+            .entry(hospital_id.unwrap_or_default())
+            .or_default()
+            .push(Guide {
+                consultation,
+                enrollment,
+                patient: *patient,
+                doctor: *doctor,
+                employee: *employee,
+                hospital: *hospital,
+                plan: plan_id.unwrap_or_default(),
+                state_code,
+                returning: !visited.insert((patient_id, doctor_id)),
+            });
+    }
+
+    let mut batches = Vec::new();
+    for (registry, hospitals) in &guides {
+        for (hospital, guides) in hospitals {
+            for (i, chunk) in guides.chunks(GUIDES_PER_BATCH).enumerate() {
+                let transaction = batches.len() + 1;
+                batches.push(Batch {
+                    registry: registry.clone(),
+                    hospital: *hospital,
+                    number: i + 1,
+                    xml: batch(chunk, *hospital, registry, i + 1, transaction, sent_at),
+                });
+            }
+        }
+    }
+    batches
+}
+
+// Writes `data/tiss/<registroANS>/lote-<hospital>-<n>.xml`.
+// The batches validate against the bundled schema:
+// `xmllint --noout --schema schemas/tiss/tissLoteConsulta.xsd data/tiss/*/lote-*.xml`
+pub(crate) fn write(tables: &Tables) -> std::io::Result<()> {
+    for batch in batches(tables, Local::now().naive_local()) {
+        let dir = format!("{}/tiss/{}", DATA_DIR, batch.registry);
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            format!("{}/lote-{}-{}.xml", dir, batch.hospital, batch.number),
+            batch.xml,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::collect::Rows;
+    use crate::sql_generator::Value;
+    use chrono::NaiveDate;
+    use std::process::Command;
+
+    fn date(year: i32, month: u32, day: u32) -> Value {
+        Value::Date(
+            NaiveDate::from_ymd_opt(year, month, day)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+                .unwrap(),
+        )
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    fn table(
+        tables: &mut Tables,
+        name: &'static str,
+        columns: &[&'static str],
+        rows: Vec<Vec<Value>>,
+    ) {
+        tables.insert(
+            name,
+            Rows {
+                columns: columns.to_vec(),
+                rows,
+            },
+        );
+    }
+
+    // One patient enrolled in plan 1 during 2023, with consultations at a hospital in SP
+    // and at one with no address
+    fn fixture() -> Tables {
+        use Value::{Integer, Null};
+
+        let mut tables = Tables::new();
+        table(
+            &mut tables,
+            "T_RHSTU_ESTADO",
+            &["ID_ESTADO", "SG_ESTADO"],
+            vec![vec![Integer(35), text("SP")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_CIDADE",
+            &["ID_CIDADE", "ID_ESTADO", "NM_CIDADE"],
+            vec![vec![Integer(3550308), Integer(35), text("São Paulo")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_BAIRRO",
+            &["ID_BAIRRO", "ID_CIDADE", "NM_BAIRRO"],
+            vec![vec![Integer(1), Integer(3550308), text("Sé")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_LOGRADOURO",
+            &["ID_LOGRADOURO", "ID_BAIRRO", "NM_LOGRADOURO", "NR_CEP"],
+            vec![vec![
+                Integer(1),
+                Integer(1),
+                text("Praça da Sé"),
+                text("01001000"),
+            ]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_PACIENTE",
+            &["ID_PACIENTE", "NM_PACIENTE"],
+            vec![vec![Integer(1), text("Maria <Souza> & Filhos")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_FUNCIONARIO",
+            &["ID_FUNC", "NM_FUNC"],
+            vec![vec![Integer(7), text("João Pereira")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_MEDICO",
+            &["ID_FUNC", "NR_CRM"],
+            vec![vec![Integer(7), text("123456/SP")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_UNID_HOSPITALAR",
+            &["ID_UNID_HOSPITAL", "NM_UNID_HOSPITALAR", "NR_CNES"],
+            vec![
+                vec![Integer(1), text("Hospital Central"), text("2077485")],
+                vec![Integer(2), text("Hospital Sem Endereço"), text("2078015")],
+            ],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_ENDERECO_UNIDHOSP",
+            &[
+                "ID_UNID_HOSPITAL",
+                "ID_LOGRADOURO",
+                "NR_LOGRADOURO",
+                "DS_COMPLEMENTO_NUMERO",
+            ],
+            vec![vec![Integer(1), Integer(1), Integer(100), Null]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_PLANO_SAUDE",
+            &["ID_PLANO_SAUDE", "NR_REGISTRO_ANS"],
+            vec![vec![Integer(1), text("123456")]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_PACIENTE_PLANO_SAUDE",
+            &[
+                "ID_PACIENTE_PS",
+                "ID_PACIENTE",
+                "ID_PLANO_SAUDE",
+                "NR_CARTEIRA_PS",
+                "DT_INICIO",
+                "DT_FIM",
+            ],
+            vec![vec![
+                Integer(5),
+                Integer(1),
+                Integer(1),
+                text("0001234567"),
+                date(2023, 1, 1),
+                date(2023, 12, 31),
+            ]],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_CONSULTA",
+            &[
+                "ID_CONSULTA",
+                "ID_PACIENTE",
+                "ID_FUNC",
+                "ID_UNID_HOSPITAL",
+                "DT_HR_CONSULTA",
+            ],
+            vec![
+                vec![
+                    Integer(1),
+                    Integer(1),
+                    Integer(7),
+                    Integer(1),
+                    date(2023, 3, 10),
+                ],
+                vec![
+                    Integer(2),
+                    Integer(1),
+                    Integer(7),
+                    Integer(1),
+                    date(2023, 4, 12),
+                ],
+                // After the enrollment ended
+                vec![
+                    Integer(3),
+                    Integer(1),
+                    Integer(7),
+                    Integer(1),
+                    date(2024, 2, 1),
+                ],
+                // At a hospital with no known state
+                vec![
+                    Integer(4),
+                    Integer(1),
+                    Integer(7),
+                    Integer(2),
+                    date(2023, 5, 2),
+                ],
+            ],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_FORMA_PAGAMENTO",
+            &["ID_FORMA_PAGTO", "NM_FORMA_PAGTO"],
+            vec![
+                vec![Integer(1), text("Dinheiro")],
+                vec![Integer(2), text(HEALTH_PLAN_PAYMENT)],
+            ],
+        );
+        table(
+            &mut tables,
+            "T_RHSTU_CONSULTA_FORMA_PAGTO",
+            &["ID_CONSULTA", "ID_PACIENTE_PS", "ID_FORMA_PAGTO"],
+            vec![
+                vec![Integer(1), Integer(5), Integer(2)],
+                vec![Integer(2), Integer(5), Integer(2)],
+                // No enrollment covered it
+                vec![Integer(3), Null, Integer(2)],
+                vec![Integer(4), Integer(5), Integer(2)],
+            ],
+        );
+        tables
+    }
+
+    fn sent_at() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn bills_only_covered_consultations_at_known_states() {
+        let batches = batches(&fixture(), sent_at());
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!((batch.registry.as_str(), batch.hospital), ("123456", 1));
+        let guides: Vec<&str> = batch
+            .xml
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("<ans:numeroGuiaPrestador>"))
+            .collect();
+        assert_eq!(
+            guides,
+            ["1</ans:numeroGuiaPrestador>", "2</ans:numeroGuiaPrestador>"]
+        );
+        assert!(batch.xml.contains("<ans:UF>35</ans:UF>"));
+        assert!(batch.xml.contains("<ans:CNES>2077485</ans:CNES>"));
+        assert!(batch
+            .xml
+            .contains("<ans:numeroCarteira>0001234567</ans:numeroCarteira>"));
+        assert!(batch.xml.contains("<ans:tipoConsulta>2</ans:tipoConsulta>"));
+    }
+
+    #[test]
+    fn batches_validate_against_the_schema() {
+        if Command::new("xmllint").arg("--version").output().is_err() {
+            eprintln!("skipping the TISS schema check: xmllint (libxml2) is not on PATH");
+            return;
+        }
+        let schema = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/schemas/tiss/tissLoteConsulta.xsd"
+        );
+        let dir = std::env::temp_dir().join(format!("tiss-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for batch in batches(&fixture(), sent_at()) {
+            let path = dir.join(format!("lote-{}-{}.xml", batch.hospital, batch.number));
+            fs::write(&path, &batch.xml).unwrap();
+
+            let output = Command::new("xmllint")
+                .args(["--noout", "--schema", schema])
+                .arg(&path)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}\n{}",
+                batch.xml,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
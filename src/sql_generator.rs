@@ -1,17 +1,152 @@
+use chrono::NaiveDateTime;
 use indicatif::ProgressBar;
 use std::fs;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 
+use crate::common::parse_to_date;
+use crate::output::{self, copy::CopyFormat, Format};
+
 // Define the SqlInsertable trait
 pub(crate) trait SqlInsertable {
     fn to_insert_sql(&self, table_name: &str) -> String;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnKind {
+    Integer,
+    Text,
+    Date,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Column {
+    pub(crate) name: &'static str,
+    pub(crate) kind: ColumnKind,
+    // Maximum rendered length, overridden with `FIELD: String [n]` in the table macro
+    pub(crate) length: usize,
+}
+
+// Typed value of a single column, shared by every output format
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Integer(u64),
+    Text(String),
+    Date(NaiveDateTime),
+}
+
+// Column metadata and typed values of a generated row
+pub(crate) trait TableRow {
+    const TABLE: &'static str;
+    fn columns() -> Vec<Column>;
+    fn values(&self) -> Vec<Value>;
+}
+
+pub(crate) trait ColumnType: Clone + std::fmt::Debug {
+    const KIND: ColumnKind;
+    fn to_value(&self) -> Value;
+
+    // Literal of the field in an INSERT, before its double quotes become single ones
+    fn to_sql(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    // The field cut to what `column` can hold
+    fn fit(&self, _column: &Column) -> Self {
+        self.clone()
+    }
+}
+
+impl ColumnType for u64 {
+    const KIND: ColumnKind = ColumnKind::Integer;
+
+    fn to_value(&self) -> Value {
+        Value::Integer(*self)
+    }
+}
+
+// A reference that may be missing, such as the enrollment of an uninsured patient
+impl ColumnType for Option<u64> {
+    const KIND: ColumnKind = ColumnKind::Integer;
+
+    fn to_value(&self) -> Value {
+        self.map_or(Value::Null, Value::Integer)
+    }
+
+    fn to_sql(&self) -> String {
+        self.map_or("NULL".to_string(), |number| number.to_string())
+    }
+}
+
+impl ColumnType for String {
+    const KIND: ColumnKind = ColumnKind::Text;
+
+    fn to_value(&self) -> Value {
+        match parse_to_date(self) {
+            Some(date) => Value::Date(date),
+            None => Value::Text(self.clone()),
+        }
+    }
+
+    // Text longer than its declared length keeps its first characters
+    fn fit(&self, column: &Column) -> Self {
+        match self.char_indices().nth(column.length) {
+            Some((end, _)) if column.kind == ColumnKind::Text => self[..end].to_string(),
+            _ => self.clone(),
+        }
+    }
+}
+
+// Value of `column`. An empty `DT_` column, such as the discharge of a stay still
+// running, is a missing date rather than text.
+pub(crate) fn column_value<T: ColumnType>(column: &Column, field: &T) -> Value {
+    match field.fit(column).to_value() {
+        Value::Text(text) if text.is_empty() && column.kind == ColumnKind::Date => Value::Null,
+        value => value,
+    }
+}
+
+impl Column {
+    // `DT_` columns hold TO_DATE literals in their String fields
+    pub(crate) fn new(name: &'static str, kind: ColumnKind) -> Self {
+        let kind = if name.starts_with("DT_") {
+            ColumnKind::Date
+        } else {
+            kind
+        };
+        let length = match kind {
+            ColumnKind::Integer => 20,
+            ColumnKind::Text => 255,
+            ColumnKind::Date => 19,
+        };
+        Column { name, kind, length }
+    }
+
+    pub(crate) fn with_length(self, length: usize) -> Self {
+        Column { length, ..self }
+    }
+}
+
+impl Value {
+    // Plain-text rendering used by the delimited formats
+    pub(crate) fn to_text(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Integer(number) => number.to_string(),
+            Value::Text(text) => text.clone(),
+            Value::Date(date) => date.format(DATE_FORMAT).to_string(),
+        }
+    }
+}
+
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
 #[macro_export]
+#[allow(clippy::crate_in_macro_def)]
 macro_rules! define_and_impl_sql_insertable {
-    ($($struct_name:ident { $($vis:vis $field_name:ident : $field_type:ty),* }),*) => {
+    ($($struct_name:ident { $($vis:vis $field_name:ident : $field_type:ty $([$length:expr])?),* }),*) => {
         $(
             #[derive(Debug, Clone)]
             #[allow(non_camel_case_types, non_snake_case)]
@@ -28,7 +163,13 @@ macro_rules! define_and_impl_sql_insertable {
                         fields.push_str(stringify!($field_name));
                         fields.push(',');
 
-                        let value = format!("{:?}", self.$field_name);
+                        let column = $crate::sql_generator::Column::new(
+                            stringify!($field_name),
+                            <$field_type as $crate::sql_generator::ColumnType>::KIND,
+                        )$(.with_length($length))?;
+                        let value = $crate::sql_generator::ColumnType::to_sql(
+                            &$crate::sql_generator::ColumnType::fit(&self.$field_name, &column),
+                        );
                         if value.starts_with("\"TO_DATE") {
                             values.push_str(&value.replace("\"", ""));
                         } else {
@@ -48,6 +189,31 @@ macro_rules! define_and_impl_sql_insertable {
                     )
                 }
             }
+
+            impl $crate::sql_generator::TableRow for $struct_name {
+                const TABLE: &'static str = stringify!($struct_name);
+
+                fn columns() -> Vec<$crate::sql_generator::Column> {
+                    vec![$(
+                        $crate::sql_generator::Column::new(
+                            stringify!($field_name),
+                            <$field_type as $crate::sql_generator::ColumnType>::KIND,
+                        )$(.with_length($length))?
+                    ),*]
+                }
+
+                fn values(&self) -> Vec<$crate::sql_generator::Value> {
+                    vec![$(
+                        $crate::sql_generator::column_value(
+                            &$crate::sql_generator::Column::new(
+                                stringify!($field_name),
+                                <$field_type as $crate::sql_generator::ColumnType>::KIND,
+                            )$(.with_length($length))?,
+                            &self.$field_name,
+                        )
+                    ),*]
+                }
+            }
         )*
     };
 }
@@ -66,7 +232,47 @@ impl<T> SqlGenerator<T> {
         full_name.split("::").last().unwrap_or(full_name)
     }
 
+    // Writes the rows in every output format selected on the command line
     pub(crate) fn write_to_file(&self, pb: &ProgressBar) -> std::io::Result<()>
+    where
+        T: SqlInsertable + TableRow,
+    {
+        let output = output::output();
+        let mut collected = false;
+        for (i, format) in output.formats.iter().enumerate() {
+            if i > 0 {
+                pb.inc_length(self.data.len() as u64);
+            }
+            match format {
+                Format::Sql => self.write_sql(pb)?,
+                Format::Csv => output::csv::write(&self.data, pb)?,
+                Format::Sqlldr => output::sqlldr::write(&self.data, pb)?,
+                Format::Copy => output::copy::write(&self.data, CopyFormat::Text, pb)?,
+                Format::CopyCsv => output::copy::write(&self.data, CopyFormat::Csv, pb)?,
+                Format::Parquet => output::parquet::write(&self.data, &output.parquet, pb)
+                    .map_err(std::io::Error::other)?,
+                Format::Arrow => {
+                    output::arrow::write(&self.data, pb).map_err(std::io::Error::other)?
+                }
+                Format::Jsonl => output::jsonl::write(&self.data, pb)?,
+                Format::Load => {
+                    crate::load::send(&self.data);
+                    pb.inc(self.data.len() as u64);
+                }
+                Format::PatientDocuments | Format::Fhir | Format::Hl7 | Format::Tiss => {
+                    if !collected {
+                        output::collect::collect(&self.data);
+                        collected = true;
+                    }
+                    pb.inc(self.data.len() as u64);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_sql(&self, pb: &ProgressBar) -> std::io::Result<()>
     where
         T: SqlInsertable,
     {
@@ -85,12 +291,82 @@ impl<T> SqlGenerator<T> {
                 writer.write_all(b"\n")?;
                 pb.inc(1);
             }
+            #[allow(clippy::useless_format)]
             writer.write_all(format!("SELECT * FROM dual;\n").as_bytes())?;
+            #[allow(clippy::useless_format)]
             writer.write_all(format!("commit;\n").as_bytes())?;
             writer.write_all(format!("select count(*) from {};\n", table_name).as_bytes())?;
         }
 
-
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::define_and_impl_sql_insertable!(T_RHSTU_MEDICAMENTO {
+        ID_MEDICAMENTO: u64,
+        NM_MEDICAMENTO: String[50],
+        DS_DETALHADA_MEDICAMENTO: String[4000],
+        DT_CADASTRO: String,
+        NM_USUARIO: String[100]
+    });
+
+    crate::define_and_impl_sql_insertable!(T_RHSTU_CONSULTA_FORMA_PAGTO {
+        ID_CONSULTA_FORMA_PAGTO: u64,
+        ID_PACIENTE_PS: Option<u64>
+    });
+
+    #[test]
+    fn truncates_text_to_the_declared_length() {
+        let name = "Ácido acetilsalicílico ".repeat(5);
+        let medicine = T_RHSTU_MEDICAMENTO {
+            ID_MEDICAMENTO: 1,
+            NM_MEDICAMENTO: name.clone(),
+            DS_DETALHADA_MEDICAMENTO: "ç".repeat(4500),
+            DT_CADASTRO: "TO_DATE('2023-11-02 11:27:08', 'YYYY-MM-DD HH24:MI:SS')".to_string(),
+            NM_USUARIO: "Maria".to_string(),
+        };
+
+        let values = medicine.values();
+        assert_eq!(
+            values[1],
+            Value::Text(name.chars().take(50).collect::<String>())
+        );
+        assert_eq!(values[2], Value::Text("ç".repeat(4000)));
+        assert!(matches!(values[3], Value::Date(_)));
+        assert_eq!(values[4], Value::Text("Maria".to_string()));
+
+        let sql = medicine.to_insert_sql(T_RHSTU_MEDICAMENTO::TABLE);
+        assert!(sql.contains(&format!("'{}'", "ç".repeat(4000))));
+        assert!(!sql.contains(&"ç".repeat(4001)));
+        assert!(sql.contains("TO_DATE('2023-11-02 11:27:08', 'YYYY-MM-DD HH24:MI:SS')"));
+    }
+
+    #[test]
+    fn writes_missing_references_as_null() {
+        let insured = T_RHSTU_CONSULTA_FORMA_PAGTO {
+            ID_CONSULTA_FORMA_PAGTO: 1,
+            ID_PACIENTE_PS: Some(5),
+        };
+        let uninsured = T_RHSTU_CONSULTA_FORMA_PAGTO {
+            ID_CONSULTA_FORMA_PAGTO: 2,
+            ID_PACIENTE_PS: None,
+        };
+
+        assert_eq!(
+            T_RHSTU_CONSULTA_FORMA_PAGTO::columns()[1].kind,
+            ColumnKind::Integer
+        );
+        assert_eq!(insured.values()[1], Value::Integer(5));
+        assert_eq!(uninsured.values()[1], Value::Null);
+        assert!(insured
+            .to_insert_sql(T_RHSTU_CONSULTA_FORMA_PAGTO::TABLE)
+            .ends_with("VALUES (1,5)"));
+        assert!(uninsured
+            .to_insert_sql(T_RHSTU_CONSULTA_FORMA_PAGTO::TABLE)
+            .ends_with("VALUES (2,NULL)"));
+    }
+}
@@ -0,0 +1,316 @@
+use chrono::{Duration, NaiveDateTime};
+use fake::{faker::name::en::Name, Fake};
+use indicatif::{MultiProgress, ProgressBar};
+use rand::{seq::SliceRandom, Rng};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{
+    common::{current_timestamp, parse_to_date, to_date, ProgressBarHelper},
+    config::profile,
+    define_and_impl_sql_insertable,
+    sql_generator::SqlGenerator,
+    tables::{
+        geography::{T_RHSTU_BAIRRO, T_RHSTU_LOGRADOURO},
+        hospital::T_RHSTU_MOTORISTA,
+        street::building_number,
+    },
+};
+
+define_and_impl_sql_insertable!(
+    T_RHSTU_AMBULANCIA {
+        pub(crate) ID_AMBULANCIA: u64,
+        pub(crate) ID_UNID_HOSPITAL: u64,
+        pub(crate) NR_PLACA: String [7],
+        pub(crate) DS_TIPO_AMBULANCIA: String [40],
+        pub(crate) DS_MODELO: String [60],
+        pub(crate) NR_ANO_FABRICACAO: u64,
+        pub(crate) NM_CATEGORIA_CNH: String [2],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_VIAGEM_AMBULANCIA {
+        pub(crate) ID_VIAGEM: u64,
+        pub(crate) ID_AMBULANCIA: u64,
+        pub(crate) ID_FUNC: u64,
+        pub(crate) ID_LOGRADOURO_ORIGEM: u64,
+        pub(crate) NR_LOGRADOURO_ORIGEM: u64,
+        pub(crate) ID_UNID_HOSPITAL_DESTINO: u64,
+        pub(crate) DT_ACIONAMENTO: String,
+        pub(crate) DT_CHEGADA: String,
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    }
+);
+
+// Model and the CNH category required to drive it
+type Vehicle = (&'static str, &'static str);
+
+// Ambulance types of Portaria MS 2048/2002, with vehicles and the CNH category they
+// require (vans up to 3.5 t need B, heavier chassis need C)
+const TYPES: [(&str, &str, &[Vehicle]); 4] = [
+    (
+        "A",
+        "Tipo A - Transporte",
+        &[
+            ("Fiat Doblò", "B"),
+            ("Renault Kangoo", "B"),
+            ("Fiat Fiorino", "B"),
+        ],
+    ),
+    (
+        "B",
+        "Tipo B - Suporte Básico",
+        &[
+            ("Fiat Ducato", "B"),
+            ("Renault Master", "B"),
+            ("Peugeot Boxer", "B"),
+        ],
+    ),
+    (
+        "C",
+        "Tipo C - Resgate",
+        &[
+            ("Mercedes-Benz Sprinter 416", "C"),
+            ("Iveco Daily 55C", "C"),
+        ],
+    ),
+    (
+        "D",
+        "Tipo D - Suporte Avançado",
+        &[
+            ("Mercedes-Benz Sprinter 516", "C"),
+            ("Iveco Daily 70C", "C"),
+        ],
+    ),
+];
+
+// Share of trips taking the patient to a hospital other than the ambulance's own
+const TRANSFER_SHARE: f64 = 0.2;
+
+// Dispatch to arrival at the destination: a minimum plus an exponential share
+const MIN_TRIP_MINUTES: i64 = 15;
+const MEAN_EXTRA_MINUTES: f64 = 30.0;
+
+// Time to clean and restock an ambulance before its next dispatch
+const TURNAROUND_MINUTES: i64 = 20;
+
+// Attempts to find an eligible driver who is not on another trip
+const DRIVER_ATTEMPTS: usize = 10;
+
+// Whether a CNH of `category` (e.g. "D" or "AE") may drive a vehicle requiring `required`.
+// Categories B to E each include the ones below them; A only covers motorcycles.
+pub(crate) fn permits(category: &str, required: &str) -> bool {
+    let Some(required) = required.chars().next() else {
+        return true;
+    };
+    if required == 'A' {
+        return category.contains('A');
+    }
+    category
+        .chars()
+        .filter(|letter| *letter != 'A')
+        .max()
+        .is_some_and(|highest| highest >= required)
+}
+
+// Mercosul plate, e.g. "BRA2E19"
+fn plate<R: Rng + ?Sized>(rng: &mut R) -> String {
+    let letter = |rng: &mut R| (b'A' + rng.gen_range(0..26)) as char;
+    let digit = |rng: &mut R| (b'0' + rng.gen_range(0..10)) as char;
+    [
+        letter(rng),
+        letter(rng),
+        letter(rng),
+        digit(rng),
+        letter(rng),
+        digit(rng),
+        digit(rng),
+    ]
+    .iter()
+    .collect()
+}
+
+pub(crate) fn generate_ambulances(
+    total: usize,
+    total_hospitals: usize,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_AMBULANCIA> {
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Ambulances:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_AMBULANCIA");
+    let mut ambulances = Vec::with_capacity(total);
+
+    for i in 0..total {
+        let kind = table.pick("DS_TIPO_AMBULANCIA", &mut rng);
+        let (_, description, vehicles) = TYPES
+            .iter()
+            .find(|(code, _, _)| *code == kind)
+            .unwrap_or_else(|| panic!("unknown ambulance type {}", kind));
+        let (model, category) = vehicles.choose(&mut rng).unwrap();
+
+        ambulances.push(T_RHSTU_AMBULANCIA {
+            ID_AMBULANCIA: i as u64,
+            ID_UNID_HOSPITAL: (i % total_hospitals.max(1)) as u64,
+            NR_PLACA: plate(&mut rng),
+            DS_TIPO_AMBULANCIA: description.to_string(),
+            DS_MODELO: model.to_string(),
+            NR_ANO_FABRICACAO: rng.gen_range(2010..=2023),
+            NM_CATEGORIA_CNH: category.to_string(),
+            DT_CADASTRO: current_timestamp(),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let generator = SqlGenerator::new(ambulances.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    ambulances
+}
+
+// Spreads the trips evenly over the ambulances and runs each ambulance's trips one
+// after the other. Patients are picked up in the city of the destination hospital,
+// by a driver whose CNH covers the vehicle and is still valid on the trip date.
+pub(crate) async fn generate_ambulance_trips(
+    total: usize,
+    ambulances: Vec<T_RHSTU_AMBULANCIA>,
+    drivers: Vec<T_RHSTU_MOTORISTA>,
+    streets: Vec<T_RHSTU_LOGRADOURO>,
+    neighborhoods: Vec<T_RHSTU_BAIRRO>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> usize {
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_VIAGEM_AMBULANCIA");
+    let total_hospitals = profile().rows("T_RHSTU_UNID_HOSPITALAR");
+    let (start, end) = table.date_window("DT_ACIONAMENTO").bounds();
+
+    // Streets of every city; hospital `h` stands on street `h`, as in its address row
+    let cities: HashMap<u64, u64> = neighborhoods
+        .iter()
+        .map(|neighborhood| (neighborhood.ID_BAIRRO, neighborhood.ID_CIDADE))
+        .collect();
+    let mut city_streets: HashMap<u64, Vec<u64>> = HashMap::new();
+    for street in &streets {
+        if let Some(city) = cities.get(&street.ID_BAIRRO) {
+            city_streets
+                .entry(*city)
+                .or_default()
+                .push(street.ID_LOGRADOURO);
+        }
+    }
+    let hospital_city = |hospital: u64| {
+        streets
+            .get(hospital as usize)
+            .and_then(|street| cities.get(&street.ID_BAIRRO))
+    };
+
+    // (ambulance, dispatch, arrival) of every trip
+    let mut schedule: Vec<(usize, NaiveDateTime, NaiveDateTime)> = Vec::with_capacity(total);
+    for index in 0..ambulances.len() {
+        let count = total / ambulances.len() + usize::from(index < total % ambulances.len());
+        let window = (end - start).num_seconds();
+        let mut dispatches: Vec<NaiveDateTime> = (0..count)
+            .map(|_| start + Duration::seconds(rng.gen_range(0..=window)))
+            .collect();
+        dispatches.sort();
+
+        let mut free = start;
+        for dispatch in dispatches {
+            let dispatch = dispatch.max(free);
+            let extra = -(1.0 - rng.gen::<f64>()).ln() * MEAN_EXTRA_MINUTES;
+            let arrival = dispatch + Duration::minutes(MIN_TRIP_MINUTES + extra as i64);
+            if arrival > end {
+                break;
+            }
+            schedule.push((index, dispatch, arrival));
+            free = arrival + Duration::minutes(TURNAROUND_MINUTES);
+        }
+    }
+    schedule.sort_by_key(|(_, dispatch, _)| *dispatch);
+
+    // Drivers able to drive each required category, with their CNH expiry
+    let mut eligible: HashMap<&str, Vec<(u64, NaiveDateTime)>> = HashMap::new();
+    for ambulance in &ambulances {
+        eligible
+            .entry(ambulance.NM_CATEGORIA_CNH.as_str())
+            .or_insert_with(|| {
+                drivers
+                    .iter()
+                    .filter(|driver| permits(&driver.NM_CATEGORIA_CNH, &ambulance.NM_CATEGORIA_CNH))
+                    .filter_map(|driver| {
+                        Some((driver.ID_FUNC, parse_to_date(&driver.DT_VALIDADE_CNH)?))
+                    })
+                    .collect()
+            });
+    }
+
+    let pb_helper = ProgressBarHelper::new(m, schedule.len() * 2, "Ambulance Trips:".to_string());
+    let pb = &pb_helper.pb;
+
+    // Arrival of each driver's current trip
+    let mut busy: HashMap<u64, NaiveDateTime> = HashMap::new();
+    let mut trips = Vec::with_capacity(schedule.len());
+
+    for (index, dispatch, arrival) in schedule {
+        let ambulance = &ambulances[index];
+
+        let candidates = &eligible[ambulance.NM_CATEGORIA_CNH.as_str()];
+        let driver = (0..DRIVER_ATTEMPTS)
+            .filter_map(|_| candidates.choose(&mut rng))
+            .find(|(driver, expiry)| {
+                *expiry >= arrival && busy.get(driver).is_none_or(|until| *until <= dispatch)
+            })
+            .map(|(driver, _)| *driver);
+        let Some(driver) = driver else {
+            pb.inc(1);
+            continue;
+        };
+        busy.insert(driver, arrival);
+
+        let destination = if total_hospitals > 1 && rng.gen_bool(TRANSFER_SHARE) {
+            rng.gen_range(0..total_hospitals) as u64
+        } else {
+            ambulance.ID_UNID_HOSPITAL
+        };
+        let origin = hospital_city(destination)
+            .and_then(|city| city_streets.get(city))
+            .and_then(|ids| ids.choose(&mut rng).copied())
+            .or_else(|| streets.choose(&mut rng).map(|street| street.ID_LOGRADOURO))
+            .unwrap_or(0);
+
+        trips.push(T_RHSTU_VIAGEM_AMBULANCIA {
+            ID_VIAGEM: trips.len() as u64,
+            ID_AMBULANCIA: ambulance.ID_AMBULANCIA,
+            ID_FUNC: driver,
+            ID_LOGRADOURO_ORIGEM: origin,
+            NR_LOGRADOURO_ORIGEM: streets
+                .get(origin as usize)
+                .map_or(1, |street| building_number(street, &mut rng)),
+            ID_UNID_HOSPITAL_DESTINO: destination,
+            DT_ACIONAMENTO: to_date(dispatch),
+            DT_CHEGADA: to_date(arrival),
+            DT_CADASTRO: to_date(dispatch),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let len = trips.len();
+    let generator = SqlGenerator::new(trips);
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    len
+}
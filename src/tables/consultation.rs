@@ -1,69 +1,105 @@
 use std::sync::Arc;
 
-use fake::{faker::name::en::Name, Fake};
 use indicatif::{MultiProgress, ProgressBar};
 use rand::{seq::SliceRandom, Rng};
 
 use crate::{
-    common::{current_timestamp, ProgressBarHelper},
+    common::{current_timestamp, parse_to_date, ProgressBarHelper},
+    config::profile,
     define_and_impl_sql_insertable,
     sql_generator::SqlGenerator,
+    tables::{health_plan::Coverage, hospital::Staff, inpatient::Deaths, reference},
 };
 // - T_RHSTU_CONSULTA - "ID_UNID_HOSPITAL","ID_CONSULTA","ID_PACIENTE","ID_FUNC","DT_HR_CONSULTA","NR_CONSULTORIO","DT_CADASTRO","NM_USUARIO"
 
 define_and_impl_sql_insertable!(
     T_RHSTU_CONSULTA {
-        ID_UNID_HOSPITAL: u64,
-        ID_CONSULTA: u64,
-        ID_PACIENTE: u64,
-        ID_FUNC: u64,
-        DT_HR_CONSULTA: String,
-        NR_CONSULTORIO: String,
-        DT_CADASTRO: String,
-        NM_USUARIO: String
+        pub(crate) ID_UNID_HOSPITAL: u64,
+        pub(crate) ID_CONSULTA: u64,
+        pub(crate) ID_PACIENTE: u64,
+        pub(crate) ID_FUNC: u64,
+        pub(crate) DT_HR_CONSULTA: String,
+        pub(crate) NR_CONSULTORIO: String [5],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_FORMA_PAGAMENTO {
         ID_FORMA_PAGTO: u64,
-        NM_FORMA_PAGTO: String,
-        DS_FORMA_PAGTO: String,
-        ST_FORMA_PAGTO: String,
+        NM_FORMA_PAGTO: String [50],
+        DS_FORMA_PAGTO: String [200],
+        ST_FORMA_PAGTO: String [1],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_CONSULTA_FORMA_PAGTO {
         ID_CONSULTA_FORMA_PAGTO: u64,
         ID_UNID_HOSPITAL: u64,
         ID_CONSULTA: u64,
-        ID_PACIENTE_PS: u64,
+        ID_PACIENTE_PS: Option<u64>,
         ID_FORMA_PAGTO: u64,
         DT_PAGTO_CONSULTA: String,
-        ST_PAGTO_CONSULTA: String,
+        ST_PAGTO_CONSULTA: String [1],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     }
 );
 
+// Hospital and date draws for a consultation before it's left out for lack of a doctor
+const DOCTOR_ATTEMPTS: usize = 10;
+
+// Patient draws for a consultation before it's left out for lack of one still alive
+const PATIENT_ATTEMPTS: usize = 10;
+
 pub(crate) async fn generate_consultations(
     total: usize,
     total_hospitals: usize,
     total_patients: usize,
+    doctors: Staff,
+    deaths: Arc<Deaths>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> Vec<T_RHSTU_CONSULTA> {
     // println!("Generating consultations...");
     let mut consultations: Vec<T_RHSTU_CONSULTA> = Vec::new();
     let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_CONSULTA");
 
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Consultations:".to_string());
     let pb = &pb_helper.pb;
 
-    for i in 0..total {
+    let mut unstaffed = 0;
+    let mut unattended = 0;
+    for _ in 0..total {
+        // Seen by a doctor allocated to the hospital on that date, redrawing both when
+        // none is
+        let drawn = (0..DOCTOR_ATTEMPTS).find_map(|_| {
+            let hospital = rng.gen_range(0..total_hospitals.max(1)) as u64;
+            let date = table.date("DT_HR_CONSULTA", &mut rng);
+            let doctor = doctors.pick(hospital, parse_to_date(&date)?, &mut rng)?;
+            Some((hospital, date, doctor))
+        });
+        let Some((hospital, date, doctor)) = drawn else {
+            unstaffed += 1;
+            pb.inc(1);
+            continue;
+        };
+        // Of a patient still alive on that date
+        let at = parse_to_date(&date).unwrap_or_default();
+        let patient = (0..PATIENT_ATTEMPTS)
+            .map(|_| rng.gen_range(1..total_patients) as u64)
+            .find(|&patient| deaths.alive(patient, at));
+        let Some(patient) = patient else {
+            unattended += 1;
+            pb.inc(1);
+            continue;
+        };
+
         let consultation = T_RHSTU_CONSULTA {
-            ID_UNID_HOSPITAL: rng.gen_range(1..total_hospitals) as u64,
-            ID_CONSULTA: i as u64,
-            ID_PACIENTE: rng.gen_range(1..total_patients) as u64,
-            ID_FUNC: rng.gen_range(1..100) as u64,
-            DT_HR_CONSULTA: current_timestamp(),
+            ID_UNID_HOSPITAL: hospital,
+            ID_CONSULTA: consultations.len() as u64,
+            ID_PACIENTE: patient,
+            ID_FUNC: doctor,
+            DT_HR_CONSULTA: date,
             NR_CONSULTORIO: rng.gen_range(1..100).to_string(),
             DT_CADASTRO: current_timestamp(),
             NM_USUARIO: "1".to_string(),
@@ -73,6 +109,20 @@ pub(crate) async fn generate_consultations(
         pb.inc(1); // Increment the progress bar
         main_pb.inc(1);
     }
+    if unstaffed > 0 {
+        pb.println(format!(
+            "Consultations: {} of {} not generated, no doctor was allocated to the hospital \
+             and date drawn in {} attempts",
+            unstaffed, total, DOCTOR_ATTEMPTS
+        ));
+    }
+    if unattended > 0 {
+        pb.println(format!(
+            "Consultations: {} of {} not generated, no patient alive on the date was drawn \
+             in {} attempts",
+            unattended, total, PATIENT_ATTEMPTS
+        ));
+    }
 
     let generator = SqlGenerator::new(consultations.clone());
     let _ = generator.write_to_file(pb);
@@ -81,70 +131,94 @@ pub(crate) async fn generate_consultations(
     consultations
 }
 
-pub(crate) async fn generate_payment_methods(
-    total: usize,
+// Name of the payment method billed to the patient's health plan
+pub(crate) const HEALTH_PLAN_PAYMENT: &str = "Plano de Saúde";
+
+// Payment methods come from the reference list, inactive ones flagged "I"
+pub(crate) fn generate_payment_methods(
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Vec<T_RHSTU_FORMA_PAGAMENTO> {
-    // println!("Generating payment methods...");
-    let mut payment_methods: Vec<T_RHSTU_FORMA_PAGAMENTO> = Vec::new();
+) -> Result<Vec<T_RHSTU_FORMA_PAGAMENTO>, anyhow::Error> {
+    let references = reference::load("T_RHSTU_FORMA_PAGAMENTO")?;
 
+    let total = references.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Payment Methods:".to_string());
     let pb = &pb_helper.pb;
 
-    for i in 0..total {
-        let payment_method = T_RHSTU_FORMA_PAGAMENTO {
-            ID_FORMA_PAGTO: i as u64,
-            NM_FORMA_PAGTO: Name().fake(),
-            DS_FORMA_PAGTO: Name().fake(),
-            ST_FORMA_PAGTO: ["A", "C", "P"]
-                .choose(&mut rand::thread_rng())
-                .unwrap()
-                .to_string(),
-            DT_CADASTRO: current_timestamp(),
-            NM_USUARIO: "1".to_string(),
-        };
-
-        payment_methods.push(payment_method);
-        pb.inc(1); // Increment the progress bar
-        main_pb.inc(1);
-    }
+    let payment_methods: Vec<T_RHSTU_FORMA_PAGAMENTO> = references
+        .into_iter()
+        .map(|reference| {
+            pb.inc(1); // Increment the progress bar
+            main_pb.inc(1);
+
+            T_RHSTU_FORMA_PAGAMENTO {
+                ID_FORMA_PAGTO: reference.id,
+                NM_FORMA_PAGTO: reference.name,
+                DS_FORMA_PAGTO: reference.description,
+                ST_FORMA_PAGTO: if reference.active { "A" } else { "I" }.to_string(),
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: "1".to_string(),
+            }
+        })
+        .collect();
 
     let generator = SqlGenerator::new(payment_methods.clone());
     let _ = generator.write_to_file(pb);
 
     pb_helper.finish();
-    payment_methods
+    Ok(payment_methods)
 }
 
+// Consultations are paid with one of the payment methods still in use. The health
+// plan is only an option for patients enrolled in one on the consultation's date, and
+// ID_PACIENTE_PS is that enrollment, or null for uninsured patients.
 pub(crate) async fn generate_consultation_payment_methods(
     total: usize,
     payment_methods: Vec<T_RHSTU_FORMA_PAGAMENTO>,
     consultations: Vec<T_RHSTU_CONSULTA>,
+    coverage: Coverage,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) {
     // println!("Generating consultation payment methods...");
     let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_CONSULTA_FORMA_PAGTO");
 
     let pb_helper =
         ProgressBarHelper::new(m, total * 2, "Consultation Payment Methods:".to_string());
     let pb = &pb_helper.pb;
     let mut consultation_payment_methods: Vec<T_RHSTU_CONSULTA_FORMA_PAGTO> = Vec::new();
 
-    for i in 0..total {
-        let payment_method = payment_methods.choose(&mut rng).unwrap();
+    let (plan, others): (Vec<&T_RHSTU_FORMA_PAGAMENTO>, Vec<&T_RHSTU_FORMA_PAGAMENTO>) =
+        payment_methods
+            .iter()
+            .filter(|method| method.ST_FORMA_PAGTO == "A")
+            .partition(|method| method.NM_FORMA_PAGTO == HEALTH_PLAN_PAYMENT);
+    let covered: Vec<&T_RHSTU_FORMA_PAGAMENTO> = others.iter().chain(&plan).copied().collect();
 
-        let consultation = consultations.choose(&mut rng).unwrap();
+    for _ in 0..total {
+        let Some(consultation) = consultations.choose(&mut rng) else {
+            break;
+        };
+        let enrollment = parse_to_date(&consultation.DT_HR_CONSULTA)
+            .and_then(|date| coverage.covers(consultation.ID_PACIENTE, date));
+        let methods = if enrollment.is_some() {
+            &covered
+        } else {
+            &others
+        };
+        let Some(payment_method) = methods.choose(&mut rng) else {
+            continue;
+        };
 
         let consultation_payment_method = T_RHSTU_CONSULTA_FORMA_PAGTO {
-            ID_CONSULTA_FORMA_PAGTO: i as u64,
+            ID_CONSULTA_FORMA_PAGTO: consultation_payment_methods.len() as u64,
             ID_UNID_HOSPITAL: consultation.ID_UNID_HOSPITAL,
             ID_CONSULTA: consultation.ID_CONSULTA,
-            ID_PACIENTE_PS: consultation.ID_PACIENTE,
+            ID_PACIENTE_PS: enrollment,
             ID_FORMA_PAGTO: payment_method.ID_FORMA_PAGTO,
             DT_PAGTO_CONSULTA: current_timestamp(),
-            ST_PAGTO_CONSULTA: ["A", "C", "P"].choose(&mut rng).unwrap().to_string(),
+            ST_PAGTO_CONSULTA: table.pick("ST_PAGTO_CONSULTA", &mut rng),
             DT_CADASTRO: current_timestamp(),
             NM_USUARIO: "1".to_string(),
         };
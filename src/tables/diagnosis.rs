@@ -0,0 +1,559 @@
+use chrono::{Datelike, NaiveDateTime};
+use fake::{faker::name::en::Name, Fake};
+use indicatif::{MultiProgress, ProgressBar};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{
+    common::{current_timestamp, parse_to_date, ProgressBarHelper},
+    define_and_impl_sql_insertable,
+    sql_generator::SqlGenerator,
+    tables::{consultation::T_RHSTU_CONSULTA, patient::T_RHSTU_PACIENTE},
+};
+
+define_and_impl_sql_insertable!(
+    T_RHSTU_CID10 {
+        pub(crate) ID_CID10: u64,
+        pub(crate) CD_CID10: String [6],
+        pub(crate) DS_CID10: String [200],
+        pub(crate) DS_CAPITULO: String [150],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_DIAGNOSTICO {
+        pub(crate) ID_DIAGNOSTICO: u64,
+        pub(crate) ID_CONSULTA: u64,
+        pub(crate) ID_CID10: u64,
+        pub(crate) TP_DIAGNOSTICO: String [1],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    }
+);
+
+// Sample of the CID-10 codes most seen in outpatient care, not the full DATASUS list,
+// with their relative frequency per age band
+const CID10_CSV: &str = include_str!("../../catalogs/cid10_sample.csv");
+
+// Upper bound (exclusive) of each age band of the catalog weights
+const AGE_BANDS: [u32; 3] = [15, 40, 60];
+
+// Secondary codes a single consultation can receive
+const MAX_SECONDARY: usize = 3;
+
+// Attempts to draw a secondary code not yet given to the consultation
+const CODE_ATTEMPTS: usize = 10;
+
+// Chapter, its title, and active ingredients prescribed for its diagnoses. Medicine
+// names are matched against these in upper case, without accents.
+const CHAPTERS: [(&str, &str, &[&str]); 19] = [
+    (
+        "I",
+        "Algumas doenças infecciosas e parasitárias",
+        &[
+            "ALBENDAZOL",
+            "NITAZOXANIDA",
+            "FLUCONAZOL",
+            "NISTATINA",
+            "IVERMECTINA",
+            "ACICLOVIR",
+            "PARACETAMOL",
+            "RIFAMPICINA",
+        ],
+    ),
+    (
+        "II",
+        "Neoplasias [tumores]",
+        &[
+            "TAMOXIFENO",
+            "ANASTROZOL",
+            "BICALUTAMIDA",
+            "CAPECITABINA",
+            "ONDANSETRONA",
+        ],
+    ),
+    (
+        "III",
+        "Doenças do sangue e dos órgãos hematopoéticos e alguns transtornos imunitários",
+        &["FERROSO", "FOLICO", "CIANOCOBALAMINA"],
+    ),
+    (
+        "IV",
+        "Doenças endócrinas, nutricionais e metabólicas",
+        &[
+            "METFORMINA",
+            "GLIBENCLAMIDA",
+            "GLICLAZIDA",
+            "INSULINA",
+            "LEVOTIROXINA",
+            "SINVASTATINA",
+            "ATORVASTATINA",
+            "ROSUVASTATINA",
+        ],
+    ),
+    (
+        "V",
+        "Transtornos mentais e comportamentais",
+        &[
+            "FLUOXETINA",
+            "SERTRALINA",
+            "ESCITALOPRAM",
+            "AMITRIPTILINA",
+            "CLONAZEPAM",
+            "DIAZEPAM",
+            "RISPERIDONA",
+            "HALOPERIDOL",
+            "METILFENIDATO",
+        ],
+    ),
+    (
+        "VI",
+        "Doenças do sistema nervoso",
+        &[
+            "CARBAMAZEPINA",
+            "FENITOINA",
+            "VALPRO",
+            "TOPIRAMATO",
+            "LEVODOPA",
+            "DONEPEZILA",
+            "SUMATRIPTANA",
+            "ZOLPIDEM",
+        ],
+    ),
+    (
+        "VII",
+        "Doenças do olho e anexos",
+        &["TOBRAMICINA", "TIMOLOL", "LATANOPROSTA", "CIPROFLOXACINO"],
+    ),
+    (
+        "VIII",
+        "Doenças do ouvido e da apófise mastóide",
+        &["AMOXICILINA", "CIPROFLOXACINO", "DIPIRONA"],
+    ),
+    (
+        "IX",
+        "Doenças do aparelho circulatório",
+        &[
+            "LOSARTANA",
+            "ENALAPRIL",
+            "CAPTOPRIL",
+            "ANLODIPINO",
+            "HIDROCLOROTIAZIDA",
+            "ATENOLOL",
+            "CARVEDILOL",
+            "FUROSEMIDA",
+            "ESPIRONOLACTONA",
+            "VARFARINA",
+            "ACETILSALICILICO",
+        ],
+    ),
+    (
+        "X",
+        "Doenças do aparelho respiratório",
+        &[
+            "AMOXICILINA",
+            "AZITROMICINA",
+            "CEFALEXINA",
+            "SALBUTAMOL",
+            "BUDESONIDA",
+            "PREDNISOLONA",
+            "LORATADINA",
+            "DESLORATADINA",
+            "DIPIRONA",
+            "PARACETAMOL",
+            "OSELTAMIVIR",
+        ],
+    ),
+    (
+        "XI",
+        "Doenças do aparelho digestivo",
+        &[
+            "OMEPRAZOL",
+            "PANTOPRAZOL",
+            "DOMPERIDONA",
+            "BROMOPRIDA",
+            "LACTULOSE",
+            "SIMETICONA",
+            "ESCOPOLAMINA",
+        ],
+    ),
+    (
+        "XII",
+        "Doenças da pele e do tecido subcutâneo",
+        &[
+            "CETOCONAZOL",
+            "BETAMETASONA",
+            "HIDROCORTISONA",
+            "ISOTRETINOINA",
+            "CEFALEXINA",
+            "DEXAMETASONA",
+        ],
+    ),
+    (
+        "XIII",
+        "Doenças do sistema osteomuscular e do tecido conjuntivo",
+        &[
+            "IBUPROFENO",
+            "DICLOFENACO",
+            "NIMESULIDA",
+            "CICLOBENZAPRINA",
+            "ALENDRONATO",
+            "ALOPURINOL",
+            "MELOXICAM",
+            "COLCHICINA",
+            "PARACETAMOL",
+        ],
+    ),
+    (
+        "XIV",
+        "Doenças do aparelho geniturinário",
+        &[
+            "NITROFURANTOINA",
+            "CIPROFLOXACINO",
+            "NORFLOXACINO",
+            "FOSFOMICINA",
+            "TANSULOSINA",
+            "FINASTERIDA",
+            "METRONIDAZOL",
+            "ESCOPOLAMINA",
+        ],
+    ),
+    (
+        "XV",
+        "Gravidez, parto e puerpério",
+        &["FOLICO", "FERROSO", "METILDOPA", "DIMENIDRINATO"],
+    ),
+    (
+        "XVIII",
+        "Sintomas, sinais e achados anormais de exames clínicos e de laboratório",
+        &[
+            "DIPIRONA",
+            "PARACETAMOL",
+            "IBUPROFENO",
+            "ESCOPOLAMINA",
+            "DIMENIDRINATO",
+            "BETAISTINA",
+        ],
+    ),
+    (
+        "XIX",
+        "Lesões, envenenamento e algumas outras conseqüências de causas externas",
+        &[
+            "DIPIRONA",
+            "IBUPROFENO",
+            "CETOPROFENO",
+            "TRAMADOL",
+            "CEFALEXINA",
+            "LORATADINA",
+            "PREDNISONA",
+        ],
+    ),
+    ("XX", "Causas externas de morbidade e de mortalidade", &[]),
+    (
+        "XXI",
+        "Fatores que influenciam o estado de saúde e o contato com os serviços de saúde",
+        &[],
+    ),
+];
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Entry {
+    CD_CID10: String,
+    DS_CID10: String,
+    CAPITULO: String,
+    // "M" or "F" for codes restricted to one biological sex, empty otherwise
+    SEXO: String,
+    PESO_0_14: f64,
+    PESO_15_39: f64,
+    PESO_40_59: f64,
+    PESO_60: f64,
+}
+
+// Bundled CID-10 codes with a weighted distribution per age band and sex
+pub(crate) struct Catalog {
+    codes: Vec<T_RHSTU_CID10>,
+    // Chapter of each code, by ID_CID10
+    chapters: HashMap<u64, &'static str>,
+    // Indexed by age band, then 0 for "M" and 1 for "F"
+    weights: Vec<[WeightedIndex<f64>; 2]>,
+}
+
+impl Catalog {
+    fn load() -> Result<Catalog, anyhow::Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(CID10_CSV.as_bytes());
+
+        let mut codes = Vec::new();
+        let mut chapters = HashMap::new();
+        // Weights per band and sex, transposed once every entry is read
+        let mut columns: Vec<[Vec<f64>; 2]> = vec![[Vec::new(), Vec::new()]; AGE_BANDS.len() + 1];
+
+        for (i, result) in reader.deserialize().enumerate() {
+            let entry: Entry = result?;
+            let Some((chapter, title, _)) = CHAPTERS.iter().find(|(c, _, _)| *c == entry.CAPITULO)
+            else {
+                anyhow::bail!("{}: unknown chapter {}", entry.CD_CID10, entry.CAPITULO);
+            };
+
+            let bands = [
+                entry.PESO_0_14,
+                entry.PESO_15_39,
+                entry.PESO_40_59,
+                entry.PESO_60,
+            ];
+            for (band, weight) in bands.into_iter().enumerate() {
+                columns[band][0].push(if entry.SEXO == "F" { 0.0 } else { weight });
+                columns[band][1].push(if entry.SEXO == "M" { 0.0 } else { weight });
+            }
+
+            chapters.insert(i as u64, *chapter);
+            codes.push(T_RHSTU_CID10 {
+                ID_CID10: i as u64,
+                CD_CID10: entry.CD_CID10,
+                DS_CID10: entry.DS_CID10,
+                DS_CAPITULO: format!("{} - {}", chapter, title),
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: Name().fake(),
+            });
+        }
+
+        let weights = columns
+            .into_iter()
+            .map(|[male, female]| -> Result<_, anyhow::Error> {
+                Ok([WeightedIndex::new(male)?, WeightedIndex::new(female)?])
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Catalog {
+            codes,
+            chapters,
+            weights,
+        })
+    }
+
+    // Draws a code for a patient of the given age and biological sex
+    fn sample<R: Rng + ?Sized>(&self, age: u32, sex: &str, rng: &mut R) -> usize {
+        let band = AGE_BANDS.iter().take_while(|limit| age >= **limit).count();
+        let sex = usize::from(sex == "F");
+        self.weights[band][sex].sample(rng)
+    }
+
+    // Chapter of the primary diagnosis of each consultation
+    pub(crate) fn primary_chapters(
+        &self,
+        diagnoses: &[T_RHSTU_DIAGNOSTICO],
+    ) -> HashMap<u64, &'static str> {
+        diagnoses
+            .iter()
+            .filter(|diagnosis| diagnosis.TP_DIAGNOSTICO == "P")
+            .filter_map(|diagnosis| {
+                let chapter = self.chapters.get(&diagnosis.ID_CID10)?;
+                Some((diagnosis.ID_CONSULTA, *chapter))
+            })
+            .collect()
+    }
+}
+
+// Active ingredients prescribed for diagnoses of `chapter`
+pub(crate) fn chapter_medicines(chapter: &str) -> &'static [&'static str] {
+    CHAPTERS
+        .iter()
+        .find(|(c, _, _)| *c == chapter)
+        .map_or(&[], |(_, _, medicines)| *medicines)
+}
+
+// Completed years between `birth` and `date`, zero if born afterwards
+fn age_at(birth: NaiveDateTime, date: NaiveDateTime) -> u32 {
+    let mut years = date.year() - birth.year();
+    if (date.month(), date.day()) < (birth.month(), birth.day()) {
+        years -= 1;
+    }
+    years.max(0) as u32
+}
+
+pub(crate) fn generate_cid10(
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Result<Arc<Catalog>, anyhow::Error> {
+    let catalog = Catalog::load()?;
+
+    let pb_helper = ProgressBarHelper::new(m, catalog.codes.len() * 2, "CID-10:".to_string());
+    let pb = &pb_helper.pb;
+    pb.inc(catalog.codes.len() as u64);
+    main_pb.inc(catalog.codes.len() as u64);
+
+    let generator = SqlGenerator::new(catalog.codes.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    Ok(Arc::new(catalog))
+}
+
+// Gives every consultation a primary diagnosis, even past the configured rows, then
+// spreads the remaining rows as secondary diagnoses. Codes are drawn for the patient's
+// age at the consultation and biological sex.
+pub(crate) async fn generate_diagnoses(
+    total: usize,
+    catalog: Arc<Catalog>,
+    consultations: Vec<T_RHSTU_CONSULTA>,
+    patients: Arc<Vec<T_RHSTU_PACIENTE>>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_DIAGNOSTICO> {
+    let total = if consultations.is_empty() {
+        0
+    } else {
+        total.max(consultations.len())
+    };
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Diagnoses:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    let diagnoses = draw_diagnoses(total, &catalog, &consultations, &patients, &mut rng);
+    pb.inc(total as u64);
+    main_pb.inc(diagnoses.len() as u64);
+
+    let generator = SqlGenerator::new(diagnoses.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    diagnoses
+}
+
+// Rows of `generate_diagnoses`, drawn without writing them
+fn draw_diagnoses<R: Rng + ?Sized>(
+    total: usize,
+    catalog: &Catalog,
+    consultations: &[T_RHSTU_CONSULTA],
+    patients: &[T_RHSTU_PACIENTE],
+    rng: &mut R,
+) -> Vec<T_RHSTU_DIAGNOSTICO> {
+    let mut diagnoses: Vec<T_RHSTU_DIAGNOSTICO> = Vec::with_capacity(total);
+    let patients: HashMap<u64, &T_RHSTU_PACIENTE> = patients
+        .iter()
+        .map(|patient| (patient.ID_PACIENTE, patient))
+        .collect();
+
+    // (age, sex) of the patient of every consultation
+    let demographics: Vec<(u32, &str)> = consultations
+        .iter()
+        .map(|consultation| {
+            let Some(patient) = patients.get(&consultation.ID_PACIENTE) else {
+                return (0, "");
+            };
+            let age = match (
+                parse_to_date(&patient.DT_NASCIMENTO),
+                parse_to_date(&consultation.DT_HR_CONSULTA),
+            ) {
+                (Some(birth), Some(date)) => age_at(birth, date),
+                _ => 0,
+            };
+            (age, patient.FL_SEXO_BIOLOGICO.as_str())
+        })
+        .collect();
+
+    let mut given: Vec<HashSet<usize>> = vec![HashSet::new(); consultations.len()];
+
+    for i in 0..total {
+        let (index, kind) = if i < consultations.len() {
+            (i, "P")
+        } else {
+            (rng.gen_range(0..consultations.len()), "S")
+        };
+
+        let (age, sex) = demographics[index];
+        let codes = &mut given[index];
+        // The primary code is among those given
+        let secondaries = codes.len().saturating_sub(1);
+        if kind == "S" && secondaries >= MAX_SECONDARY {
+            continue;
+        }
+
+        let mut code = catalog.sample(age, sex, rng);
+        for _ in 0..CODE_ATTEMPTS {
+            if !codes.contains(&code) {
+                break;
+            }
+            code = catalog.sample(age, sex, rng);
+        }
+        if !codes.insert(code) {
+            continue;
+        }
+
+        diagnoses.push(T_RHSTU_DIAGNOSTICO {
+            ID_DIAGNOSTICO: diagnoses.len() as u64,
+            ID_CONSULTA: consultations[index].ID_CONSULTA,
+            ID_CID10: catalog.codes[code].ID_CID10,
+            TP_DIAGNOSTICO: kind.to_string(),
+            DT_CADASTRO: consultations[index].DT_HR_CONSULTA.clone(),
+            NM_USUARIO: Name().fake(),
+        });
+    }
+
+    diagnoses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_every_consultation_a_primary_and_caps_secondaries() {
+        let catalog = Catalog::load().unwrap();
+        // Patient ids don't follow their position
+        let patients: Vec<T_RHSTU_PACIENTE> = [7, 3]
+            .into_iter()
+            .map(|id| T_RHSTU_PACIENTE {
+                ID_PACIENTE: id,
+                NM_PACIENTE: String::new(),
+                NR_CPF: 0,
+                NM_RG: String::new(),
+                DS_ORGAO_EMISSOR_RG: String::new(),
+                DT_NASCIMENTO: "TO_DATE('1980-05-01 00:00:00', 'YYYY-MM-DD HH24:MI:SS')"
+                    .to_string(),
+                FL_SEXO_BIOLOGICO: "F".to_string(),
+                DS_ESCOLARIDADE: String::new(),
+                DS_ESTADO_CIVIL: String::new(),
+                NM_GRUPO_SANGUINEO: String::new(),
+                NR_ALTURA: 0,
+                NR_PESO: 0,
+                DT_CADASTRO: String::new(),
+                NM_USUARIO: String::new(),
+            })
+            .collect();
+        let consultations: Vec<T_RHSTU_CONSULTA> = (0..4)
+            .map(|id| T_RHSTU_CONSULTA {
+                ID_UNID_HOSPITAL: 0,
+                ID_CONSULTA: id,
+                ID_PACIENTE: if id % 2 == 0 { 7 } else { 3 },
+                ID_FUNC: 0,
+                DT_HR_CONSULTA: "TO_DATE('2023-01-10 09:00:00', 'YYYY-MM-DD HH24:MI:SS')"
+                    .to_string(),
+                NR_CONSULTORIO: String::new(),
+                DT_CADASTRO: String::new(),
+                NM_USUARIO: String::new(),
+            })
+            .collect();
+
+        let mut rng = rand::thread_rng();
+        let diagnoses = draw_diagnoses(200, &catalog, &consultations, &patients, &mut rng);
+        for consultation in &consultations {
+            let kinds: Vec<&str> = diagnoses
+                .iter()
+                .filter(|diagnosis| diagnosis.ID_CONSULTA == consultation.ID_CONSULTA)
+                .map(|diagnosis| diagnosis.TP_DIAGNOSTICO.as_str())
+                .collect();
+            assert_eq!(kinds.iter().filter(|kind| **kind == "P").count(), 1);
+            assert_eq!(kinds.len() - 1, MAX_SECONDARY);
+        }
+        assert_eq!(
+            catalog.primary_chapters(&diagnoses).len(),
+            consultations.len()
+        );
+    }
+}
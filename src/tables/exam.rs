@@ -0,0 +1,428 @@
+use chrono::{Duration, NaiveDateTime};
+use fake::{faker::name::en::Name, Fake};
+use indicatif::{MultiProgress, ProgressBar};
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{
+    common::{current_timestamp, parse_to_date, to_date, ProgressBarHelper},
+    config::profile,
+    define_and_impl_sql_insertable,
+    sql_generator::SqlGenerator,
+    tables::{consultation::T_RHSTU_CONSULTA, inpatient::Deaths, patient::T_RHSTU_PACIENTE},
+};
+
+define_and_impl_sql_insertable!(
+    T_RHSTU_EXAME {
+        pub(crate) ID_EXAME: u64,
+        pub(crate) CD_SIGTAP: String [10],
+        pub(crate) CD_LOINC: String [10],
+        pub(crate) NM_EXAME: String [100],
+        pub(crate) DS_UNIDADE: String [20],
+        pub(crate) VL_REFERENCIA_MIN: String [20],
+        pub(crate) VL_REFERENCIA_MAX: String [20],
+        pub(crate) DS_RESULTADO_NORMAL: String [50],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_PEDIDO_EXAME {
+        pub(crate) ID_PEDIDO_EXAME: u64,
+        pub(crate) ID_CONSULTA: u64,
+        pub(crate) ID_PACIENTE: u64,
+        pub(crate) ID_EXAME: u64,
+        pub(crate) DT_PEDIDO: String,
+        pub(crate) ST_PEDIDO: String [1],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_RESULTADO_EXAME {
+        pub(crate) ID_RESULTADO_EXAME: u64,
+        pub(crate) ID_PEDIDO_EXAME: u64,
+        pub(crate) VL_RESULTADO: String [20],
+        pub(crate) DS_RESULTADO: String [50],
+        pub(crate) FL_FORA_REFERENCIA: String [1],
+        pub(crate) DT_COLETA: String,
+        pub(crate) DT_RESULTADO: String,
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    }
+);
+
+// Exams with SIGTAP and LOINC codes, units, reference ranges and ordering weight
+const EXAMES_CSV: &str = include_str!("../../catalogs/exames.csv");
+
+// Share of orders repeating an exam the patient already had
+const REPEAT_SHARE: f64 = 0.6;
+
+// Attempts to pick an exam not yet ordered in the same consultation
+const EXAM_ATTEMPTS: usize = 10;
+
+// Hours between the order and the sample collection, and between collection and result
+const COLLECTION_HOURS: (i64, i64) = (1, 72);
+const RESULT_HOURS: (i64, i64) = (2, 96);
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Entry {
+    CD_SIGTAP: String,
+    CD_LOINC: String,
+    NM_EXAME: String,
+    DS_UNIDADE: String,
+    VL_REF_MIN: Option<f64>,
+    VL_REF_MAX: Option<f64>,
+    NR_CASAS: Option<usize>,
+    // "A" when abnormal results lie above the range, "B" below, empty for both
+    SENTIDO: String,
+    // "M" or "F" for exams ordered for one biological sex only
+    SEXO: String,
+    DS_NORMAL: String,
+    DS_ALTERADO: String,
+    PESO: f64,
+}
+
+enum Reference {
+    Numeric {
+        min: f64,
+        max: f64,
+        decimals: usize,
+        above: bool,
+        below: bool,
+    },
+    Text {
+        normal: String,
+        abnormal: String,
+    },
+}
+
+// Bundled exam catalog with an ordering distribution per biological sex
+pub(crate) struct Catalog {
+    exams: Vec<T_RHSTU_EXAME>,
+    references: Vec<Reference>,
+    // Biological sex each exam is restricted to, empty for both
+    sexes: Vec<String>,
+    // 0 for "M", 1 for "F"
+    weights: [WeightedIndex<f64>; 2],
+}
+
+impl Catalog {
+    fn load() -> Result<Catalog, anyhow::Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(EXAMES_CSV.as_bytes());
+
+        let mut exams = Vec::new();
+        let mut references = Vec::new();
+        let mut sexes = Vec::new();
+        let mut weights = [Vec::new(), Vec::new()];
+
+        for (i, result) in reader.deserialize().enumerate() {
+            let entry: Entry = result?;
+            let reference = match (entry.VL_REF_MIN, entry.VL_REF_MAX) {
+                (Some(min), Some(max)) => Reference::Numeric {
+                    min,
+                    max,
+                    decimals: entry.NR_CASAS.unwrap_or(0),
+                    above: entry.SENTIDO != "B",
+                    // Ranges starting at zero cannot be undershot
+                    below: entry.SENTIDO != "A" && min > 0.0,
+                },
+                (None, None) if !entry.DS_NORMAL.is_empty() && !entry.DS_ALTERADO.is_empty() => {
+                    Reference::Text {
+                        normal: entry.DS_NORMAL.clone(),
+                        abnormal: entry.DS_ALTERADO,
+                    }
+                }
+                _ => anyhow::bail!("{}: incomplete reference", entry.NM_EXAME),
+            };
+
+            let (min, max) = match &reference {
+                Reference::Numeric {
+                    min, max, decimals, ..
+                } => (
+                    format!("{:.*}", decimals, min),
+                    format!("{:.*}", decimals, max),
+                ),
+                Reference::Text { .. } => (String::new(), String::new()),
+            };
+
+            weights[0].push(if entry.SEXO == "F" { 0.0 } else { entry.PESO });
+            weights[1].push(if entry.SEXO == "M" { 0.0 } else { entry.PESO });
+
+            references.push(reference);
+            sexes.push(entry.SEXO);
+            exams.push(T_RHSTU_EXAME {
+                ID_EXAME: i as u64,
+                CD_SIGTAP: entry.CD_SIGTAP,
+                CD_LOINC: entry.CD_LOINC,
+                NM_EXAME: entry.NM_EXAME,
+                DS_UNIDADE: entry.DS_UNIDADE,
+                VL_REFERENCIA_MIN: min,
+                VL_REFERENCIA_MAX: max,
+                DS_RESULTADO_NORMAL: entry.DS_NORMAL,
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: Name().fake(),
+            });
+        }
+
+        let [male, female] = weights;
+        Ok(Catalog {
+            exams,
+            references,
+            sexes,
+            weights: [WeightedIndex::new(male)?, WeightedIndex::new(female)?],
+        })
+    }
+
+    fn sample<R: Rng + ?Sized>(&self, sex: &str, rng: &mut R) -> usize {
+        self.weights[usize::from(sex == "F")].sample(rng)
+    }
+
+    fn allowed(&self, exam: usize, sex: &str) -> bool {
+        self.sexes[exam].is_empty() || self.sexes[exam] == sex
+    }
+}
+
+// Standard normal sample (Box-Muller)
+fn gaussian<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+// Level a patient's results gravitate to: inside the range, or outside it on the
+// pathological side for the configured share of patients
+fn baseline<R: Rng + ?Sized>(reference: &Reference, abnormal: bool, rng: &mut R) -> f64 {
+    let Reference::Numeric {
+        min,
+        max,
+        above,
+        below,
+        ..
+    } = reference
+    else {
+        return 0.0;
+    };
+    let width = max - min;
+    if !abnormal {
+        // Away from the limits, so noise rarely crosses them
+        return rng.gen_range(min + width * 0.1..=max - width * 0.1);
+    }
+    // Above the range whenever results can't fall below it, e.g. a "B" exam whose
+    // range starts at zero
+    let high = if *above && *below {
+        rng.gen_bool(0.5)
+    } else {
+        !*below
+    };
+    if high {
+        max + width * rng.gen_range(0.1..1.0)
+    } else {
+        (min - width * rng.gen_range(0.1..0.6)).max(min * rng.gen_range(0.3..0.9))
+    }
+}
+
+// Standard deviation of repeated measurements, as a share of the reference range width
+fn spread(reference: &Reference, variation: f64) -> f64 {
+    match reference {
+        Reference::Numeric { min, max, .. } => (max - min) * variation,
+        Reference::Text { .. } => 0.0,
+    }
+}
+
+pub(crate) fn generate_exams(
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Result<Arc<Catalog>, anyhow::Error> {
+    let catalog = Catalog::load()?;
+
+    let pb_helper = ProgressBarHelper::new(m, catalog.exams.len() * 2, "Exams:".to_string());
+    let pb = &pb_helper.pb;
+    pb.inc(catalog.exams.len() as u64);
+    main_pb.inc(catalog.exams.len() as u64);
+
+    let generator = SqlGenerator::new(catalog.exams.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    Ok(Arc::new(catalog))
+}
+
+// Spreads the orders over random consultations. Going through them in date order,
+// patients are often re-tested for exams they already had, so results form series.
+pub(crate) fn generate_exam_orders(
+    total: usize,
+    catalog: &Catalog,
+    consultations: &[T_RHSTU_CONSULTA],
+    patients: &[T_RHSTU_PACIENTE],
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_PEDIDO_EXAME> {
+    let total = if consultations.is_empty() { 0 } else { total };
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Exam Orders:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_PEDIDO_EXAME");
+
+    let mut picks: Vec<&T_RHSTU_CONSULTA> = (0..total)
+        .filter_map(|_| consultations.choose(&mut rng))
+        .collect();
+    picks.sort_by_key(|consultation| {
+        (
+            parse_to_date(&consultation.DT_HR_CONSULTA),
+            consultation.ID_CONSULTA,
+        )
+    });
+
+    // Exams each patient already had, and those ordered in each consultation
+    let mut history: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut ordered: HashMap<u64, HashSet<usize>> = HashMap::new();
+    let mut orders = Vec::with_capacity(total);
+
+    for consultation in picks {
+        let sex = patients
+            .get(consultation.ID_PACIENTE as usize)
+            .map_or("", |patient| patient.FL_SEXO_BIOLOGICO.as_str());
+        let previous = history.entry(consultation.ID_PACIENTE).or_default();
+        let current = ordered.entry(consultation.ID_CONSULTA).or_default();
+
+        let mut exam = catalog.sample(sex, &mut rng);
+        for _ in 0..EXAM_ATTEMPTS {
+            if rng.gen_bool(REPEAT_SHARE) {
+                if let Some(repeat) = previous.choose(&mut rng) {
+                    exam = *repeat;
+                }
+            }
+            if !current.contains(&exam) && catalog.allowed(exam, sex) {
+                break;
+            }
+            exam = catalog.sample(sex, &mut rng);
+        }
+        if !current.insert(exam) {
+            pb.inc(1);
+            continue;
+        }
+        if !previous.contains(&exam) {
+            previous.push(exam);
+        }
+
+        orders.push(T_RHSTU_PEDIDO_EXAME {
+            ID_PEDIDO_EXAME: orders.len() as u64,
+            ID_CONSULTA: consultation.ID_CONSULTA,
+            ID_PACIENTE: consultation.ID_PACIENTE,
+            ID_EXAME: catalog.exams[exam].ID_EXAME,
+            DT_PEDIDO: consultation.DT_HR_CONSULTA.clone(),
+            ST_PEDIDO: table.pick("ST_PEDIDO", &mut rng),
+            DT_CADASTRO: consultation.DT_HR_CONSULTA.clone(),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let generator = SqlGenerator::new(orders.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    orders
+}
+
+// Reports every performed order. Each patient keeps a level per exam that drifts
+// slowly over time, so repeated measurements stay close to each other.
+pub(crate) async fn generate_exam_results(
+    orders: Vec<T_RHSTU_PEDIDO_EXAME>,
+    catalog: Arc<Catalog>,
+    deaths: Arc<Deaths>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> usize {
+    let exams = profile().exams();
+    let performed: Vec<&T_RHSTU_PEDIDO_EXAME> = orders
+        .iter()
+        .filter(|order| order.ST_PEDIDO == "R")
+        .collect();
+
+    let pb_helper = ProgressBarHelper::new(m, performed.len() * 2, "Exam Results:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    // (level, abnormal, last collection) of each patient and exam
+    let mut levels: HashMap<(u64, u64), (f64, bool, NaiveDateTime)> = HashMap::new();
+    let mut results = Vec::with_capacity(performed.len());
+
+    // Orders are in date order, so the series of each patient is built forwards
+    for order in performed {
+        let ordered_at = parse_to_date(&order.DT_PEDIDO).unwrap_or_default();
+        // Collected by the time of death of a patient who died in hospital meanwhile
+        let collected = (ordered_at
+            + Duration::hours(rng.gen_range(COLLECTION_HOURS.0..=COLLECTION_HOURS.1)))
+        .min(deaths.date(order.ID_PACIENTE).unwrap_or(NaiveDateTime::MAX))
+        .max(ordered_at);
+        let reported = collected + Duration::hours(rng.gen_range(RESULT_HOURS.0..=RESULT_HOURS.1));
+        let reference = &catalog.references[order.ID_EXAME as usize];
+
+        let (level, abnormal, _) = match levels.get(&(order.ID_PACIENTE, order.ID_EXAME)) {
+            Some(&(level, abnormal, last)) => {
+                // Random walk of the level, spreading with the time since the last sample
+                let years = (collected - last).num_days().max(0) as f64 / 365.0;
+                let drift = gaussian(&mut rng) * spread(reference, exams.variation) * years.sqrt();
+                ((level + drift).max(0.0), abnormal, last)
+            }
+            None => {
+                let abnormal = rng.gen_bool(exams.out_of_range);
+                (baseline(reference, abnormal, &mut rng), abnormal, collected)
+            }
+        };
+        levels.insert(
+            (order.ID_PACIENTE, order.ID_EXAME),
+            (level, abnormal, collected),
+        );
+
+        let (value, text, out_of_range) = match reference {
+            Reference::Numeric {
+                min, max, decimals, ..
+            } => {
+                let measured =
+                    (level + gaussian(&mut rng) * spread(reference, exams.variation)).max(0.0);
+                let value = format!("{:.*}", decimals, measured);
+                let rounded: f64 = value.parse().unwrap_or(measured);
+                (value, String::new(), rounded < *min || rounded > *max)
+            }
+            Reference::Text {
+                normal,
+                abnormal: altered,
+            } => {
+                let text = if abnormal { altered } else { normal };
+                (String::new(), text.clone(), abnormal)
+            }
+        };
+
+        results.push(T_RHSTU_RESULTADO_EXAME {
+            ID_RESULTADO_EXAME: results.len() as u64,
+            ID_PEDIDO_EXAME: order.ID_PEDIDO_EXAME,
+            VL_RESULTADO: value,
+            DS_RESULTADO: text,
+            FL_FORA_REFERENCIA: if out_of_range { "S" } else { "N" }.to_string(),
+            DT_COLETA: to_date(collected),
+            DT_RESULTADO: to_date(reported),
+            DT_CADASTRO: to_date(reported),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let len = results.len();
+    let generator = SqlGenerator::new(results);
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    len
+}
@@ -1,48 +1,54 @@
-use fake::{faker::address::en::StreetName, Fake};
+use anyhow::bail;
 use indicatif::{MultiProgress, ProgressBar};
-use rand::seq::SliceRandom;
+use rand::{seq::SliceRandom, Rng};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use crate::{
-    common::{current_timestamp, fetch_data, random_cep, ProgressBarHelper},
+    common::{current_timestamp, fetch_data, ProgressBarHelper},
+    config::profile,
     define_and_impl_sql_insertable,
     sql_generator::SqlGenerator,
 };
 
+use super::street::StreetNames;
+
 define_and_impl_sql_insertable!(
     T_RHSTU_ESTADO {
         pub ID_ESTADO: u64,
-        SG_ESTADO: String,
-        NM_ESTADO: String,
+        pub SG_ESTADO: String [2],
+        NM_ESTADO: String [50],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_CIDADE {
         pub ID_CIDADE: u64,
         pub ID_ESTADO: u64,
-        NM_CIDADE: String,
+        NM_CIDADE: String [100],
         CD_IBGE: u64,
         pub NR_DDD: u64,
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_BAIRRO {
         pub ID_BAIRRO: u64,
         pub ID_CIDADE: u64,
-        NM_BAIRRO: String,
-        NM_ZONA_BAIRRO: String,
+        NM_BAIRRO: String [100],
+        NM_ZONA_BAIRRO: String [20],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_LOGRADOURO {
         pub ID_LOGRADOURO: u64,
         pub ID_BAIRRO: u64,
-        NM_LOGRADOURO: String,
-        NR_CEP: u64,
+        pub NM_LOGRADOURO: String [150],
+        pub NR_CEP: String [9],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     }
 );
 
@@ -55,10 +61,9 @@ pub(crate) async fn generate_states(
     client: Client,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Result<usize, anyhow::Error> {
+) -> Result<Vec<T_RHSTU_ESTADO>, anyhow::Error> {
     let json: Vec<UF> = fetch_data(&client, STATES_URL).await?;
 
-    let mut len: usize = 0;
     let mut states: Vec<T_RHSTU_ESTADO> = Vec::with_capacity(json.len());
 
     let pb_helper = ProgressBarHelper::new(m, json.len() * 2, "States:".to_string());
@@ -73,18 +78,17 @@ pub(crate) async fn generate_states(
             NM_USUARIO: CREATED_BY.to_string(),
         };
         states.push(state_data);
-        len += 1;
         pb.inc(1);
         main_pb.inc(1);
     }
 
     // Use SqlGenerator to generate SQL and write to a file
-    let generator = SqlGenerator::new(states);
+    let generator = SqlGenerator::new(states.clone());
     generator.write_to_file(pb)?;
 
     pb_helper.finish();
 
-    Ok(len)
+    Ok(states)
 }
 
 pub(crate) async fn generate_cities(
@@ -153,19 +157,6 @@ fn get_ibge_code_to_ddd() -> Result<HashMap<String, String>, anyhow::Error> {
     Ok(ibge_code_to_ddd)
 }
 
-pub(crate) fn get_ddds() -> Result<Vec<u64>, anyhow::Error> {
-    let mut ddds = Vec::new();
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b';')
-        .from_path("Codigos_Nacionais.csv")?;
-    for result in reader.deserialize() {
-        let record: HashMap<String, String> = result?;
-        let ddd = record.get("CN").unwrap();
-        ddds.push(ddd.parse::<u64>()?);
-    }
-    Ok(ddds)
-}
-
 pub(crate) async fn generate_neighborhoods(
     client: Client,
     m: Arc<MultiProgress>,
@@ -181,23 +172,15 @@ pub(crate) async fn generate_neighborhoods(
 
     let pb_helper = ProgressBarHelper::new(m, json.len() * 2, "Neighborhoods:".to_string());
     let pb = &pb_helper.pb;
+    let table = profile().table("T_RHSTU_BAIRRO");
 
     for neighborhood in json.iter() {
+        #[allow(clippy::useless_conversion)]
         let neighborhood_data: T_RHSTU_BAIRRO = T_RHSTU_BAIRRO {
             ID_BAIRRO: pb.position().try_into().expect("cant fit into u64"),
             ID_CIDADE: neighborhood.municipio.id,
             NM_BAIRRO: neighborhood.nome.clone(),
-            NM_ZONA_BAIRRO: [
-                "CENTRO",
-                "ZONA LESTE",
-                "ZONA NORTE",
-                "ZONA OESTE",
-                "ZONA SUL",
-            ]
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .to_string(),
-
+            NM_ZONA_BAIRRO: table.pick("NM_ZONA_BAIRRO", &mut rand::thread_rng()),
             DT_CADASTRO: created_at.clone(),
             NM_USUARIO: created_by.clone(),
         };
@@ -215,32 +198,162 @@ pub(crate) async fn generate_neighborhoods(
     Ok(neighborhoods)
 }
 
+// Correios CEP ranges of every state, and of the cities with ranges of their own
+const FAIXAS_CEP_CSV: &str = include_str!("../../catalogs/faixas_cep.csv");
+
+// Draws within a city's own ranges before settling for the unclaimed part of its
+// state, and draws there before giving up on finding an unused CEP
+const CEP_ATTEMPTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct CepRange {
+    SG_UF: String,
+    CD_IBGE: Option<u64>,
+    CEP_INICIO: u32,
+    CEP_FIM: u32,
+}
+
+struct CepRanges {
+    states: HashMap<String, Vec<(u32, u32)>>,
+    cities: HashMap<u64, Vec<(u32, u32)>>,
+    // Ranges of the cities of every state, which its other cities stay out of
+    claimed: HashMap<String, Vec<(u32, u32)>>,
+}
+
+impl CepRanges {
+    fn load() -> Result<CepRanges, csv::Error> {
+        let mut ranges = CepRanges {
+            states: HashMap::new(),
+            cities: HashMap::new(),
+            claimed: HashMap::new(),
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(FAIXAS_CEP_CSV.as_bytes());
+        for range in reader.deserialize() {
+            let range: CepRange = range?;
+            let bounds = (range.CEP_INICIO, range.CEP_FIM);
+            match range.CD_IBGE {
+                Some(city) => {
+                    ranges.cities.entry(city).or_default().push(bounds);
+                    ranges.claimed.entry(range.SG_UF).or_default().push(bounds);
+                }
+                None => ranges.states.entry(range.SG_UF).or_default().push(bounds),
+            }
+        }
+        Ok(ranges)
+    }
+
+    // A CEP of `city` in `state`: within the city's ranges when it has any, otherwise
+    // within the state's ranges but outside those of its cities. `loose` gives up on
+    // the city's own ranges, never on staying out of the other cities'. None when no
+    // draw lands outside them.
+    fn sample<R: Rng + ?Sized>(
+        &self,
+        state: &str,
+        city: u64,
+        loose: bool,
+        rng: &mut R,
+    ) -> Option<u32> {
+        let Some(ranges) = self.states.get(state) else {
+            return Some(rng.gen_range(1_000_000..100_000_000));
+        };
+        if !loose {
+            if let Some(ranges) = self.cities.get(&city) {
+                return Some(draw(ranges, rng));
+            }
+        }
+        let claimed = self.claimed.get(state).map_or(&[][..], |ranges| ranges);
+        (0..CEP_ATTEMPTS).map(|_| draw(ranges, rng)).find(|cep| {
+            !claimed
+                .iter()
+                .any(|(start, end)| (start..=end).contains(&cep))
+        })
+    }
+}
+
+// Uniform draw over the union of `ranges`
+fn draw<R: Rng + ?Sized>(ranges: &[(u32, u32)], rng: &mut R) -> u32 {
+    let total: u32 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+    let mut offset = rng.gen_range(0..total);
+    for (start, end) in ranges {
+        let width = end - start + 1;
+        if offset < width {
+            return start + offset;
+        }
+        offset -= width;
+    }
+    unreachable!()
+}
+
+// CEP as eight digits, or as "00000-000" when the profile asks for it
+fn render_cep(cep: u32) -> String {
+    if profile().addresses().formatted_cep {
+        format!("{:05}-{:03}", cep / 1000, cep % 1000)
+    } else {
+        format!("{:08}", cep)
+    }
+}
+
+// Streets get a Brazilian name and a CEP of their neighborhood's city and state that
+// no other street has
 pub(crate) fn generate_address(
-    neighborhood: &Vec<T_RHSTU_BAIRRO>,
+    neighborhood: &[T_RHSTU_BAIRRO],
+    cities: &[T_RHSTU_CIDADE],
+    states: &[T_RHSTU_ESTADO],
     total: usize,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Result<Vec<T_RHSTU_LOGRADOURO>, csv::Error> {
+) -> Result<Vec<T_RHSTU_LOGRADOURO>, anyhow::Error> {
     // println!("Generating addresses...");
 
     let mut addresses: Vec<T_RHSTU_LOGRADOURO> = Vec::new();
+    let mut rng = rand::thread_rng();
+    let ranges = CepRanges::load()?;
+    let names = StreetNames::load()?;
+    let abbreviations: HashMap<u64, &str> = states
+        .iter()
+        .map(|state| (state.ID_ESTADO, state.SG_ESTADO.as_str()))
+        .collect();
+    let city_states: HashMap<u64, &str> = cities
+        .iter()
+        .filter_map(|city| Some((city.ID_CIDADE, *abbreviations.get(&city.ID_ESTADO)?)))
+        .collect();
+    let mut used: HashSet<u32> = HashSet::with_capacity(total);
 
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Addresses:".to_string());
     let pb = &pb_helper.pb;
 
     for i in 0..total {
-        let street_name: String = StreetName().fake();
-        let neighborhood_id: usize = neighborhood
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .clone()
-            .ID_BAIRRO as usize;
+        let street_name = names.sample(&mut rng);
+        let chosen = neighborhood.choose(&mut rng).unwrap();
+        let neighborhood_id = chosen.ID_BAIRRO;
+
+        let state = city_states
+            .get(&chosen.ID_CIDADE)
+            .copied()
+            .unwrap_or(DEFAULT_STATE);
+        let Some(cep) = (0..CEP_ATTEMPTS * 2)
+            .filter_map(|attempt| {
+                ranges.sample(state, chosen.ID_CIDADE, attempt >= CEP_ATTEMPTS, &mut rng)
+            })
+            .find(|cep| used.insert(*cep))
+        else {
+            bail!(
+                "no unused CEP left for city {} in {} after {} draws: its CEP ranges are \
+                 nearly exhausted, lower total_rows",
+                chosen.ID_CIDADE,
+                state,
+                CEP_ATTEMPTS * 2
+            );
+        };
 
         let address_data: T_RHSTU_LOGRADOURO = T_RHSTU_LOGRADOURO {
             ID_LOGRADOURO: i.try_into().expect("cant fit into u64"),
-            ID_BAIRRO: neighborhood_id.try_into().expect("cant fit into usize"),
+            ID_BAIRRO: neighborhood_id,
             NM_LOGRADOURO: street_name,
-            NR_CEP: random_cep(),
+            NR_CEP: render_cep(cep),
             DT_CADASTRO: current_timestamp(),
             NM_USUARIO: CREATED_BY.to_string(),
         };
@@ -258,6 +371,66 @@ pub(crate) fn generate_address(
     Ok(addresses)
 }
 
+// State and DDD assumed for people and hospitals whose address could not be resolved
+pub(crate) const DEFAULT_STATE: &str = "SP";
+pub(crate) const DEFAULT_DDD: u64 = 11;
+
+// Federative unit (e.g. "SP") of every street, indexed like `streets`
+pub(crate) fn street_states(
+    streets: &[T_RHSTU_LOGRADOURO],
+    neighborhoods: &[T_RHSTU_BAIRRO],
+    cities: &[T_RHSTU_CIDADE],
+    states: &[T_RHSTU_ESTADO],
+) -> Vec<Option<String>> {
+    let neighborhood_cities: HashMap<u64, u64> = neighborhoods
+        .iter()
+        .map(|neighborhood| (neighborhood.ID_BAIRRO, neighborhood.ID_CIDADE))
+        .collect();
+    let city_states: HashMap<u64, u64> = cities
+        .iter()
+        .map(|city| (city.ID_CIDADE, city.ID_ESTADO))
+        .collect();
+    let abbreviations: HashMap<u64, &str> = states
+        .iter()
+        .map(|state| (state.ID_ESTADO, state.SG_ESTADO.as_str()))
+        .collect();
+
+    streets
+        .iter()
+        .map(|street| {
+            let city = neighborhood_cities.get(&street.ID_BAIRRO)?;
+            let state = city_states.get(city)?;
+            abbreviations
+                .get(state)
+                .map(|abbreviation| abbreviation.to_string())
+        })
+        .collect()
+}
+
+// DDD of the city of every street, indexed like `streets`
+pub(crate) fn street_ddds(
+    streets: &[T_RHSTU_LOGRADOURO],
+    neighborhoods: &[T_RHSTU_BAIRRO],
+    cities: &[T_RHSTU_CIDADE],
+) -> Vec<Option<u64>> {
+    let neighborhood_cities: HashMap<u64, u64> = neighborhoods
+        .iter()
+        .map(|neighborhood| (neighborhood.ID_BAIRRO, neighborhood.ID_CIDADE))
+        .collect();
+    let ddds: HashMap<u64, u64> = cities
+        .iter()
+        .map(|city| (city.ID_CIDADE, city.NR_DDD))
+        .collect();
+
+    streets
+        .iter()
+        .map(|street| {
+            ddds.get(neighborhood_cities.get(&street.ID_BAIRRO)?)
+                .copied()
+        })
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Regiao {
     id: u64,
@@ -1,116 +1,374 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use fake::{
-    faker::{company::en::CompanyName, name::en::Name},
-    Fake,
-};
+use anyhow::bail;
+use chrono::{Duration, NaiveDateTime};
+use fake::{faker::name::en::Name, Fake};
 use indicatif::{MultiProgress, ProgressBar};
-use rand::{seq::SliceRandom, Rng};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
 
 use crate::{
-    common::{current_timestamp, random_br_phone, random_cnpj, ProgressBarHelper},
+    common::{
+        current_timestamp, parse_to_date, random_br_phone, random_cnpj, to_date, ProgressBarHelper,
+    },
+    config::profile,
     define_and_impl_sql_insertable,
     sql_generator::SqlGenerator,
+    tables::patient::{owners, runs, T_RHSTU_PACIENTE},
 };
 
-// - T_RHSTU_PLANO_SAUDE - "ID_PLANO_SAUDE","DS_RAZAO_SOCIAL","NM_FANTASIA_PLANO_SAUDE","DS_PLANO_SAUDE","NR_CNPJ","NM_CONTATO","DS_TELEFONE","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
+// - T_RHSTU_PLANO_SAUDE - "ID_PLANO_SAUDE","DS_RAZAO_SOCIAL","NM_FANTASIA_PLANO_SAUDE","DS_PLANO_SAUDE","NR_REGISTRO_ANS","DS_SEGMENTACAO","DS_ACOMODACAO","NR_CNPJ","NM_CONTATO","DS_TELEFONE","DT_INICIO","DT_FIM","DT_CADASTRO","NM_USUARIO"
 define_and_impl_sql_insertable!(
     T_RHSTU_PLANO_SAUDE {
         ID_PLANO_SAUDE: u64,
-        DS_RAZAO_SOCIAL: String,
-        NM_FANTASIA_PLANO_SAUDE: String,
-        DS_PLANO_SAUDE: String,
-        NR_CNPJ: String,
-        NM_CONTATO: String,
+        DS_RAZAO_SOCIAL: String[150],
+        NM_FANTASIA_PLANO_SAUDE: String[80],
+        DS_PLANO_SAUDE: String[80],
+        NR_REGISTRO_ANS: String[6],
+        DS_SEGMENTACAO: String[60],
+        DS_ACOMODACAO: String[20],
+        NR_CNPJ: String[14],
+        NM_CONTATO: String[100],
         DS_TELEFONE: u64,
         DT_INICIO: String,
         DT_FIM: String,
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String[100]
     },
     T_RHSTU_PACIENTE_PLANO_SAUDE {
         ID_PACIENTE_PS: u64,
         ID_PACIENTE: u64,
         ID_PLANO_SAUDE: u64,
-        NR_CARTEIRA_PS: String,
+        NR_CARTEIRA_PS: String[20],
         DT_INICIO: String,
         DT_FIM: String,
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String[100]
     }
 );
 
+// Health plan operators with their ANS registration, the format of their card numbers
+// and their share of beneficiaries
+const OPERADORAS_CSV: &str = include_str!("../../catalogs/operadoras.csv");
+
+// Products sold by each operator, with their coverage and their share of the
+// operator's beneficiaries
+const PLANOS_SAUDE_CSV: &str = include_str!("../../catalogs/planos_saude.csv");
+
+// TISS guides carry card numbers of at most 20 characters
+const MAX_CARD_LENGTH: usize = 20;
+
+// Share of patients whose latest enrollment is still in force
+const ACTIVE_SHARE: f64 = 0.8;
+
+// Longest gap between the end of an enrollment and the start of the next one, and
+// shortest enrollment followed by another
+const MAX_GAP_DAYS: i64 = 90;
+const MIN_ENROLLMENT_DAYS: i64 = 1;
+
+// Card numbers drawn for an enrollment before giving up on finding an unused one
+const CARD_ATTEMPTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct OperatorEntry {
+    NR_REGISTRO_ANS: String,
+    DS_RAZAO_SOCIAL: String,
+    NM_FANTASIA: String,
+    DS_FORMATO_CARTEIRA: String,
+    PESO: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ProductEntry {
+    NR_REGISTRO_ANS: String,
+    DS_PLANO_SAUDE: String,
+    DS_SEGMENTACAO: String,
+    DS_ACOMODACAO: String,
+    PESO: f64,
+}
+
+struct Operator {
+    card_format: String,
+    plans: Vec<usize>,
+    weights: WeightedIndex<f64>,
+}
+
+pub(crate) struct Catalog {
+    plans: Vec<T_RHSTU_PLANO_SAUDE>,
+    operators: Vec<Operator>,
+    weights: WeightedIndex<f64>,
+}
+
+impl Catalog {
+    // One plan row per product, sharing the CNPJ and contact of its operator. Products
+    // are on sale since the start of the enrollment window.
+    fn load(since: NaiveDateTime) -> Result<Catalog, anyhow::Error> {
+        let operators: Vec<OperatorEntry> = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(OPERADORAS_CSV.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        let products: Vec<ProductEntry> = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(PLANOS_SAUDE_CSV.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+
+        let mut registries = HashMap::new();
+        for (i, operator) in operators.iter().enumerate() {
+            if registries
+                .insert(operator.NR_REGISTRO_ANS.as_str(), i)
+                .is_some()
+            {
+                bail!("operator {} is listed twice", operator.NR_REGISTRO_ANS);
+            }
+            if operator.DS_FORMATO_CARTEIRA.chars().count() > MAX_CARD_LENGTH {
+                bail!(
+                    "operator {}: card numbers are longer than {} characters",
+                    operator.NR_REGISTRO_ANS,
+                    MAX_CARD_LENGTH
+                );
+            }
+        }
+
+        let mut by_operator: Vec<Vec<(usize, f64)>> = vec![Vec::new(); operators.len()];
+        for (i, product) in products.iter().enumerate() {
+            let Some(&operator) = registries.get(product.NR_REGISTRO_ANS.as_str()) else {
+                bail!(
+                    "{}: operator {} is not listed",
+                    product.DS_PLANO_SAUDE,
+                    product.NR_REGISTRO_ANS
+                );
+            };
+            by_operator[operator].push((i, product.PESO));
+        }
+
+        let contacts: Vec<(String, String, u64)> = operators
+            .iter()
+            .map(|_| (random_cnpj(), Name().fake(), random_br_phone(false)))
+            .collect();
+        let plans = products
+            .into_iter()
+            .enumerate()
+            .map(|(i, product)| {
+                let operator = registries[product.NR_REGISTRO_ANS.as_str()];
+                let (cnpj, contact, phone) = &contacts[operator];
+                T_RHSTU_PLANO_SAUDE {
+                    ID_PLANO_SAUDE: i as u64,
+                    DS_RAZAO_SOCIAL: operators[operator].DS_RAZAO_SOCIAL.clone(),
+                    NM_FANTASIA_PLANO_SAUDE: operators[operator].NM_FANTASIA.clone(),
+                    DS_PLANO_SAUDE: product.DS_PLANO_SAUDE,
+                    NR_REGISTRO_ANS: product.NR_REGISTRO_ANS,
+                    DS_SEGMENTACAO: product.DS_SEGMENTACAO,
+                    DS_ACOMODACAO: product.DS_ACOMODACAO,
+                    NR_CNPJ: cnpj.clone(),
+                    NM_CONTATO: contact.clone(),
+                    DS_TELEFONE: *phone,
+                    DT_INICIO: to_date(since),
+                    DT_FIM: String::new(),
+                    DT_CADASTRO: current_timestamp(),
+                    NM_USUARIO: Name().fake(),
+                }
+            })
+            .collect();
+
+        let weights = WeightedIndex::new(operators.iter().map(|operator| operator.PESO))?;
+        let operators = operators
+            .into_iter()
+            .zip(by_operator)
+            .map(|(operator, products)| {
+                if products.is_empty() {
+                    bail!("operator {} sells no plans", operator.NR_REGISTRO_ANS);
+                }
+                Ok(Operator {
+                    card_format: operator.DS_FORMATO_CARTEIRA,
+                    weights: WeightedIndex::new(products.iter().map(|(_, weight)| *weight))?,
+                    plans: products.into_iter().map(|(plan, _)| plan).collect(),
+                })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        Ok(Catalog {
+            plans,
+            operators,
+            weights,
+        })
+    }
+
+    // An operator drawn by its share of beneficiaries, then one of its plans
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> (&Operator, &T_RHSTU_PLANO_SAUDE) {
+        let operator = &self.operators[self.weights.sample(rng)];
+        let plan = &self.plans[operator.plans[operator.weights.sample(rng)]];
+        (operator, plan)
+    }
+}
+
+// Enrollment periods of every patient, to tell whether a plan covered them on a date
+pub(crate) struct Coverage {
+    periods: HashMap<u64, Vec<(u64, NaiveDateTime, Option<NaiveDateTime>)>>,
+}
+
+impl Coverage {
+    pub(crate) fn new(enrollments: &[T_RHSTU_PACIENTE_PLANO_SAUDE]) -> Coverage {
+        let mut periods: HashMap<u64, Vec<_>> = HashMap::new();
+        for enrollment in enrollments {
+            if let Some(start) = parse_to_date(&enrollment.DT_INICIO) {
+                periods.entry(enrollment.ID_PACIENTE).or_default().push((
+                    enrollment.ID_PACIENTE_PS,
+                    start,
+                    parse_to_date(&enrollment.DT_FIM),
+                ));
+            }
+        }
+        Coverage { periods }
+    }
+
+    // Enrollment (ID_PACIENTE_PS) covering `patient` on `date`
+    pub(crate) fn covers(&self, patient: u64, date: NaiveDateTime) -> Option<u64> {
+        self.periods
+            .get(&patient)?
+            .iter()
+            .find(|(_, start, end)| *start <= date && end.is_none_or(|end| date <= end))
+            .map(|(enrollment, _, _)| *enrollment)
+    }
+}
+
+// Modulo 11 check digit of `digits`, weighted 2 to 9 from the rightmost one
+fn check_digit(digits: &[u32]) -> u32 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .zip((2..=9).cycle())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    match 11 - sum % 11 {
+        10 | 11 => 0,
+        digit => digit,
+    }
+}
+
+// A card number in an operator's format: `#` is a random digit, `V` the check digit of
+// the digits before it and anything else is kept as is, e.g. "865.####.######.##-V"
+fn card_number<R: Rng + ?Sized>(format: &str, rng: &mut R) -> String {
+    let mut card = String::with_capacity(format.len());
+    let mut digits = Vec::new();
+    for c in format.chars() {
+        let digit = match c {
+            '#' => rng.gen_range(0..10),
+            'V' => check_digit(&digits),
+            c => {
+                digits.extend(c.to_digit(10));
+                card.push(c);
+                continue;
+            }
+        };
+        digits.push(digit);
+        card.extend(char::from_digit(digit, 10));
+    }
+    card
+}
+
 pub(crate) fn generate_health_plans(
-    total: usize,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Vec<T_RHSTU_PLANO_SAUDE> {
-    // println!("Generating health plans...");
-    let mut health_plans: Vec<T_RHSTU_PLANO_SAUDE> = Vec::with_capacity(total);
+) -> Result<Arc<Catalog>, anyhow::Error> {
+    let (since, _) = profile()
+        .table("T_RHSTU_PACIENTE_PLANO_SAUDE")
+        .date_window("DT_INICIO")
+        .bounds();
+    let catalog = Catalog::load(since)?;
 
+    let total = catalog.plans.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Health Plans:".to_string());
     let pb = &pb_helper.pb;
+    pb.inc(total as u64);
+    main_pb.inc(total as u64);
 
-    for i in 0..total {
-        let health_plan = T_RHSTU_PLANO_SAUDE {
-            ID_PLANO_SAUDE: i as u64,
-            DS_RAZAO_SOCIAL: CompanyName().fake(),
-            NM_FANTASIA_PLANO_SAUDE: CompanyName().fake(),
-            DS_PLANO_SAUDE: CompanyName().fake(),
-            NR_CNPJ: random_cnpj(),
-            NM_CONTATO: Name().fake(),
-            DS_TELEFONE: random_br_phone(),
-            DT_INICIO: current_timestamp(),
-            DT_FIM: current_timestamp(),
-            DT_CADASTRO: current_timestamp(),
-            NM_USUARIO: "1".to_string(),
-        };
-
-        health_plans.push(health_plan);
-        pb.inc(1);
-        main_pb.inc(1);
-    }
-
-    let generator = SqlGenerator::new(health_plans.clone());
+    let generator = SqlGenerator::new(catalog.plans.clone());
     let _ = generator.write_to_file(pb);
 
     pb_helper.finish();
 
-    health_plans
+    Ok(Arc::new(catalog))
 }
 
+// Enrollments of each patient, one after the other since their birth: each ends before
+// the next starts, ends after it starts and only the latest may still be in force.
+// Every enrollment gets a card number of its own.
 pub(crate) async fn generate_patient_health_plans(
-    total: usize,
-    health_plans: Vec<T_RHSTU_PLANO_SAUDE>,
-    total_patients: usize,
+    patients: Arc<Vec<T_RHSTU_PACIENTE>>,
+    catalog: Arc<Catalog>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Vec<T_RHSTU_PACIENTE_PLANO_SAUDE> {
-    // println!("Generating patient health plans...");
-    let mut patient_health_plans: Vec<T_RHSTU_PACIENTE_PLANO_SAUDE> = Vec::with_capacity(total);
-    let mut rng = rand::thread_rng();
+) -> Result<Vec<T_RHSTU_PACIENTE_PLANO_SAUDE>, anyhow::Error> {
+    let owners = owners("T_RHSTU_PACIENTE_PLANO_SAUDE", patients.len());
+    let total = owners.len();
+    let (first, last) = profile()
+        .table("T_RHSTU_PACIENTE_PLANO_SAUDE")
+        .date_window("DT_INICIO")
+        .bounds();
 
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Patient Health Plans:".to_string());
     let pb = &pb_helper.pb;
 
-    for i in 0..total {
-        let patient_health_plan = T_RHSTU_PACIENTE_PLANO_SAUDE {
-            ID_PACIENTE_PS: i as u64,
-            ID_PACIENTE: rng.gen_range(1..total_patients) as u64,
-            ID_PLANO_SAUDE: health_plans
-                .choose(&mut rand::thread_rng())
-                .unwrap()
-                .ID_PLANO_SAUDE,
-            NR_CARTEIRA_PS: rng.gen_range(1..100).to_string(),
-            DT_INICIO: current_timestamp(),
-            DT_FIM: current_timestamp(),
-            DT_CADASTRO: current_timestamp(),
-            NM_USUARIO: "1".to_string(),
-        };
+    let mut rng = rand::thread_rng();
+    let mut cards = HashSet::new();
+    let mut patient_health_plans: Vec<T_RHSTU_PACIENTE_PLANO_SAUDE> = Vec::with_capacity(total);
+    for (patient, ids) in runs(&owners) {
+        let start = patients
+            .get(patient as usize)
+            .and_then(|patient| parse_to_date(&patient.DT_NASCIMENTO))
+            .map_or(first, |birth| first.max(birth))
+            .min(last);
+        let seconds = (last - start).num_seconds();
+        let mut starts: Vec<NaiveDateTime> = ids
+            .map(|_| start + Duration::seconds(rng.gen_range(0..=seconds)))
+            .collect();
+        // Far enough apart for each enrollment to end before the next starts
+        let shortest = Duration::days(MIN_ENROLLMENT_DAYS);
+        starts.sort();
+        starts.dedup_by(|next, previous| *next - *previous <= shortest);
+
+        for (i, &start) in starts.iter().enumerate() {
+            let end = match starts.get(i + 1) {
+                Some(&next) => Some(
+                    (next - Duration::days(rng.gen_range(1..=MAX_GAP_DAYS))).max(start + shortest),
+                ),
+                None if rng.gen_bool(ACTIVE_SHARE) => None,
+                None => {
+                    let seconds = (last - start).num_seconds().max(1);
+                    Some(start + Duration::seconds(rng.gen_range(1..=seconds)))
+                }
+            };
+            let (operator, plan) = catalog.sample(&mut rng);
+            let Some(card) = (0..CARD_ATTEMPTS)
+                .map(|_| card_number(&operator.card_format, &mut rng))
+                .find(|card| cards.insert(card.clone()))
+            else {
+                bail!(
+                    "no unused card number left for operator {} after {} draws: its card \
+                     format is nearly exhausted, lower total_rows",
+                    plan.NR_REGISTRO_ANS,
+                    CARD_ATTEMPTS
+                );
+            };
 
-        patient_health_plans.push(patient_health_plan);
-        pb.inc(1);
-        main_pb.inc(1);
+            patient_health_plans.push(T_RHSTU_PACIENTE_PLANO_SAUDE {
+                ID_PACIENTE_PS: patient_health_plans.len() as u64,
+                ID_PACIENTE: patient,
+                ID_PLANO_SAUDE: plan.ID_PLANO_SAUDE,
+                NR_CARTEIRA_PS: card,
+                DT_INICIO: to_date(start),
+                DT_FIM: end.map(to_date).unwrap_or_default(),
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: "1".to_string(),
+            });
+        }
+        pb.inc(starts.len() as u64);
+        main_pb.inc(starts.len() as u64);
     }
 
     let generator = SqlGenerator::new(patient_health_plans.clone());
@@ -118,5 +376,5 @@ pub(crate) async fn generate_patient_health_plans(
 
     pb_helper.finish();
 
-    patient_health_plans
+    Ok(patient_health_plans)
 }
@@ -1,79 +1,105 @@
-use crate::common::{current_timestamp, random_cpf, random_rg, ProgressBarHelper};
+use crate::common::{
+    current_timestamp, parse_to_date, random_cnh, random_cpf, random_rg, rg_issuer, to_date,
+    ProgressBarHelper,
+};
+use crate::config::profile;
 use crate::define_and_impl_sql_insertable;
 use crate::sql_generator::SqlGenerator;
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime};
 use fake::{
-    faker::{
-        address::en::{BuildingNumber, SecondaryAddress, StreetName},
-        company::en::CompanyName,
-        name::en::Name,
-    },
+    faker::{company::en::CompanyName, name::en::Name},
     Fake,
 };
 use indicatif::{MultiProgress, ProgressBar};
-use rand::{seq::SliceRandom, Rng};
+use rand::{
+    distributions::WeightedIndex,
+    prelude::Distribution,
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
 
-use super::geography::T_RHSTU_LOGRADOURO;
+use super::geography::{DEFAULT_STATE, T_RHSTU_LOGRADOURO};
+use super::specialty::Catalog;
+use super::street::{building_complement, building_number, reference_point};
 
 define_and_impl_sql_insertable!(
     T_RHSTU_UNID_HOSPITALAR {
         ID_UNID_HOSPITAL: u64,
-        NM_UNID_HOSPITALAR: String,
-        NM_RAZAO_SOCIAL_UNID_HOSP: String,
+        NM_UNID_HOSPITALAR: String [150],
+        NM_RAZAO_SOCIAL_UNID_HOSP: String [150],
+        NR_CNES: String [7],
         DT_FUNDACAO: String,
         NR_LOGRADOURO: u64,
-        DS_COMPLEMENTO_NUMERO: String,
-        DS_PONTO_REFERENCIA: String,
+        DS_COMPLEMENTO_NUMERO: String [50],
+        DS_PONTO_REFERENCIA: String [100],
         DT_INICIO: String,
         DT_TERMINO: String,
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_ENDERECO_UNIDHOSP {
         ID_END_UNIDHOSP: u64,
         ID_UNID_HOSPITAL: u64,
         ID_LOGRADOURO: u64,
         NR_LOGRADOURO: u64,
-        DS_COMPLEMENTO_NUMERO: String,
-        DS_PONTO_REFERENCIA: String,
+        DS_COMPLEMENTO_NUMERO: String [50],
+        DS_PONTO_REFERENCIA: String [100],
         DT_INICIO: String,
         DT_FIM: String,
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_FUNCIONARIO {
-        ID_FUNC: u64,
-        ID_SUPERIOR: u64,
-        NM_FUNC: String,
-        DS_CARGO: String,
-        DT_NASCIMENTO: String,
-        VL_SALARIO: u64,
-        NR_RG: String,
-        NR_CPF: u64,
-        ST_FUNC: String,
-        DT_CADASTRO: String,
-        NM_USUARIO: String
+        pub(crate) ID_FUNC: u64,
+        pub(crate) ID_SUPERIOR: u64,
+        pub(crate) NM_FUNC: String [100],
+        pub(crate) DS_CARGO: String [50],
+        pub(crate) DT_NASCIMENTO: String,
+        pub(crate) VL_SALARIO: u64,
+        pub(crate) NR_RG: String [14],
+        pub(crate) DS_ORGAO_EMISSOR_RG: String [9],
+        pub(crate) NR_CPF: u64,
+        pub(crate) ST_FUNC: String [1],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_FUNCIONARIO_UNIDHOSP {
+        pub(crate) ID_FUNC_UNIDHOSP: u64,
+        pub(crate) ID_FUNC: u64,
+        pub(crate) ID_UNID_HOSPITAL: u64,
+        pub(crate) DT_INICIO: String,
+        pub(crate) DT_FIM: String,
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_MEDICO {
-        ID_FUNC: u64,
-        NR_CRM: u64,
-        DS_ESPECIALIDADE: String,
-        DT_CADASTRO: String,
-        NM_USUARIO: String
+        pub(crate) ID_FUNC: u64,
+        pub(crate) NR_CRM: String [9],
+        pub(crate) DS_ESPECIALIDADE: String [80],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_MOTORISTA {
-        ID_FUNC: u64,
-        NR_CNH: u64,
-        NM_CATEGORIA_CNH: String,
-        DT_VALIDADE_CNH: String,
-        DT_CADASTRO: String,
-        NM_USUARIO: String
+        pub(crate) ID_FUNC: u64,
+        pub(crate) NR_CNH: String [11],
+        pub(crate) NM_CATEGORIA_CNH: String [2],
+        pub(crate) DT_PRIMEIRA_HABILITACAO: String,
+        pub(crate) DT_EMISSAO_CNH: String,
+        pub(crate) DT_VALIDADE_CNH: String,
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
     }
 );
 
+// Hospitals repeat the number, complement and reference point of their address
 pub(crate) async fn generate_hospital(
     total: usize,
+    addresses: Vec<T_RHSTU_ENDERECO_UNIDHOSP>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> usize {
@@ -84,16 +110,25 @@ pub(crate) async fn generate_hospital(
 
     let mut hospitals: Vec<T_RHSTU_UNID_HOSPITALAR> = Vec::new();
     let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_UNID_HOSPITALAR");
+    // Seven-digit CNES registrations, one per hospital
+    let cnes = rand::seq::index::sample(&mut rng, 9_000_000, total);
 
     for i in 0..total {
+        let address = addresses.get(i);
         let hospital = T_RHSTU_UNID_HOSPITALAR {
             ID_UNID_HOSPITAL: i as u64,
             NM_UNID_HOSPITALAR: CompanyName().fake(),
             NM_RAZAO_SOCIAL_UNID_HOSP: CompanyName().fake(),
-            DT_FUNDACAO: current_timestamp(),
-            NR_LOGRADOURO: rng.gen_range(1..1000) as u64,
-            DS_COMPLEMENTO_NUMERO: SecondaryAddress().fake(),
-            DS_PONTO_REFERENCIA: SecondaryAddress().fake(),
+            NR_CNES: (1_000_000 + cnes.index(i)).to_string(),
+            DT_FUNDACAO: table.date("DT_FUNDACAO", &mut rng),
+            NR_LOGRADOURO: address.map_or(0, |address| address.NR_LOGRADOURO),
+            DS_COMPLEMENTO_NUMERO: address
+                .map(|address| address.DS_COMPLEMENTO_NUMERO.clone())
+                .unwrap_or_default(),
+            DS_PONTO_REFERENCIA: address
+                .map(|address| address.DS_PONTO_REFERENCIA.clone())
+                .unwrap_or_default(),
             DT_INICIO: current_timestamp(),
             DT_TERMINO: current_timestamp(),
             DT_CADASTRO: current_timestamp(),
@@ -115,14 +150,13 @@ pub(crate) async fn generate_hospital(
     len
 }
 
+// Hospital h stands on street h, at a number within the street's length
 pub(crate) async fn generate_hospital_address(
     total: usize,
     address: Vec<T_RHSTU_LOGRADOURO>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> usize {
-    let mut len = 0;
-
+) -> Vec<T_RHSTU_ENDERECO_UNIDHOSP> {
     let mut rng = rand::thread_rng();
 
     let pb_helper = ProgressBarHelper::new(m, total * 2, "T_RHSTU_ENDERECO_UNIDHOSP:".to_string());
@@ -130,14 +164,16 @@ pub(crate) async fn generate_hospital_address(
 
     let mut hospitals_addresses: Vec<T_RHSTU_ENDERECO_UNIDHOSP> = Vec::new();
 
+    #[allow(clippy::needless_range_loop)]
     for i in 0..total {
+        let street = &address[i];
         let hospital_address = T_RHSTU_ENDERECO_UNIDHOSP {
             ID_UNID_HOSPITAL: i as u64,
             ID_END_UNIDHOSP: i as u64,
-            ID_LOGRADOURO: address[i].ID_LOGRADOURO,
-            NR_LOGRADOURO: rng.gen_range(1..1000) as u64,
-            DS_COMPLEMENTO_NUMERO: BuildingNumber().fake(),
-            DS_PONTO_REFERENCIA: BuildingNumber().fake(),
+            ID_LOGRADOURO: street.ID_LOGRADOURO,
+            NR_LOGRADOURO: building_number(street, &mut rng),
+            DS_COMPLEMENTO_NUMERO: building_complement(&mut rng),
+            DS_PONTO_REFERENCIA: reference_point(&mut rng),
             DT_INICIO: current_timestamp(),
             DT_FIM: current_timestamp(),
             DT_CADASTRO: current_timestamp(),
@@ -146,44 +182,198 @@ pub(crate) async fn generate_hospital_address(
 
         hospitals_addresses.push(hospital_address);
 
-        len += 1;
         pb.inc(1);
         main_pb.inc(1);
     }
 
-    let generator = SqlGenerator::new(hospitals_addresses);
+    let generator = SqlGenerator::new(hospitals_addresses.clone());
     let _ = generator.write_to_file(pb);
 
     pb_helper.finish();
 
-    len
+    hospitals_addresses
+}
+
+// Job titles with their level in the org chart (0 for the head of a hospital, 4 for
+// staff without reports), salary band and weight within the level
+const CARGOS_CSV: &str = include_str!("../../catalogs/cargos.csv");
+
+const STAFF_LEVEL: u64 = 4;
+
+// Titles of the employees taken as doctors and ambulance drivers
+pub(crate) const DOCTOR: &str = "Médico";
+pub(crate) const DRIVER: &str = "Motorista de Ambulância";
+
+// Registration numbers handed out by each CRM
+const CRM_NUMBERS: Range<u64> = 1_000..300_000;
+
+// Minimum age for a first CNH, and the age and years licensed required for each
+// category (CTB arts. 140, 143 and 145)
+const CNH_MINIMUM_AGE: u32 = 18;
+
+// Years after coming of age within which drivers take their first licence
+const CNH_FIRST_LICENCE_YEARS: u32 = 7;
+const CNH_REQUIREMENTS: [(char, i32, i32); 4] =
+    [('B', 18, 0), ('C', 18, 1), ('D', 21, 2), ('E', 21, 3)];
+
+// Day the renewal periods of Lei 14.071/2020 took effect
+const RENEWAL_RULES_CHANGE: NaiveDate = match NaiveDate::from_ymd_opt(2021, 4, 12) {
+    Some(date) => date,
+    None => panic!("invalid date"),
+};
+
+// Minimum age at hiring
+const HIRING_AGE: i64 = 18;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct JobTitle {
+    DS_CARGO: String,
+    NR_NIVEL: u64,
+    VL_SALARIO_MIN: u64,
+    VL_SALARIO_MAX: u64,
+    PESO: f64,
+}
+
+fn job_titles() -> Vec<JobTitle> {
+    csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .from_reader(CARGOS_CSV.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("invalid job title catalog")
 }
 
+// Position in its hospital's tree of the first node at `depth`, when every manager
+// has `span` reports
+fn level_start(span: usize, depth: usize) -> usize {
+    (0..depth)
+        .fold((0usize, 1usize), |(start, width), _| {
+            (start.saturating_add(width), width.saturating_mul(span))
+        })
+        .0
+}
+
+// Position of the superior of `position`, and its own depth. Nodes that would sit
+// deeper than `max_depth` are spread over the managers of the last managerial level.
+fn superior(position: usize, span: usize, max_depth: usize) -> (usize, usize) {
+    let last = level_start(span, max_depth - 1);
+    if position >= last {
+        let managers = level_start(span, max_depth - 2);
+        let count = last - managers;
+        return (managers + (position - last) % count, max_depth - 1);
+    }
+    let depth = (1..max_depth)
+        .find(|depth| position < level_start(span, *depth))
+        .unwrap_or(max_depth)
+        - 1;
+    ((position.max(1) - 1) / span, depth)
+}
+
+// Builds one org chart per hospital: employee `i` works at hospital `i % n`, the first
+// of each hospital heads it and everyone else reports to a superior of the same
+// hospital. Managers get a title of their level and the staff without reports are given
+// exactly `doctors` doctor and `drivers` driver positions, the rest by title weight.
 pub(crate) async fn generate_employee(
     total: usize,
+    doctors: usize,
+    drivers: usize,
+    total_hospitals: usize,
+    street_states: &[Option<String>],
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Vec<u64> {
+) -> Vec<T_RHSTU_FUNCIONARIO> {
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Employees:".to_string());
     let pb = &pb_helper.pb;
 
+    let mut rng = rand::thread_rng();
+    let org = profile().employees();
+    let hospitals = total_hospitals.max(1);
+    let titles = job_titles();
+
+    // Superior and depth of every employee
+    let tree: Vec<(u64, usize)> = (0..total)
+        .map(|i| {
+            let (hospital, position) = (i % hospitals, i / hospitals);
+            if position == 0 {
+                // Heads of a hospital are their own superior
+                return (i as u64, 0);
+            }
+            let (parent, depth) = superior(position, org.span_of_control, org.max_depth);
+            ((parent * hospitals + hospital) as u64, depth)
+        })
+        .collect();
+    // Whether every employee has reports, and whether any of those manage in turn
+    let mut managers = vec![false; total];
+    let mut middle = vec![false; total];
+    for (i, (parent, _)) in tree.iter().enumerate() {
+        if *parent != i as u64 {
+            managers[*parent as usize] = true;
+        }
+    }
+    for (i, (parent, _)) in tree.iter().enumerate() {
+        if *parent != i as u64 && managers[i] {
+            middle[*parent as usize] = true;
+        }
+    }
+
+    // Title of every employee, as an index into `titles`
+    let by_level = |level: u64| -> (Vec<usize>, WeightedIndex<f64>) {
+        let indices: Vec<usize> = (0..titles.len())
+            .filter(|i| titles[*i].NR_NIVEL == level && titles[*i].PESO > 0.0)
+            .collect();
+        let weights = WeightedIndex::new(indices.iter().map(|i| titles[*i].PESO))
+            .expect("every level needs a weighted title");
+        (indices, weights)
+    };
+    let levels: Vec<_> = (0..=STAFF_LEVEL).map(by_level).collect();
+    let pick = |level: u64, rng: &mut rand::rngs::ThreadRng| {
+        let (indices, weights) = &levels[level as usize];
+        indices[weights.sample(rng)]
+    };
+    let title_index = |name: &str| {
+        titles
+            .iter()
+            .position(|title| title.DS_CARGO == name)
+            .unwrap_or_else(|| panic!("{} missing from the job titles", name))
+    };
+
+    let mut staff: Vec<usize> = (0..total).filter(|i| !managers[*i]).collect();
+    if doctors + drivers > staff.len() {
+        panic!("Not enough employees to generate doctors and drivers");
+    }
+    staff.shuffle(&mut rng);
+
+    // Heads and directors by depth; further down, managers of managers are managers
+    // and those leading only staff are supervisors
+    let mut assigned: Vec<usize> = (0..total)
+        .map(|i| match tree[i].1 {
+            0 => pick(0, &mut rng),
+            1 => pick(1, &mut rng),
+            _ if middle[i] => pick(2, &mut rng),
+            _ => pick(3, &mut rng),
+        })
+        .collect();
+    let (doctor, driver) = (title_index(DOCTOR), title_index(DRIVER));
+    for (n, i) in staff.into_iter().enumerate() {
+        assigned[i] = match n {
+            n if n < doctors => doctor,
+            n if n < doctors + drivers => driver,
+            _ => pick(STAFF_LEVEL, &mut rng),
+        };
+    }
+
     let employees: Vec<T_RHSTU_FUNCIONARIO> = (0..total)
         .into_par_iter()
         .map(|i| {
-            let mut rng = rand::thread_rng();
-            let employee = T_RHSTU_FUNCIONARIO {
-                ID_FUNC: i as u64,
-                ID_SUPERIOR: i as u64,
-                NM_FUNC: Name().fake(),
-                DS_CARGO: Name().fake(),
-                DT_NASCIMENTO: current_timestamp(),
-                VL_SALARIO: rng.gen_range(1000.0..10000.0) as u64,
-                NR_RG: random_rg().to_string(),
-                NR_CPF: random_cpf(),
-                ST_FUNC: ["A", "I"].choose(&mut rng).unwrap().to_string(),
-                DT_CADASTRO: current_timestamp(),
-                NM_USUARIO: Name().fake(),
-            };
+            let employee = employee_row(
+                i as u64,
+                tree[i].0,
+                &titles[assigned[i]],
+                street_states,
+                hospitals,
+                &mut rand::thread_rng(),
+            );
 
             pb.inc(1);
             main_pb.inc(1);
@@ -197,32 +387,200 @@ pub(crate) async fn generate_employee(
 
     pb_helper.finish();
 
-    employees.into_par_iter().map(|e| e.ID_FUNC).collect()
+    employees
 }
 
-pub(crate) async fn generate_doctor(
-    employee_ids: Arc<Mutex<Vec<u64>>>,
-    total: usize,
+// Employee `id` of the hospital `id % hospitals`. RGs are issued in the state of the
+// hospital, which stands on the street of the same index.
+fn employee_row(
+    id: u64,
+    superior: u64,
+    title: &JobTitle,
+    street_states: &[Option<String>],
+    hospitals: usize,
+    rng: &mut impl Rng,
+) -> T_RHSTU_FUNCIONARIO {
+    let table = profile().table("T_RHSTU_FUNCIONARIO");
+    let state = street_states
+        .get(id as usize % hospitals)
+        .and_then(|state| state.as_deref())
+        .unwrap_or(DEFAULT_STATE);
+    T_RHSTU_FUNCIONARIO {
+        ID_FUNC: id,
+        ID_SUPERIOR: superior,
+        NM_FUNC: Name().fake(),
+        DS_CARGO: title.DS_CARGO.clone(),
+        DT_NASCIMENTO: table.date("DT_NASCIMENTO", rng),
+        // Rounded to tens of reais
+        VL_SALARIO: rng.gen_range(title.VL_SALARIO_MIN..=title.VL_SALARIO_MAX) / 10 * 10,
+        NR_RG: random_rg(state),
+        DS_ORGAO_EMISSOR_RG: rg_issuer(state),
+        NR_CPF: random_cpf(),
+        ST_FUNC: table.pick("ST_FUNC", rng),
+        DT_CADASTRO: current_timestamp(),
+        NM_USUARIO: Name().fake(),
+    }
+}
+
+// Allocates every employee to the hospital of their org chart from their hiring date,
+// ending it for inactive employees. A share of the doctors also works at a second unit.
+pub(crate) async fn generate_employee_allocations(
+    employees: &[T_RHSTU_FUNCIONARIO],
+    total_hospitals: usize,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> usize {
-    let mut employee_ids_guard = employee_ids.lock().unwrap();
+) -> Vec<T_RHSTU_FUNCIONARIO_UNIDHOSP> {
+    let mut rng = rand::thread_rng();
+    let org = profile().employees();
+    let hospitals = total_hospitals.max(1);
+
+    let pb_helper =
+        ProgressBarHelper::new(m, employees.len() * 2, "Employee Allocations:".to_string());
+    let pb = &pb_helper.pb;
 
-    if total > employee_ids_guard.len() {
-        panic!("Not enough employees to generate doctors");
+    let mut allocations = Vec::with_capacity(employees.len());
+    for employee in employees {
+        let hospital = employee.ID_FUNC % hospitals as u64;
+        allocations.push(allocation_row(
+            allocations.len() as u64,
+            employee,
+            hospital,
+            &mut rng,
+        ));
+        if employee.DS_CARGO == DOCTOR && hospitals > 1 && rng.gen_bool(org.second_hospital) {
+            let other = (hospital + rng.gen_range(1..hospitals) as u64) % hospitals as u64;
+            allocations.push(allocation_row(
+                allocations.len() as u64,
+                employee,
+                other,
+                &mut rng,
+            ));
+            main_pb.inc(1);
+        }
+
+        pb.inc(1);
+        main_pb.inc(1);
     }
 
+    let generator = SqlGenerator::new(allocations.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    allocations
+}
+
+// Allocation `id` of `employee` to `hospital`, starting once they're of hiring age
+fn allocation_row(
+    id: u64,
+    employee: &T_RHSTU_FUNCIONARIO,
+    hospital: u64,
+    rng: &mut impl Rng,
+) -> T_RHSTU_FUNCIONARIO_UNIDHOSP {
+    let table = profile().table("T_RHSTU_FUNCIONARIO_UNIDHOSP");
+    let (first, last) = table.date_window("DT_INICIO").bounds();
+    let adult = parse_to_date(&employee.DT_NASCIMENTO).map_or(first, |birth| {
+        birth + Duration::days(HIRING_AGE * 365 + HIRING_AGE / 4)
+    });
+    let from = first.max(adult).min(last);
+    let start = from + Duration::seconds(rng.gen_range(0..=(last - from).num_seconds()));
+    let end = match employee.ST_FUNC.as_str() {
+        "I" => to_date(start + Duration::seconds(rng.gen_range(0..=(last - start).num_seconds()))),
+        _ => String::new(),
+    };
+    T_RHSTU_FUNCIONARIO_UNIDHOSP {
+        ID_FUNC_UNIDHOSP: id,
+        ID_FUNC: employee.ID_FUNC,
+        ID_UNID_HOSPITAL: hospital,
+        DT_INICIO: to_date(start),
+        DT_FIM: end,
+        DT_CADASTRO: to_date(start),
+        NM_USUARIO: Name().fake(),
+    }
+}
+
+// Employees with one title and the periods they're allocated to each hospital
+#[derive(Clone)]
+pub(crate) struct Staff {
+    periods: HashMap<u64, Vec<(u64, NaiveDateTime, Option<NaiveDateTime>)>>,
+}
+
+impl Staff {
+    pub(crate) fn new(
+        employees: &[T_RHSTU_FUNCIONARIO],
+        allocations: &[T_RHSTU_FUNCIONARIO_UNIDHOSP],
+        title: &str,
+    ) -> Self {
+        let mut periods: HashMap<u64, Vec<_>> = HashMap::new();
+        for allocation in allocations {
+            let Some(start) = parse_to_date(&allocation.DT_INICIO) else {
+                continue;
+            };
+            if employees[allocation.ID_FUNC as usize].DS_CARGO == title {
+                periods
+                    .entry(allocation.ID_UNID_HOSPITAL)
+                    .or_default()
+                    .push((allocation.ID_FUNC, start, parse_to_date(&allocation.DT_FIM)));
+            }
+        }
+        Staff { periods }
+    }
+
+    // One of the employees allocated to `hospital` on `date`
+    pub(crate) fn pick(
+        &self,
+        hospital: u64,
+        date: NaiveDateTime,
+        rng: &mut impl Rng,
+    ) -> Option<u64> {
+        self.periods
+            .get(&hospital)?
+            .iter()
+            .filter(|(_, start, end)| *start <= date && end.is_none_or(|end| date <= end))
+            .map(|(id, _, _)| *id)
+            .choose(rng)
+    }
+}
+
+// Registers every doctor at the regional council (CRM) of the state of the hospital
+// heading their org chart, with a number unique within that state, e.g. "123456/SP"
+pub(crate) async fn generate_doctor(
+    employee_ids: Vec<u64>,
+    street_states: Vec<Option<String>>,
+    specialties: Arc<Catalog>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_MEDICO> {
+    let total = employee_ids.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Doctors:".to_string());
     let pb = &pb_helper.pb;
 
     let mut rng = rand::thread_rng(); // Reuse the random number generator
     let mut doctors = Vec::new(); // For example, a batch size of 1000
+    let hospitals = profile().rows("T_RHSTU_UNID_HOSPITALAR").max(1);
+    let mut registered: HashMap<&str, HashSet<u64>> = HashMap::new();
+
+    for id in employee_ids.iter() {
+        // Hospital `h` stands on street `h`, as in its address row
+        let state = street_states
+            .get(*id as usize % hospitals)
+            .and_then(|state| state.as_deref())
+            .unwrap_or(DEFAULT_STATE);
+        let numbers = registered.entry(state).or_default();
+        if numbers.len() as u64 >= CRM_NUMBERS.end - CRM_NUMBERS.start {
+            panic!("Not enough CRM numbers for the doctors of {}", state);
+        }
+        let number = loop {
+            let number = rng.gen_range(CRM_NUMBERS);
+            if numbers.insert(number) {
+                break number;
+            }
+        };
 
-    for i in 0..total {
         let doctor = T_RHSTU_MEDICO {
-            ID_FUNC: employee_ids_guard[i],
-            NR_CRM: rng.gen_range(1000000..9999999) as u64,
-            DS_ESPECIALIDADE: Name().fake(),
+            ID_FUNC: *id,
+            NR_CRM: format!("{}/{}", number, state),
+            DS_ESPECIALIDADE: specialties.sample(&mut rng).NM_ESPECIALIDADE.clone(),
             DT_CADASTRO: current_timestamp(),
             NM_USUARIO: Name().fake(),
         };
@@ -233,44 +591,111 @@ pub(crate) async fn generate_doctor(
         main_pb.inc(1);
     }
 
-    let generator = SqlGenerator::new(doctors);
+    let generator = SqlGenerator::new(doctors.clone());
     let _ = generator.write_to_file(pb);
 
-    // Remove used employee IDs after the loop to avoid shifting elements multiple times
-    employee_ids_guard.drain(0..total);
-
     pb_helper.finish();
 
-    total
+    doctors
 }
 
-pub(crate) async fn generate_driver(
-    employee_ids: Arc<Mutex<Vec<u64>>>,
-    total: usize,
-    m: Arc<MultiProgress>,
-    main_pb: Arc<ProgressBar>,
-) -> usize {
-    let mut employee_ids_guard = employee_ids.lock().unwrap();
+// Years a CNH issued on `date` to someone aged `age` stays valid (CTB art. 147), before
+// and after the longer periods of Lei 14.071/2020
+fn cnh_validity(age: i32, date: NaiveDateTime) -> u32 {
+    if date < RENEWAL_RULES_CHANGE.and_hms_opt(0, 0, 0).unwrap() {
+        return if age < 65 { 5 } else { 3 };
+    }
+    match age {
+        ..=49 => 10,
+        50..=69 => 5,
+        _ => 3,
+    }
+}
+
+// Whole years between `birth` and `date`
+fn age_at(birth: NaiveDateTime, date: NaiveDateTime) -> i32 {
+    let age = date.year() - birth.year();
+    if (date.month(), date.day()) < (birth.month(), birth.day()) {
+        age - 1
+    } else {
+        age
+    }
+}
+
+fn add_years(date: NaiveDateTime, years: u32) -> NaiveDateTime {
+    date.checked_add_months(Months::new(years * 12))
+        .unwrap_or(date)
+}
 
-    if total > employee_ids_guard.len() {
-        panic!("Not enough employees to generate drivers");
+// Highest letter of `category` the driver may hold given their age and years since
+// their first licence, keeping the motorcycle A alongside it
+fn allowed_category(category: &str, age: i32, licensed: i32) -> String {
+    let motorcycle = category.contains('A');
+    let highest = category
+        .chars()
+        .filter(|letter| *letter != 'A')
+        .max()
+        .map(|letter| {
+            CNH_REQUIREMENTS
+                .iter()
+                .rev()
+                .find(|(candidate, min_age, min_years)| {
+                    *candidate <= letter && age >= *min_age && licensed >= *min_years
+                })
+                .map_or('B', |(candidate, _, _)| *candidate)
+        });
+    match (motorcycle, highest) {
+        (true, Some(letter)) => format!("A{}", letter),
+        (false, Some(letter)) => letter.to_string(),
+        (true, None) => "A".to_string(),
+        (false, None) => "B".to_string(),
     }
+}
 
+// Licenses drivers within a few years of coming of age, inside the first licence
+// window, and renews the CNH on every expiry up to the end of that window. The category
+// is drawn from the profile and lowered when the driver is too young or too recently
+// licensed for it.
+pub(crate) async fn generate_driver(
+    employees: Vec<T_RHSTU_FUNCIONARIO>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_MOTORISTA> {
+    let total = employees.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Drivers:".to_string());
     let pb = &pb_helper.pb;
 
     let mut rng = rand::thread_rng(); // Reuse the random number generator
     let mut drivers = Vec::with_capacity(1000); // For example, a batch size of 1000
+    let table = profile().table("T_RHSTU_MOTORISTA");
+    let (first, last) = table.date_window("DT_PRIMEIRA_HABILITACAO").bounds();
+
+    for employee in employees {
+        let birth = parse_to_date(&employee.DT_NASCIMENTO).unwrap_or(first);
+        let from = add_years(birth, CNH_MINIMUM_AGE).max(first).min(last);
+        let until = add_years(from, CNH_FIRST_LICENCE_YEARS).min(last);
+        let licensed = from + Duration::seconds(rng.gen_range(0..=(until - from).num_seconds()));
+
+        let mut issue = licensed;
+        let mut expiry = add_years(issue, cnh_validity(age_at(birth, issue), issue));
+        while expiry <= last {
+            issue = expiry;
+            expiry = add_years(issue, cnh_validity(age_at(birth, issue), issue));
+        }
+
+        let category = allowed_category(
+            &table.pick("NM_CATEGORIA_CNH", &mut rng),
+            age_at(birth, last),
+            age_at(licensed, last),
+        );
 
-    for i in 0..total {
         let driver = T_RHSTU_MOTORISTA {
-            ID_FUNC: employee_ids_guard[i],
-            NR_CNH: rng.gen_range(1000000..9999999) as u64,
-            NM_CATEGORIA_CNH: ["A", "B", "C", "D", "E"]
-                .choose(&mut rng)
-                .unwrap()
-                .to_string(),
-            DT_VALIDADE_CNH: current_timestamp(),
+            ID_FUNC: employee.ID_FUNC,
+            NR_CNH: random_cnh(),
+            NM_CATEGORIA_CNH: category,
+            DT_PRIMEIRA_HABILITACAO: to_date(licensed),
+            DT_EMISSAO_CNH: to_date(issue),
+            DT_VALIDADE_CNH: to_date(expiry),
             DT_CADASTRO: current_timestamp(),
             NM_USUARIO: Name().fake(),
         };
@@ -281,13 +706,10 @@ pub(crate) async fn generate_driver(
         main_pb.inc(1);
     }
 
-    let generator = SqlGenerator::new(drivers);
+    let generator = SqlGenerator::new(drivers.clone());
     let _ = generator.write_to_file(pb);
 
-    // Remove used employee IDs after the loop to avoid shifting elements multiple times
-    employee_ids_guard.drain(0..total);
-
     pb_helper.finish();
 
-    total
+    drivers
 }
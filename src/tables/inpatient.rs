@@ -0,0 +1,303 @@
+use chrono::{Duration, NaiveDateTime};
+use fake::{faker::name::en::Name, Fake};
+use indicatif::{MultiProgress, ProgressBar};
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use crate::{
+    common::{current_timestamp, parse_to_date, to_date, ProgressBarHelper},
+    config::profile,
+    define_and_impl_sql_insertable,
+    sql_generator::SqlGenerator,
+    tables::{hospital::Staff, patient::T_RHSTU_PACIENTE},
+};
+
+define_and_impl_sql_insertable!(
+    T_RHSTU_ALA {
+        ID_ALA: u64,
+        ID_UNID_HOSPITAL: u64,
+        NM_ALA: String[60],
+        DS_TIPO_ALA: String[40],
+        NR_ANDAR: u64,
+        DT_CADASTRO: String,
+        NM_USUARIO: String[100]
+    },
+    T_RHSTU_LEITO {
+        ID_LEITO: u64,
+        ID_ALA: u64,
+        NR_LEITO: String[10],
+        ST_LEITO: String[1],
+        DT_CADASTRO: String,
+        NM_USUARIO: String[100]
+    },
+    T_RHSTU_INTERNACAO {
+        ID_INTERNACAO: u64,
+        ID_LEITO: u64,
+        ID_PACIENTE: u64,
+        ID_FUNC: u64,
+        DT_ADMISSAO: String,
+        DT_ALTA: String,
+        DS_MOTIVO_ALTA: String[30],
+        DT_CADASTRO: String,
+        NM_USUARIO: String[100]
+    }
+);
+
+// Shortest stay, and time a bed is cleaned between two stays
+const MIN_STAY_HOURS: i64 = 4;
+const TURNOVER_HOURS: i64 = 1;
+
+// Discharge reason of a patient who died during the stay
+const DEATH: &str = "Óbito";
+
+// Patients drawn looking for one born by the admission, and the delay and number of
+// times a stay waits for a doctor or patient before it is left out
+const PATIENT_ATTEMPTS: usize = 10;
+const RETRY_HOURS: i64 = 1;
+const STAY_ATTEMPTS: usize = 48;
+
+// Date of death of the patients who died in hospital
+#[derive(Default)]
+pub(crate) struct Deaths {
+    dates: HashMap<u64, NaiveDateTime>,
+}
+
+impl Deaths {
+    pub(crate) fn date(&self, patient: u64) -> Option<NaiveDateTime> {
+        self.dates.get(&patient).copied()
+    }
+
+    pub(crate) fn alive(&self, patient: u64, date: NaiveDateTime) -> bool {
+        self.date(patient).is_none_or(|death| date < death)
+    }
+}
+
+pub(crate) fn generate_wards(
+    total: usize,
+    total_hospitals: usize,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_ALA> {
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Wards:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_ALA");
+    let mut wards = Vec::with_capacity(total);
+
+    for i in 0..total {
+        // Wards are spread evenly, so hospital `h` gets wards h, h + n, h + 2n...
+        let letter = (b'A' + (i / total_hospitals.max(1) % 26) as u8) as char;
+        let kind = table.pick("DS_TIPO_ALA", &mut rng);
+
+        wards.push(T_RHSTU_ALA {
+            ID_ALA: i as u64,
+            ID_UNID_HOSPITAL: (i % total_hospitals.max(1)) as u64,
+            NM_ALA: format!("Ala {} - {}", letter, kind),
+            DS_TIPO_ALA: kind,
+            NR_ANDAR: rng.gen_range(1..=10),
+            DT_CADASTRO: current_timestamp(),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let generator = SqlGenerator::new(wards.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    wards
+}
+
+pub(crate) fn generate_beds(
+    total: usize,
+    wards: &[T_RHSTU_ALA],
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Vec<T_RHSTU_LEITO> {
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Beds:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_LEITO");
+    let mut beds = Vec::with_capacity(total);
+
+    for i in 0..total {
+        let ward = &wards[i % wards.len()];
+        let number = i / wards.len() + 1;
+
+        beds.push(T_RHSTU_LEITO {
+            ID_LEITO: i as u64,
+            ID_ALA: ward.ID_ALA,
+            NR_LEITO: format!("{}{:02}", ward.NR_ANDAR, number),
+            ST_LEITO: table.pick("ST_LEITO", &mut rng),
+            DT_CADASTRO: current_timestamp(),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let generator = SqlGenerator::new(beds.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    beds
+}
+
+// Exponentially distributed duration with the given mean
+fn exponential<R: Rng + ?Sized>(mean: Duration, rng: &mut R) -> Duration {
+    let u: f64 = rng.gen();
+    Duration::seconds((-(1.0 - u).ln() * mean.num_seconds() as f64) as i64)
+}
+
+// Simulates every active bed over the admission window as alternating stays and
+// vacancies, so a bed never holds two patients and is occupied the configured share
+// of the time. Stays still running at the end of the window have no discharge.
+// Patients are drawn among those born, not admitted at the time and still alive, and
+// doctors among those allocated to the hospital on admission; a stay missing either
+// is tried again later, keeping its discharge, while it can still last the shortest
+// stay. Returns the date of death of the patients who died in hospital.
+pub(crate) async fn generate_admissions(
+    beds: Vec<T_RHSTU_LEITO>,
+    wards: Vec<T_RHSTU_ALA>,
+    doctors: Staff,
+    patients: Arc<Vec<T_RHSTU_PACIENTE>>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Deaths {
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_INTERNACAO");
+    let admissions = profile().admissions();
+    let (start, end) = table.date_window("DT_ADMISSAO").bounds();
+    let mean_stay = admissions.mean_stay();
+
+    let ward_hospitals: HashMap<u64, u64> = wards
+        .iter()
+        .map(|ward| (ward.ID_ALA, ward.ID_UNID_HOSPITAL))
+        .collect();
+
+    // (admission, bed, discharge, attempts) of every stay still to be admitted
+    type Stay = (NaiveDateTime, u64, Option<NaiveDateTime>, usize);
+    let mut pending: BinaryHeap<Reverse<Stay>> = BinaryHeap::new();
+    for bed in beds.iter().filter(|bed| bed.ST_LEITO == "A") {
+        let occupancy = admissions.occupancy(ward_hospitals[&bed.ID_ALA]);
+        if occupancy == 0.0 {
+            continue;
+        }
+        let mean_vacancy = Duration::seconds(
+            (mean_stay.num_seconds() as f64 * (1.0 - occupancy) / occupancy) as i64,
+        );
+
+        let mut time = start + exponential(mean_vacancy, &mut rng);
+        while time < end {
+            let stay = exponential(mean_stay, &mut rng).max(Duration::hours(MIN_STAY_HOURS));
+            let discharge = time + stay;
+            if discharge > end {
+                pending.push(Reverse((time, bed.ID_LEITO, None, 0)));
+                break;
+            }
+            pending.push(Reverse((time, bed.ID_LEITO, Some(discharge), 0)));
+            time =
+                discharge + Duration::hours(TURNOVER_HOURS) + exponential(mean_vacancy, &mut rng);
+        }
+    }
+    let total = pending.len();
+
+    let bed_hospitals: HashMap<u64, u64> = beds
+        .iter()
+        .map(|bed| (bed.ID_LEITO, ward_hospitals[&bed.ID_ALA]))
+        .collect();
+    let births: HashMap<u64, NaiveDateTime> = patients
+        .iter()
+        .filter_map(|patient| Some((patient.ID_PACIENTE, parse_to_date(&patient.DT_NASCIMENTO)?)))
+        .collect();
+
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Admissions:".to_string());
+    let pb = &pb_helper.pb;
+
+    // Patients free to be admitted, and the discharges returning the others to the pool
+    let mut available: Vec<u64> = births.keys().copied().collect();
+    let mut discharges: BinaryHeap<Reverse<(NaiveDateTime, u64)>> = BinaryHeap::new();
+    let mut deaths = Deaths::default();
+    let mut rows = Vec::with_capacity(total);
+    let mut dropped = 0;
+
+    while let Some(Reverse((admission, bed, discharge, attempts))) = pending.pop() {
+        while let Some(Reverse((until, patient))) = discharges.peek().copied() {
+            if until > admission {
+                break;
+            }
+            discharges.pop();
+            available.push(patient);
+        }
+        // Attended by a doctor allocated to the ward's hospital on admission
+        let doctor = doctors.pick(bed_hospitals[&bed], admission, &mut rng);
+        let patient = (0..PATIENT_ATTEMPTS)
+            .filter(|_| !available.is_empty())
+            .map(|_| rng.gen_range(0..available.len()))
+            .find(|&index| births[&available[index]] <= admission);
+        let (Some(doctor), Some(index)) = (doctor, patient) else {
+            let retry = admission + Duration::hours(RETRY_HOURS);
+            let fits = discharge.map_or(retry < end, |discharge| {
+                discharge - retry >= Duration::hours(MIN_STAY_HOURS)
+            });
+            if fits && attempts + 1 < STAY_ATTEMPTS {
+                pending.push(Reverse((retry, bed, discharge, attempts + 1)));
+            } else {
+                dropped += 1;
+                pb.inc(1);
+            }
+            continue;
+        };
+        let patient = available.swap_remove(index);
+
+        let reason = match discharge {
+            Some(_) => table.pick("DS_MOTIVO_ALTA", &mut rng),
+            None => String::new(),
+        };
+        match discharge {
+            Some(discharge) if reason == DEATH => {
+                deaths.dates.insert(patient, discharge);
+            }
+            Some(discharge) => discharges.push(Reverse((discharge, patient))),
+            None => {}
+        }
+
+        rows.push(T_RHSTU_INTERNACAO {
+            ID_INTERNACAO: rows.len() as u64,
+            ID_LEITO: bed,
+            ID_PACIENTE: patient,
+            ID_FUNC: doctor,
+            DT_ADMISSAO: to_date(admission),
+            DT_ALTA: discharge.map(to_date).unwrap_or_default(),
+            DS_MOTIVO_ALTA: reason,
+            DT_CADASTRO: to_date(admission),
+            NM_USUARIO: Name().fake(),
+        });
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+    if dropped > 0 {
+        pb.println(format!(
+            "Admissions: {} of {} stays not generated, no doctor or patient was available \
+             in {} attempts",
+            dropped, total, STAY_ATTEMPTS
+        ));
+    }
+
+    let generator = SqlGenerator::new(rows);
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    deaths
+}
@@ -4,10 +4,11 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::task;
 
-use crate::{bulario::BularioClient, common::{ProgressBarHelper, current_timestamp, StringUtils}, define_and_impl_sql_insertable, sql_generator::SqlGenerator};
+use crate::{bulario::BularioClient, common::{ProgressBarHelper, current_timestamp, unaccent, StringUtils}, define_and_impl_sql_insertable, sql_generator::SqlGenerator, tables::{consultation::T_RHSTU_CONSULTA, diagnosis::chapter_medicines}};
 
 // - T_RHSTU_MEDICAMENTO - "ID_MEDICAMENTO","NM_MEDICAMENTO","DS_DETALHADA_MEDICAMENTO","NR_CODIGO_BARRAS","DT_CADASTRO","NM_USUARIO"
 // - T_RHSTU_PRESCRICAO_MEDICA - "ID_PRESCRICAO_MEDICA","ID_UNID_HOSPITAL","ID_CONSULTA","ID_MEDICAMENTO","DS_POSOLOGIA","DS_VIA","DS_OBSERVACAO_USO","QT_MEDICAMENTO","NM_USUARIO","DT_CADASTRO"
@@ -15,27 +16,28 @@ use crate::{bulario::BularioClient, common::{ProgressBarHelper, current_timestam
 define_and_impl_sql_insertable!(
     T_RHSTU_MEDICAMENTO {
         ID_MEDICAMENTO: u64,
-        NM_MEDICAMENTO: String,
-        DS_DETALHADA_MEDICAMENTO: String,
-        NR_CODIGO_BARRAS: String,
+        NM_MEDICAMENTO: String [50],
+        DS_DETALHADA_MEDICAMENTO: String [4000],
+        NR_CODIGO_BARRAS: String [20],
         DT_CADASTRO: String,
-        NM_USUARIO: String
+        NM_USUARIO: String [100]
     },
     T_RHSTU_PRESCRICAO_MEDICA {
         ID_PRESCRICAO_MEDICA: u64,
         ID_UNID_HOSPITAL: u64,
         ID_CONSULTA: u64,
         ID_MEDICAMENTO: u64,
-        DS_POSOLOGIA: String,
-        DS_VIA: String,
-        DS_OBSERVACAO_USO: String,
+        DS_POSOLOGIA: String [500],
+        DS_VIA: String [50],
+        DS_OBSERVACAO_USO: String [500],
         QT_MEDICAMENTO: u64,
-        NM_USUARIO: String,
+        NM_USUARIO: String [100],
         DT_CADASTRO: String
     }
 );
 
 #[derive(Deserialize, Debug, Serialize)]
+#[allow(dead_code)]
 struct MedicineCategories {
     id: u64,
     descricao: String,
@@ -50,6 +52,7 @@ pub(crate) async fn get_medicines(
 
     let client = Arc::new(BularioClient::new());
     let mut categories = client.fetch_categories().await.expect("Error fetching categories");
+    #[allow(clippy::unnecessary_sort_by)]
     categories.sort_by(|a, b| a.id.cmp(&b.id));
     // Shared data structure for all tasks to aggregate their results
     let aggregated_results = Arc::new(Mutex::new(Vec::new()));
@@ -75,6 +78,7 @@ pub(crate) async fn get_medicines(
             let task = task::spawn(spawn_theard( client.clone(),category.id as usize, pb.clone(), main_pb.clone(), shared_results));
 
             
+            #[allow(clippy::let_and_return)]
             task
         })
         .collect();
@@ -111,6 +115,7 @@ async fn spawn_theard(
     shared_results: Arc<Mutex<Vec<T_RHSTU_MEDICAMENTO>>>,
 ){
         let main = main_pb.clone();
+        #[allow(clippy::unnecessary_cast)]
         let results = process_category(&client, category_id as usize, &pb, main).await;
         {
             let mut shared = shared_results.lock().unwrap();
@@ -179,28 +184,127 @@ async fn process_category(
     medicines
 }
 
+// Posology, route and usage notes of the active ingredients prescribed for each chapter
+const POSOLOGIAS_CSV: &str = include_str!("../../catalogs/posologias.csv");
+
+// Directions of a medicine whose active ingredient isn't in the catalog
+const DEFAULT_DIRECTIONS: (&str, &str, &str) = (
+    "Conforme orientação médica",
+    "Oral",
+    "Seguir as orientações da bula",
+);
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Directions {
+    PRINCIPIO_ATIVO: String,
+    DS_POSOLOGIA: String,
+    DS_VIA: String,
+    DS_OBSERVACAO_USO: String,
+}
+
+// Bundled directions for use, matched against medicine names by active ingredient
+pub(crate) struct Posology {
+    directions: Vec<Directions>,
+}
+
+impl Posology {
+    pub(crate) fn load() -> Result<Posology, anyhow::Error> {
+        let directions = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(POSOLOGIAS_CSV.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        Ok(Posology { directions })
+    }
+
+    // (posology, route, notes) of the first active ingredient found in `medicine`
+    fn of(&self, medicine: &T_RHSTU_MEDICAMENTO) -> (&str, &str, &str) {
+        let name = unaccent(&medicine.NM_MEDICAMENTO.to_uppercase());
+        self.directions
+            .iter()
+            .find(|directions| name.contains(&directions.PRINCIPIO_ATIVO))
+            .map_or(DEFAULT_DIRECTIONS, |directions| {
+                (
+                    directions.DS_POSOLOGIA.as_str(),
+                    directions.DS_VIA.as_str(),
+                    directions.DS_OBSERVACAO_USO.as_str(),
+                )
+            })
+    }
+}
+
 // MedicalPrescription
 // - T_RHSTU_PRESCRICAO_MEDICA - "ID_PRESCRICAO_MEDICA","ID_UNID_HOSPITAL","ID_CONSULTA","ID_MEDICAMENTO","DS_POSOLOGIA","DS_VIA","DS_OBSERVACAO_USO","QT_MEDICAMENTO","NM_USUARIO","DT_CADASTRO"
-pub(crate) async fn generate_medical_prescription(total: usize, medicines: Vec<T_RHSTU_MEDICAMENTO>,
+// Prescriptions go to consultations whose primary diagnosis is usually treated with medicines,
+// and prefer medicines of that diagnosis chapter, with the directions of their active ingredient.
+pub(crate) async fn generate_medical_prescription(
+    total: usize,
+    medicines: Vec<T_RHSTU_MEDICAMENTO>,
+    consultations: Vec<T_RHSTU_CONSULTA>,
+    chapters: HashMap<u64, &'static str>,
+    posology: Posology,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) {
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Medical Prescription:".to_string());
     let pb = &pb_helper.pb;
     let mut prescripitions: Vec<T_RHSTU_PRESCRICAO_MEDICA> = Vec::new();
+    let mut rng = rand::thread_rng();
+
+    // Medicines whose name contains an active ingredient of each chapter
+    let mut by_chapter: HashMap<&str, Vec<&T_RHSTU_MEDICAMENTO>> = HashMap::new();
+    for chapter in chapters.values() {
+        by_chapter.entry(chapter).or_insert_with(|| {
+            let ingredients = chapter_medicines(chapter);
+            medicines
+                .iter()
+                .filter(|medicine| {
+                    let name = unaccent(&medicine.NM_MEDICAMENTO.to_uppercase());
+                    ingredients
+                        .iter()
+                        .any(|ingredient| name.contains(ingredient))
+                })
+                .collect()
+        });
+    }
+
+    let treated: Vec<&T_RHSTU_CONSULTA> = consultations
+        .iter()
+        .filter(|consultation| {
+            chapters
+                .get(&consultation.ID_CONSULTA)
+                .is_some_and(|chapter| !chapter_medicines(chapter).is_empty())
+        })
+        .collect();
+    let candidates: Vec<&T_RHSTU_CONSULTA> = if treated.is_empty() {
+        consultations.iter().collect()
+    } else {
+        treated
+    };
 
     for i in 0..total {
-        let medicine = medicines.choose(&mut rand::thread_rng()).unwrap();
+        let (Some(consultation), Some(fallback)) =
+            (candidates.choose(&mut rng), medicines.choose(&mut rng))
+        else {
+            break;
+        };
+        let medicine = chapters
+            .get(&consultation.ID_CONSULTA)
+            .and_then(|chapter| by_chapter.get(chapter))
+            .and_then(|matching| matching.choose(&mut rng).copied())
+            .unwrap_or(fallback);
+        let (dosage, route, notes) = posology.of(medicine);
 
         let medical_prescription = T_RHSTU_PRESCRICAO_MEDICA {
             ID_PRESCRICAO_MEDICA: i as u64,
-            ID_UNID_HOSPITAL: rand::thread_rng().gen_range(1..100) as u64,
-            ID_CONSULTA: rand::thread_rng().gen_range(1..100) as u64,
+            ID_UNID_HOSPITAL: consultation.ID_UNID_HOSPITAL,
+            ID_CONSULTA: consultation.ID_CONSULTA,
             ID_MEDICAMENTO: medicine.ID_MEDICAMENTO,
-            DS_POSOLOGIA: "DS_POSOLOGIA".to_string(),
-            DS_VIA: "DS_VIA".to_string(),
-            DS_OBSERVACAO_USO: "DS_OBSERVACAO_USO".to_string(),
-            QT_MEDICAMENTO: rand::thread_rng().gen_range(1..100) as u64,
+            DS_POSOLOGIA: dosage.to_string(),
+            DS_VIA: route.to_string(),
+            DS_OBSERVACAO_USO: notes.to_string(),
+            QT_MEDICAMENTO: rng.gen_range(1..100) as u64,
             NM_USUARIO: "NM_USUARIO".to_string(),
             DT_CADASTRO: current_timestamp(),
         };
@@ -214,6 +318,5 @@ pub(crate) async fn generate_medical_prescription(total: usize, medicines: Vec<T
     let generator = SqlGenerator::new(prescripitions.clone());
     let _ = generator.write_to_file(pb);
 
-    
     pb_helper.finish();
 }
@@ -1,6 +1,68 @@
+pub(crate) mod ambulance;
 pub(crate) mod consultation;
+pub(crate) mod diagnosis;
+pub(crate) mod exam;
 pub(crate) mod geography;
 pub(crate) mod health_plan;
 pub(crate) mod hospital;
+pub(crate) mod inpatient;
 pub(crate) mod medicine;
 pub(crate) mod patient;
+pub(crate) mod reference;
+pub(crate) mod specialty;
+pub(crate) mod street;
+
+use crate::sql_generator::{Column, TableRow};
+
+pub(crate) struct TableInfo {
+    pub(crate) name: &'static str,
+    pub(crate) columns: Vec<Column>,
+}
+
+fn info<T: TableRow>() -> TableInfo {
+    TableInfo {
+        name: T::TABLE,
+        columns: T::columns(),
+    }
+}
+
+// Every table, parents before the tables referencing them
+pub(crate) fn schema() -> Vec<TableInfo> {
+    vec![
+        info::<geography::T_RHSTU_ESTADO>(),
+        info::<geography::T_RHSTU_CIDADE>(),
+        info::<geography::T_RHSTU_BAIRRO>(),
+        info::<geography::T_RHSTU_LOGRADOURO>(),
+        info::<hospital::T_RHSTU_UNID_HOSPITALAR>(),
+        info::<hospital::T_RHSTU_ENDERECO_UNIDHOSP>(),
+        info::<inpatient::T_RHSTU_ALA>(),
+        info::<inpatient::T_RHSTU_LEITO>(),
+        info::<hospital::T_RHSTU_FUNCIONARIO>(),
+        info::<hospital::T_RHSTU_FUNCIONARIO_UNIDHOSP>(),
+        info::<specialty::T_RHSTU_ESPECIALIDADE>(),
+        info::<hospital::T_RHSTU_MEDICO>(),
+        info::<specialty::T_RHSTU_MEDICO_ESPECIALIDADE>(),
+        info::<hospital::T_RHSTU_MOTORISTA>(),
+        info::<ambulance::T_RHSTU_AMBULANCIA>(),
+        info::<ambulance::T_RHSTU_VIAGEM_AMBULANCIA>(),
+        info::<patient::T_RHSTU_PACIENTE>(),
+        info::<patient::T_RHSTU_TIPO_CONTATO>(),
+        info::<patient::T_RHSTU_CONTATO_PACIENTE>(),
+        info::<patient::T_RHSTU_EMAIL_PACIENTE>(),
+        info::<patient::T_RHSTU_TELEFONE_PACIENTE>(),
+        info::<patient::T_RHSTU_ENDERECO_PACIENTE>(),
+        info::<health_plan::T_RHSTU_PLANO_SAUDE>(),
+        info::<health_plan::T_RHSTU_PACIENTE_PLANO_SAUDE>(),
+        info::<medicine::T_RHSTU_MEDICAMENTO>(),
+        info::<consultation::T_RHSTU_FORMA_PAGAMENTO>(),
+        info::<consultation::T_RHSTU_CONSULTA>(),
+        info::<consultation::T_RHSTU_CONSULTA_FORMA_PAGTO>(),
+        info::<diagnosis::T_RHSTU_CID10>(),
+        info::<diagnosis::T_RHSTU_DIAGNOSTICO>(),
+        info::<exam::T_RHSTU_EXAME>(),
+        info::<exam::T_RHSTU_PEDIDO_EXAME>(),
+        info::<exam::T_RHSTU_RESULTADO_EXAME>(),
+        info::<medicine::T_RHSTU_PRESCRICAO_MEDICA>(),
+        info::<inpatient::T_RHSTU_INTERNACAO>(),
+    ]
+}
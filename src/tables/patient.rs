@@ -1,57 +1,72 @@
-use crate::common::{current_timestamp, random_br_phone, random_cpf, random_rg, ProgressBarHelper};
+use crate::common::{
+    current_timestamp, parse_to_date, random_br_phone, random_cpf, random_rg, rg_issuer, to_date,
+    unaccent, ProgressBarHelper,
+};
+use crate::config::profile;
 use crate::define_and_impl_sql_insertable;
 use crate::sql_generator::SqlGenerator;
-use crate::tables::geography::{get_ddds, T_RHSTU_LOGRADOURO};
-use fake::faker::internet::en::FreeEmail;
-use fake::{faker::name::en::Name, Fake};
+use crate::tables::geography::{DEFAULT_DDD, DEFAULT_STATE, T_RHSTU_LOGRADOURO};
+use crate::tables::reference;
+use crate::tables::street::{building_number, home_complement, reference_point};
+use chrono::{Duration, NaiveDateTime};
+use fake::faker::company::en::CompanyName;
+use fake::{
+    faker::name::en::{FirstName, Name},
+    Fake,
+};
 use indicatif::{MultiProgress, ProgressBar};
 use rand::{seq::SliceRandom, Rng};
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use std::collections::HashSet;
+use std::ops::{Range, RangeInclusive};
 use std::sync::Arc;
 
 define_and_impl_sql_insertable!(
     T_RHSTU_PACIENTE {
         pub(crate) ID_PACIENTE: u64,
-        pub(crate) NM_PACIENTE: String,
+        pub(crate) NM_PACIENTE: String [100],
         pub(crate) NR_CPF: u64,
-        pub(crate) NM_RG: u64,
+        pub(crate) NM_RG: String [14],
+        pub(crate) DS_ORGAO_EMISSOR_RG: String [9],
         pub(crate) DT_NASCIMENTO: String,
-        pub(crate) FL_SEXO_BIOLOGICO: String,
-        pub(crate) DS_ESCOLARIDADE: String,
-        pub(crate) DS_ESTADO_CIVIL: String,
-        pub(crate) NM_GRUPO_SANGUINEO: String,
+        pub(crate) FL_SEXO_BIOLOGICO: String [1],
+        pub(crate) DS_ESCOLARIDADE: String [30],
+        pub(crate) DS_ESTADO_CIVIL: String [20],
+        pub(crate) NM_GRUPO_SANGUINEO: String [3],
         pub(crate) NR_ALTURA: u64,
         pub(crate) NR_PESO: u64,
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_TIPO_CONTATO {
         pub(crate) ID_TIPO_CONTATO: u64,
-        pub(crate) NM_TIPO_CONTATO: String,
+        pub(crate) NM_TIPO_CONTATO: String [50],
         pub(crate) DT_INICIO: String,
         pub(crate) DT_FIM: String,
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_CONTATO_PACIENTE {
         pub(crate) ID_PACIENTE: u64,
         pub(crate) ID_CONTATO: u64,
         pub(crate) ID_TIPO_CONTATO: u64,
-        pub(crate) NM_CONTATO: String,
+        pub(crate) NM_CONTATO: String [100],
         pub(crate) NR_DDI: u64,
         pub(crate) NR_DDD: u64,
-        pub(crate) NR_TELEFONE: String,
+        pub(crate) NR_TELEFONE: String [15],
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_EMAIL_PACIENTE {
         pub(crate) ID_EMAIL: u64,
         pub(crate) ID_PACIENTE: u64,
-        pub(crate) DS_EMAIL: String,
-        pub(crate) TP_EMAIL: String,
-        pub(crate) ST_EMAIL: String,
+        pub(crate) DS_EMAIL: String [150],
+        pub(crate) TP_EMAIL: String [20],
+        pub(crate) ST_EMAIL: String [1],
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_TELEFONE_PACIENTE {
         pub(crate) ID_PACIENTE: u64,
@@ -59,33 +74,134 @@ define_and_impl_sql_insertable!(
         pub(crate) NR_DDI: u64,
         pub(crate) NR_DDD: u64,
         pub(crate) NR_TELEFONE: u64,
-        pub(crate) TP_TELEFONE: String,
-        pub(crate) ST_TELEFONE: String,
+        pub(crate) TP_TELEFONE: String [20],
+        pub(crate) ST_TELEFONE: String [1],
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     },
     T_RHSTU_ENDERECO_PACIENTE {
         pub(crate) ID_ENDERECO: u64,
         pub(crate) ID_PACIENTE: u64,
         pub(crate) ID_LOGRADOURO: u64,
         pub(crate) NR_LOGRADOURO: u64,
-        pub(crate) DS_COMPLEMENTO_NUMERO: String,
-        pub(crate) DS_PONTO_REFERENCIA: String,
+        pub(crate) DS_COMPLEMENTO_NUMERO: String [50],
+        pub(crate) DS_PONTO_REFERENCIA: String [100],
         pub(crate) DT_INICIO: String,
         pub(crate) DT_FIM: String,
         pub(crate) DT_CADASTRO: String,
-        pub(crate) NM_USUARIO: String
+        pub(crate) NM_USUARIO: String [100]
     }
 );
 
+// Words of company names left out of their email domain
+const COMPANY_SUFFIXES: [&str; 6] = ["and", "group", "inc", "llc", "ltd", "sons"];
+
+// Relationship of a contact to the patient. Relatives share the patient's surname,
+// `single` ones are listed at most once per patient, and `ages` bounds the age of the
+// patients who can have them.
+struct Relationship {
+    name: &'static str,
+    relative: bool,
+    single: bool,
+    ages: RangeInclusive<u32>,
+    weight: f64,
+}
+
+impl Relationship {
+    const fn relative(
+        name: &'static str,
+        single: bool,
+        ages: RangeInclusive<u32>,
+        weight: f64,
+    ) -> Self {
+        Relationship {
+            name,
+            relative: true,
+            single,
+            ages,
+            weight,
+        }
+    }
+
+    const fn other(
+        name: &'static str,
+        single: bool,
+        ages: RangeInclusive<u32>,
+        weight: f64,
+    ) -> Self {
+        Relationship {
+            name,
+            relative: false,
+            single,
+            ages,
+            weight,
+        }
+    }
+}
+
+// Rules of the contact types in the reference list, by name. Types without one can be
+// anyone's contact.
+const RELATIONSHIPS: [Relationship; 13] = [
+    Relationship::relative("Mãe", true, 0..=85, 20.0),
+    Relationship::relative("Pai", true, 0..=80, 12.0),
+    Relationship::relative("Cônjuge", true, 18..=120, 25.0),
+    Relationship::relative("Filho(a)", false, 36..=120, 20.0),
+    Relationship::relative("Irmão(ã)", false, 0..=120, 10.0),
+    Relationship::relative("Avô(ó)", false, 0..=40, 3.0),
+    Relationship::relative("Neto(a)", false, 60..=120, 4.0),
+    Relationship::relative("Tio(a)", false, 0..=60, 2.0),
+    Relationship::relative("Sobrinho(a)", false, 40..=120, 3.0),
+    Relationship::relative("Primo(a)", false, 0..=120, 2.0),
+    Relationship::other("Amigo(a)", false, 14..=120, 6.0),
+    Relationship::other("Vizinho(a)", false, 18..=120, 2.0),
+    Relationship::other("Cuidador(a)", true, 70..=120, 4.0),
+];
+const ACQUAINTANCE: Relationship = Relationship::other("", false, 0..=120, 1.0);
+
+// Share of message phones ("CONTATO OU RECADO") that are mobiles
+const MOBILE_MESSAGE_SHARE: f64 = 0.7;
+
+// Patient of each row of a child table: as many rows per patient as drawn from the
+// table's cardinality, or its row count spread over the patients in turn
+pub(crate) fn owners(table: &str, patients: usize) -> Vec<u64> {
+    let mut rng = rand::thread_rng();
+    match profile().table(table).cardinality() {
+        Some(cardinality) => (0..patients as u64)
+            .flat_map(|patient| std::iter::repeat_n(patient, cardinality.sample(&mut rng)))
+            .collect(),
+        None if patients == 0 => Vec::new(),
+        None => (0..profile().rows(table))
+            .map(|i| (i % patients) as u64)
+            .collect(),
+    }
+}
+
+// Each patient's run of rows in `owners`, with the ids those rows take
+pub(crate) fn runs(owners: &[u64]) -> Vec<(u64, Range<usize>)> {
+    owners
+        .chunk_by(|a, b| a == b)
+        .scan(0, |first, rows| {
+            let ids = *first..*first + rows.len();
+            *first = ids.end;
+            Some((rows[0], ids))
+        })
+        .collect()
+}
+
+// Patients get their RG from the state of their address, and are born before they
+// moved into their first one
 pub(crate) async fn generate_patients(
     total: usize,
+    states: Vec<Option<String>>,
+    moved_in: Vec<Option<NaiveDateTime>>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) {
+) -> Vec<T_RHSTU_PACIENTE> {
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Patients:".to_string());
     let pb = &pb_helper.pb;
 
+    let table = profile().table("T_RHSTU_PACIENTE");
+
     let patients: Vec<T_RHSTU_PACIENTE> = (0..total)
         .into_par_iter()
         .map(|i| {
@@ -93,25 +209,29 @@ pub(crate) async fn generate_patients(
             main_pb.inc(1);
 
             let mut rng = rand::thread_rng();
+            let state = states
+                .get(i)
+                .and_then(|state| state.as_deref())
+                .unwrap_or(DEFAULT_STATE);
+            let (start, end) = table.date_window("DT_NASCIMENTO").bounds();
+            let end = moved_in
+                .get(i)
+                .copied()
+                .flatten()
+                .map_or(end, |moved_in| end.min(moved_in))
+                .max(start);
+            let birth = start + Duration::seconds(rng.gen_range(0..=(end - start).num_seconds()));
             T_RHSTU_PACIENTE {
                 ID_PACIENTE: i as u64,
                 NM_PACIENTE: Name().fake(),
                 NR_CPF: random_cpf(),
-                NM_RG: random_rg(),
-                DT_NASCIMENTO: current_timestamp(),
-                FL_SEXO_BIOLOGICO: ["M", "F"].choose(&mut rng).unwrap().to_string(),
-                DS_ESCOLARIDADE: ["Ensino Fundamental", "Ensino Médio", "Ensino Superior"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
-                DS_ESTADO_CIVIL: ["Solteiro", "Casado", "Divorciado", "Viúvo"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
-                NM_GRUPO_SANGUINEO: ["A+", "A-", "B+", "B-", "AB+", "AB-", "O+", "O-"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
+                NM_RG: random_rg(state),
+                DS_ORGAO_EMISSOR_RG: rg_issuer(state),
+                DT_NASCIMENTO: to_date(birth),
+                FL_SEXO_BIOLOGICO: table.pick("FL_SEXO_BIOLOGICO", &mut rng),
+                DS_ESCOLARIDADE: table.pick("DS_ESCOLARIDADE", &mut rng),
+                DS_ESTADO_CIVIL: table.pick("DS_ESTADO_CIVIL", &mut rng),
+                NM_GRUPO_SANGUINEO: table.pick("NM_GRUPO_SANGUINEO", &mut rng),
                 DT_CADASTRO: current_timestamp(),
                 NM_USUARIO: Name().fake(),
                 NR_ALTURA: rng.gen_range(1.0..2.0) as u64,
@@ -120,36 +240,39 @@ pub(crate) async fn generate_patients(
         })
         .collect();
 
-    let generator = SqlGenerator::new(patients);
+    let generator = SqlGenerator::new(patients.clone());
     let _ = generator.write_to_file(pb);
 
     pb_helper.finish();
+    patients
 }
 
-pub(crate) async fn generate_contact_types(
-    total: usize,
+// Contact types come from the reference list; inactive ones have ended
+pub(crate) fn generate_contact_types(
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
-) -> Vec<T_RHSTU_TIPO_CONTATO> {
+) -> Result<Vec<T_RHSTU_TIPO_CONTATO>, anyhow::Error> {
+    let references = reference::load("T_RHSTU_TIPO_CONTATO")?;
+
+    let total = references.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Contact types:".to_string());
     let pb = &pb_helper.pb;
 
-    let contact_types: Vec<T_RHSTU_TIPO_CONTATO> = (0..total)
-        .into_par_iter()
-        .map(|i| {
+    let contact_types: Vec<T_RHSTU_TIPO_CONTATO> = references
+        .into_iter()
+        .map(|reference| {
             pb.inc(1); // Increment the progress bar
             main_pb.inc(1);
 
-            let mut rng = rand::thread_rng();
             T_RHSTU_TIPO_CONTATO {
-                ID_TIPO_CONTATO: i as u64,
-                // pick a random emergency contact type relationship to the patient
-                NM_TIPO_CONTATO: ["Pessoal", "Trabalho", "Emergência"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
+                ID_TIPO_CONTATO: reference.id,
+                NM_TIPO_CONTATO: reference.name,
                 DT_INICIO: current_timestamp(),
-                DT_FIM: current_timestamp(),
+                DT_FIM: if reference.active {
+                    String::new()
+                } else {
+                    current_timestamp()
+                },
                 DT_CADASTRO: current_timestamp(),
                 NM_USUARIO: 1.to_string(),
             }
@@ -161,37 +284,108 @@ pub(crate) async fn generate_contact_types(
 
     pb_helper.finish();
 
-    contact_types
+    Ok(contact_types)
+}
+
+// Age in whole years of someone born on `birth`, at the end of the profile's dates
+fn age(birth: NaiveDateTime) -> u32 {
+    profile()
+        .as_of()
+        .date()
+        .years_since(birth.date())
+        .unwrap_or(0)
 }
 
+// Draws the types of a patient's contacts among those plausible at their age, without
+// repeating the ones a patient can have only one of
+fn relationships<'a, R: Rng + ?Sized>(
+    kinds: &[(u64, &'a Relationship)],
+    age: u32,
+    count: usize,
+    rng: &mut R,
+) -> Vec<(u64, &'a Relationship)> {
+    let mut chosen: Vec<(u64, &Relationship)> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let candidates: Vec<&(u64, &Relationship)> = kinds
+            .iter()
+            .filter(|(id, relationship)| {
+                relationship.ages.contains(&age)
+                    && !(relationship.single && chosen.iter().any(|(taken, _)| taken == id))
+            })
+            .collect();
+        let Ok(picked) = candidates.choose_weighted(rng, |(_, relationship)| relationship.weight)
+        else {
+            break;
+        };
+        chosen.push(**picked);
+    }
+    chosen
+}
+
+// Contacts are relatives or acquaintances plausible at the patient's age, relatives
+// carrying the patient's surname. They are reached on a mobile with the DDD of the
+// patient's city.
 pub(crate) async fn generate_patient_contacts(
-    total: usize,
+    patients: Arc<Vec<T_RHSTU_PACIENTE>>,
     contact_types: Vec<T_RHSTU_TIPO_CONTATO>,
+    ddds: Vec<Option<u64>>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> Vec<T_RHSTU_CONTATO_PACIENTE> {
-    let pb_helper = ProgressBarHelper::new(m, total * 2, "Patient contacts:".to_string());
+    let owners = owners("T_RHSTU_CONTATO_PACIENTE", patients.len());
+    let pb_helper = ProgressBarHelper::new(m, owners.len() * 2, "Patient contacts:".to_string());
     let pb = &pb_helper.pb;
 
-    let contacts: Vec<T_RHSTU_CONTATO_PACIENTE> = (0..total)
-        .into_par_iter()
-        .map(|i| {
-            pb.inc(1); // Increment the progress bar
-            main_pb.inc(1);
+    let kinds: Vec<(u64, &Relationship)> = contact_types
+        .iter()
+        .filter(|kind| kind.DT_FIM.is_empty())
+        .map(|kind| {
+            let relationship = RELATIONSHIPS
+                .iter()
+                .find(|relationship| relationship.name == kind.NM_TIPO_CONTATO)
+                .unwrap_or(&ACQUAINTANCE);
+            (kind.ID_TIPO_CONTATO, relationship)
+        })
+        .collect();
 
+    let contacts: Vec<T_RHSTU_CONTATO_PACIENTE> = runs(&owners)
+        .into_par_iter()
+        .flat_map_iter(|(owner, ids)| {
             let mut rng = rand::thread_rng();
-            let contact_type = contact_types.choose(&mut rng).unwrap();
-            T_RHSTU_CONTATO_PACIENTE {
-                ID_PACIENTE: i as u64,
-                ID_CONTATO: i as u64,
-                ID_TIPO_CONTATO: contact_type.ID_TIPO_CONTATO,
-                NM_CONTATO: Name().fake(),
-                NR_DDI: rng.gen_range(1..100) as u64,
-                NR_DDD: rng.gen_range(1..100) as u64,
-                NR_TELEFONE: rng.gen_range(1..100).to_string(),
-                DT_CADASTRO: current_timestamp(),
-                NM_USUARIO: 1.to_string(),
-            }
+            let patient = &patients[owner as usize];
+            let age = parse_to_date(&patient.DT_NASCIMENTO).map_or(0, age);
+            let surname = patient.NM_PACIENTE.split_whitespace().last().unwrap_or("");
+            let ddd = ddds
+                .get(owner as usize)
+                .copied()
+                .flatten()
+                .unwrap_or(DEFAULT_DDD);
+
+            relationships(&kinds, age, ids.len(), &mut rng)
+                .into_iter()
+                .zip(ids)
+                .map(|((kind, relationship), id)| {
+                    pb.inc(1); // Increment the progress bar
+                    main_pb.inc(1);
+
+                    let name = if relationship.relative {
+                        format!("{} {}", FirstName().fake::<String>(), surname)
+                    } else {
+                        Name().fake()
+                    };
+                    T_RHSTU_CONTATO_PACIENTE {
+                        ID_PACIENTE: owner,
+                        ID_CONTATO: id as u64,
+                        ID_TIPO_CONTATO: kind,
+                        NM_CONTATO: name,
+                        NR_DDI: profile().phones().ddi,
+                        NR_DDD: ddd,
+                        NR_TELEFONE: random_br_phone(true).to_string(),
+                        DT_CADASTRO: current_timestamp(),
+                        NM_USUARIO: 1.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
         })
         .collect();
 
@@ -203,35 +397,99 @@ pub(crate) async fn generate_patient_contacts(
     contacts
 }
 
+// Lowercase ASCII words of a name, e.g. "João D'Ávila" gives ["joao", "davila"]
+fn name_words(name: &str) -> Vec<String> {
+    unaccent(name)
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// Mailbox of a personal address, e.g. "joao.silva", "joaosilva", "joao_silva" or
+// "jsilva"
+fn personal_mailbox<R: Rng + ?Sized>(first: &str, last: &str, rng: &mut R) -> String {
+    match rng.gen_range(0..4) {
+        0 => format!("{}.{}", first, last),
+        1 => format!("{}{}", first, last),
+        2 => format!("{}_{}", first, last),
+        _ => format!("{}{}", &first[..1], last),
+    }
+}
+
+// Emails built from the patient's name. Personal ones use a provider of the profile,
+// work ones the domain of an employer; a number is appended when the address is
+// already taken, so every address is unique.
 pub(crate) async fn generate_emails(
-    total: usize,
+    patients: Arc<Vec<T_RHSTU_PACIENTE>>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> Vec<T_RHSTU_EMAIL_PACIENTE> {
+    let owners = owners("T_RHSTU_EMAIL_PACIENTE", patients.len());
+    let total = owners.len();
     let pb_helper = ProgressBarHelper::new(m, total * 2, "Patient emails:".to_string());
     let pb = &pb_helper.pb;
 
-    let emails: Vec<T_RHSTU_EMAIL_PACIENTE> = (0..total)
-        .into_par_iter()
-        .map(|i| {
-            pb.inc(1); // Increment the progress bar
-            main_pb.inc(1);
-            let mut rng = rand::thread_rng();
-
-            T_RHSTU_EMAIL_PACIENTE {
-                ID_EMAIL: i as u64,
-                ID_PACIENTE: i as u64,
-                DS_EMAIL: FreeEmail().fake(),
-                TP_EMAIL: ["Pessoal", "Trabalho"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
-                ST_EMAIL: ["A", "I"].choose(&mut rng).unwrap().to_string(),
-                DT_CADASTRO: current_timestamp(),
-                NM_USUARIO: 1.to_string(),
-            }
-        })
-        .collect();
+    let mut rng = rand::thread_rng();
+    let table = profile().table("T_RHSTU_EMAIL_PACIENTE");
+    let providers = &profile().emails().providers;
+    let mut taken: HashSet<String> = HashSet::with_capacity(total);
+    let mut emails: Vec<T_RHSTU_EMAIL_PACIENTE> = Vec::with_capacity(total);
+
+    for (i, &owner) in owners.iter().enumerate() {
+        let patient = &patients[owner as usize];
+        let words = name_words(&patient.NM_PACIENTE);
+        let first = words.first().map_or("paciente", String::as_str);
+        let last = words
+            .last()
+            .filter(|_| words.len() > 1)
+            .map_or("", String::as_str);
+
+        let kind = table.pick("TP_EMAIL", &mut rng);
+        let (mailbox, domain) = if kind == "Trabalho" {
+            let company: String = CompanyName().fake();
+            let company = name_words(&company)
+                .into_iter()
+                .filter(|word| !COMPANY_SUFFIXES.contains(&word.as_str()))
+                .collect::<String>();
+            let mailbox = match last {
+                "" => first.to_string(),
+                last => format!("{}.{}", first, last),
+            };
+            (mailbox, format!("{}.com.br", company))
+        } else {
+            let mailbox = match last {
+                "" => first.to_string(),
+                last => personal_mailbox(first, last, &mut rng),
+            };
+            (mailbox, providers.choose(&mut rng).to_string())
+        };
+
+        let mut address = format!("{}@{}", mailbox, domain);
+        let mut suffix = 1;
+        while !taken.insert(address.clone()) {
+            suffix += 1;
+            address = format!("{}{}@{}", mailbox, suffix, domain);
+        }
+
+        emails.push(T_RHSTU_EMAIL_PACIENTE {
+            ID_EMAIL: i as u64,
+            ID_PACIENTE: patient.ID_PACIENTE,
+            DS_EMAIL: address,
+            TP_EMAIL: kind,
+            ST_EMAIL: table.pick("ST_EMAIL", &mut rng),
+            DT_CADASTRO: current_timestamp(),
+            NM_USUARIO: 1.to_string(),
+        });
+
+        pb.inc(1); // Increment the progress bar
+        main_pb.inc(1);
+    }
 
     let generator = SqlGenerator::new(emails.clone());
     let _ = generator.write_to_file(pb);
@@ -241,35 +499,47 @@ pub(crate) async fn generate_emails(
     emails
 }
 
+// Phones carry the DDD of the patient's city, and a mobile or landline number
+// matching their type
 pub(crate) async fn generate_telephones(
-    total: usize,
+    patients: usize,
+    ddds: Vec<Option<u64>>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> Vec<T_RHSTU_TELEFONE_PACIENTE> {
-    let pb_helper = ProgressBarHelper::new(m, total * 2, "Patient telephones:".to_string());
+    let owners = owners("T_RHSTU_TELEFONE_PACIENTE", patients);
+    let pb_helper = ProgressBarHelper::new(m, owners.len() * 2, "Patient telephones:".to_string());
     let pb = &pb_helper.pb;
 
-    let ddds = get_ddds().unwrap();
+    let table = profile().table("T_RHSTU_TELEFONE_PACIENTE");
 
-    let telephones: Vec<T_RHSTU_TELEFONE_PACIENTE> = (0..total)
-        .into_par_iter()
-        .map(|i| {
+    let telephones: Vec<T_RHSTU_TELEFONE_PACIENTE> = owners
+        .par_iter()
+        .enumerate()
+        .map(|(i, &patient)| {
             pb.inc(1); // Increment the progress bar
             main_pb.inc(1);
 
             let mut rng = rand::thread_rng();
+            let kind = table.pick("TP_TELEFONE", &mut rng);
+            let mobile = match kind.as_str() {
+                "CELULAR" => true,
+                "CONTATO OU RECADO" => rng.gen_bool(MOBILE_MESSAGE_SHARE),
+                _ => false,
+            };
 
             T_RHSTU_TELEFONE_PACIENTE {
-                ID_PACIENTE: i as u64,
+                ID_PACIENTE: patient,
                 ID_TELEFONE: i as u64,
-                NR_DDI: rng.gen_range(1..100) as u64,
-                NR_DDD: *ddds.choose(&mut rng).unwrap(),
-                NR_TELEFONE: random_br_phone(),
-                TP_TELEFONE: ["CELULAR", "COMERCIAL", "CONTATO OU RECADO", "RESIDENCIAL"]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_string(),
-                ST_TELEFONE: ["A", "I"].choose(&mut rng).unwrap().to_string(),
+                NR_DDI: profile().phones().ddi,
+                NR_DDD: ddds
+                    .get(patient as usize)
+                    .copied()
+                    .flatten()
+                    .unwrap_or(DEFAULT_DDD),
+                NR_TELEFONE: random_br_phone(mobile),
+                TP_TELEFONE: kind,
+                ST_TELEFONE: table.pick("ST_TELEFONE", &mut rng),
                 DT_CADASTRO: current_timestamp(),
                 NM_USUARIO: 1.to_string(),
             }
@@ -284,36 +554,90 @@ pub(crate) async fn generate_telephones(
     telephones
 }
 
+// Value of the street of each patient's latest address (e.g. its state or DDD),
+// indexed by patient id
+pub(crate) fn by_patient<T: Clone>(
+    total: usize,
+    addresses: &[T_RHSTU_ENDERECO_PACIENTE],
+    streets: &[Option<T>],
+) -> Vec<Option<T>> {
+    let mut values = vec![None; total];
+    for address in addresses {
+        if let Some(value) = values.get_mut(address.ID_PACIENTE as usize) {
+            *value = streets
+                .get(address.ID_LOGRADOURO as usize)
+                .cloned()
+                .flatten();
+        }
+    }
+    values
+}
+
+// Date each patient moved into their first address, indexed by patient id
+pub(crate) fn moved_in(
+    total: usize,
+    addresses: &[T_RHSTU_ENDERECO_PACIENTE],
+) -> Vec<Option<NaiveDateTime>> {
+    let mut dates = vec![None; total];
+    for address in addresses {
+        if let Some(date) = dates.get_mut(address.ID_PACIENTE as usize) {
+            let start = parse_to_date(&address.DT_INICIO);
+            *date = match (*date, start) {
+                (Some(date), Some(start)) => Some(start.min(date)),
+                (date, start) => date.or(start),
+            };
+        }
+    }
+    dates
+}
+
+// Address history of each patient, oldest first. Every address but the current one
+// ends the day the patient moved into the next.
 pub(crate) async fn generate_patients_addresses(
     patients: usize,
     address: Vec<T_RHSTU_LOGRADOURO>,
     m: Arc<MultiProgress>,
     main_pb: Arc<ProgressBar>,
 ) -> Vec<T_RHSTU_ENDERECO_PACIENTE> {
-    let pb_helper = ProgressBarHelper::new(m, patients, "Patient addresses:".to_string());
+    let owners = owners("T_RHSTU_ENDERECO_PACIENTE", patients);
+    let pb_helper = ProgressBarHelper::new(m, owners.len(), "Patient addresses:".to_string());
     let pb = &pb_helper.pb;
 
-    let patient_addresses: Vec<T_RHSTU_ENDERECO_PACIENTE> = (0..patients)
-        .into_par_iter()
-        .map(|i| {
-            pb.inc(1); // Increment the progress bar
-            main_pb.inc(1);
+    let window = profile()
+        .table("T_RHSTU_ENDERECO_PACIENTE")
+        .date_window("DT_INICIO");
 
+    let patient_addresses: Vec<T_RHSTU_ENDERECO_PACIENTE> = runs(&owners)
+        .into_par_iter()
+        .flat_map_iter(|(patient, ids)| {
             let mut rng = rand::thread_rng();
-            let chosen_address = address.choose(&mut rng).unwrap().clone();
-
-            T_RHSTU_ENDERECO_PACIENTE {
-                ID_ENDERECO: chosen_address.ID_LOGRADOURO,
-                ID_PACIENTE: i as u64,
-                ID_LOGRADOURO: chosen_address.ID_LOGRADOURO,
-                NR_LOGRADOURO: rng.gen_range(1..100) as u64,
-                DS_COMPLEMENTO_NUMERO: "DS_COMPLEMENTO_NUMERO".to_string(),
-                DS_PONTO_REFERENCIA: "DS_PONTO_REFERENCIA".to_string(),
-                DT_INICIO: current_timestamp(),
-                DT_FIM: current_timestamp(),
-                DT_CADASTRO: current_timestamp(),
-                NM_USUARIO: 1.to_string(),
-            }
+            let mut starts: Vec<NaiveDateTime> =
+                ids.clone().map(|_| window.sample(&mut rng)).collect();
+            starts.sort();
+
+            ids.enumerate()
+                .map(|(step, id)| {
+                    pb.inc(1); // Increment the progress bar
+                    main_pb.inc(1);
+
+                    let street = address.choose(&mut rng).unwrap();
+                    T_RHSTU_ENDERECO_PACIENTE {
+                        ID_ENDERECO: id as u64,
+                        ID_PACIENTE: patient,
+                        ID_LOGRADOURO: street.ID_LOGRADOURO,
+                        NR_LOGRADOURO: building_number(street, &mut rng),
+                        DS_COMPLEMENTO_NUMERO: home_complement(&mut rng),
+                        DS_PONTO_REFERENCIA: reference_point(&mut rng),
+                        DT_INICIO: to_date(starts[step]),
+                        DT_FIM: starts
+                            .get(step + 1)
+                            .map(|end| to_date(*end))
+                            .unwrap_or_default(),
+                        DT_CADASTRO: current_timestamp(),
+                        NM_USUARIO: 1.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
         })
         .collect();
 
@@ -0,0 +1,84 @@
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::config::profile;
+
+// Lookup tables filled from versioned lists instead of random rows. Every entry
+// carries its ID, so IDs stay the same across runs and list versions: entries are
+// only ever added, or retired by setting the version that ended them.
+const LISTS: [(&str, &str); 2] = [
+    (
+        "T_RHSTU_FORMA_PAGAMENTO",
+        include_str!("../../catalogs/formas_pagamento.csv"),
+    ),
+    (
+        "T_RHSTU_TIPO_CONTATO",
+        include_str!("../../catalogs/tipos_contato.csv"),
+    ),
+];
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Entry {
+    ID: u64,
+    NOME: String,
+    DESCRICAO: String,
+    VERSAO: u32,
+    VERSAO_FIM: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Reference {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) active: bool,
+}
+
+// Entries of `table` in the list version set by the profile. Entries retired by that
+// version keep their row and ID but are inactive, as are those the profile disables.
+pub(crate) fn load(table: &str) -> Result<Vec<Reference>, anyhow::Error> {
+    let Some((_, list)) = LISTS.iter().find(|(name, _)| *name == table) else {
+        bail!("{} is not a reference table", table);
+    };
+    let settings = profile().reference(table);
+
+    let entries: Vec<Entry> = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .from_reader(list.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("reading the list of {}", table))?;
+
+    let latest = entries.iter().map(|entry| entry.VERSAO).max().unwrap_or(0);
+    if settings.version > latest {
+        bail!(
+            "references.{}: version {} is newer than the list's latest ({})",
+            table,
+            settings.version,
+            latest
+        );
+    }
+    for name in &settings.inactive {
+        if !entries.iter().any(|entry| &entry.NOME == name) {
+            bail!("references.{}: {:?} is not in the list", table, name);
+        }
+    }
+    let mut ids = HashSet::new();
+    if let Some(entry) = entries.iter().find(|entry| !ids.insert(entry.ID)) {
+        bail!("{}: ID {} is listed twice", table, entry.ID);
+    }
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.VERSAO <= settings.version)
+        .map(|entry| Reference {
+            active: entry.VERSAO_FIM.is_none_or(|end| end > settings.version)
+                && !settings.inactive.contains(&entry.NOME),
+            id: entry.ID,
+            name: entry.NOME,
+            description: entry.DESCRICAO,
+        })
+        .collect())
+}
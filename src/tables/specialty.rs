@@ -0,0 +1,162 @@
+use fake::{faker::name::en::Name, Fake};
+use indicatif::{MultiProgress, ProgressBar};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{
+    common::{current_timestamp, ProgressBarHelper},
+    config::profile,
+    define_and_impl_sql_insertable,
+    sql_generator::SqlGenerator,
+    tables::hospital::T_RHSTU_MEDICO,
+};
+
+define_and_impl_sql_insertable!(
+    T_RHSTU_ESPECIALIDADE {
+        pub(crate) ID_ESPECIALIDADE: u64,
+        pub(crate) NM_ESPECIALIDADE: String [80],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    },
+    T_RHSTU_MEDICO_ESPECIALIDADE {
+        pub(crate) ID_MEDICO_ESPECIALIDADE: u64,
+        pub(crate) ID_FUNC: u64,
+        pub(crate) ID_ESPECIALIDADE: u64,
+        pub(crate) FL_PRINCIPAL: String [1],
+        pub(crate) DT_CADASTRO: String,
+        pub(crate) NM_USUARIO: String [100]
+    }
+);
+
+// Medical specialties recognised by the CFM, weighted by their share of specialists
+const ESPECIALIDADES_CSV: &str = include_str!("../../catalogs/especialidades.csv");
+
+// Most specialties a single doctor holds
+const MAX_SPECIALTIES: usize = 3;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Entry {
+    NM_ESPECIALIDADE: String,
+    PESO: f64,
+}
+
+pub(crate) struct Catalog {
+    specialties: Vec<T_RHSTU_ESPECIALIDADE>,
+    weights: WeightedIndex<f64>,
+}
+
+impl Catalog {
+    fn load() -> Result<Catalog, anyhow::Error> {
+        let entries: Vec<Entry> = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(ESPECIALIDADES_CSV.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+
+        let weights = WeightedIndex::new(entries.iter().map(|entry| entry.PESO))?;
+        let specialties = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| T_RHSTU_ESPECIALIDADE {
+                ID_ESPECIALIDADE: i as u64,
+                NM_ESPECIALIDADE: entry.NM_ESPECIALIDADE,
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: Name().fake(),
+            })
+            .collect();
+
+        Ok(Catalog {
+            specialties,
+            weights,
+        })
+    }
+
+    // A specialty drawn by its prevalence among specialists
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> &T_RHSTU_ESPECIALIDADE {
+        &self.specialties[self.weights.sample(rng)]
+    }
+}
+
+pub(crate) fn generate_specialties(
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> Result<Arc<Catalog>, anyhow::Error> {
+    let catalog = Catalog::load()?;
+
+    let total = catalog.specialties.len();
+    let pb_helper = ProgressBarHelper::new(m, total * 2, "Specialties:".to_string());
+    let pb = &pb_helper.pb;
+    pb.inc(total as u64);
+    main_pb.inc(total as u64);
+
+    let generator = SqlGenerator::new(catalog.specialties.clone());
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    Ok(Arc::new(catalog))
+}
+
+// Links every doctor to the specialty in their record, flagged as the main one. A
+// share of them also holds one or more other specialties.
+pub(crate) async fn generate_doctor_specialties(
+    doctors: Vec<T_RHSTU_MEDICO>,
+    catalog: Arc<Catalog>,
+    m: Arc<MultiProgress>,
+    main_pb: Arc<ProgressBar>,
+) -> usize {
+    let mut rng = rand::thread_rng();
+    let share = profile().employees().extra_specialty;
+    let ids: HashMap<&str, u64> = catalog
+        .specialties
+        .iter()
+        .map(|specialty| {
+            (
+                specialty.NM_ESPECIALIDADE.as_str(),
+                specialty.ID_ESPECIALIDADE,
+            )
+        })
+        .collect();
+
+    let pb_helper = ProgressBarHelper::new(m, doctors.len() * 2, "Doctor Specialties:".to_string());
+    let pb = &pb_helper.pb;
+
+    let mut links = Vec::with_capacity(doctors.len());
+    for doctor in &doctors {
+        let Some(main) = ids.get(doctor.DS_ESPECIALIDADE.as_str()) else {
+            continue;
+        };
+        let mut held = vec![*main];
+        while held.len() < MAX_SPECIALTIES && rng.gen_bool(share) {
+            let other = catalog.sample(&mut rng).ID_ESPECIALIDADE;
+            if !held.contains(&other) {
+                held.push(other);
+            }
+        }
+
+        for (n, specialty) in held.into_iter().enumerate() {
+            links.push(T_RHSTU_MEDICO_ESPECIALIDADE {
+                ID_MEDICO_ESPECIALIDADE: links.len() as u64,
+                ID_FUNC: doctor.ID_FUNC,
+                ID_ESPECIALIDADE: specialty,
+                FL_PRINCIPAL: if n == 0 { "S" } else { "N" }.to_string(),
+                DT_CADASTRO: current_timestamp(),
+                NM_USUARIO: Name().fake(),
+            });
+        }
+
+        pb.inc(1);
+        main_pb.inc(1);
+    }
+
+    let len = links.len();
+    let generator = SqlGenerator::new(links);
+    let _ = generator.write_to_file(pb);
+
+    pb_helper.finish();
+
+    len
+}
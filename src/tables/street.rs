@@ -0,0 +1,231 @@
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::geography::T_RHSTU_LOGRADOURO;
+
+// Street types and the parts of Brazilian street names: people honoured with or
+// without a title, historical dates and themes, weighted within their category
+const LOGRADOUROS_CSV: &str = include_str!("../../catalogs/logradouros.csv");
+
+// Highest building number of the longest street of each type, as numbers count the
+// meters from the start of the street
+const STREET_LENGTHS: [(&str, u64); 9] = [
+    ("Rua", 2_500),
+    ("Avenida", 6_000),
+    ("Travessa", 300),
+    ("Alameda", 1_500),
+    ("Praça", 250),
+    ("Estrada", 9_000),
+    ("Largo", 150),
+    ("Rodovia", 20_000),
+    ("Viela", 120),
+];
+const DEFAULT_LENGTH: u64 = 1_000;
+
+// Shortest street as a share of the longest of its type
+const SHORTEST_STREET: f64 = 0.1;
+
+// Share of streets named after a person with a title, a person by their full name, a
+// famous person, a date and a theme
+const NAME_KINDS: [f64; 5] = [0.3, 0.25, 0.2, 0.08, 0.17];
+
+// Share of homes that are apartments, and of apartment buildings split in blocks
+const APARTMENT_SHARE: f64 = 0.4;
+const BLOCK_SHARE: f64 = 0.35;
+
+// Share of addresses given with a reference point
+const REFERENCE_SHARE: f64 = 0.6;
+
+// Landmarks of reference points, with their gender for the contraction of the
+// preposition ("ao mercado", "à padaria")
+const LANDMARKS: [(&str, bool); 24] = [
+    ("mercado", false),
+    ("supermercado", false),
+    ("posto de gasolina", false),
+    ("ponto de ônibus", false),
+    ("shopping", false),
+    ("terminal de ônibus", false),
+    ("campo de futebol", false),
+    ("cemitério", false),
+    ("estádio", false),
+    ("fórum", false),
+    ("colégio estadual", false),
+    ("antigo cinema", false),
+    ("padaria", true),
+    ("farmácia", true),
+    ("igreja matriz", true),
+    ("praça", true),
+    ("escola municipal", true),
+    ("creche", true),
+    ("estação de metrô", true),
+    ("estação de trem", true),
+    ("prefeitura", true),
+    ("delegacia", true),
+    ("agência dos Correios", true),
+    ("feira livre", true),
+];
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Entry {
+    CATEGORIA: String,
+    NOME: String,
+    PESO: f64,
+}
+
+struct Words {
+    words: Vec<String>,
+    weights: WeightedIndex<f64>,
+}
+
+impl Words {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> &str {
+        &self.words[self.weights.sample(rng)]
+    }
+}
+
+pub(crate) struct StreetNames {
+    categories: HashMap<String, Words>,
+    kinds: WeightedIndex<f64>,
+}
+
+impl StreetNames {
+    pub(crate) fn load() -> Result<StreetNames, anyhow::Error> {
+        let mut entries: HashMap<String, Vec<Entry>> = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(LOGRADOUROS_CSV.as_bytes());
+        for entry in reader.deserialize() {
+            let entry: Entry = entry?;
+            entries
+                .entry(entry.CATEGORIA.clone())
+                .or_default()
+                .push(entry);
+        }
+
+        let mut categories = HashMap::new();
+        for (category, entries) in entries {
+            let weights = WeightedIndex::new(entries.iter().map(|entry| entry.PESO))?;
+            let words = entries.into_iter().map(|entry| entry.NOME).collect();
+            categories.insert(category, Words { words, weights });
+        }
+
+        let kinds = WeightedIndex::new(NAME_KINDS)?;
+        Ok(StreetNames { categories, kinds })
+    }
+
+    fn word<R: Rng + ?Sized>(&self, category: &str, rng: &mut R) -> &str {
+        self.categories
+            .get(category)
+            .map_or("", |words| words.sample(rng))
+    }
+
+    // A full person's name, e.g. "Antônio Carlos Ferreira" or "Maria Souza"
+    fn person<R: Rng + ?Sized>(&self, female: bool, rng: &mut R) -> String {
+        let first = self.word(if female { "PRENOME_F" } else { "PRENOME_M" }, rng);
+        let surname = self.word("SOBRENOME", rng);
+        match rng.gen_range(0..3) {
+            0 => format!("{} {}", first, surname),
+            1 => format!("{} {} {}", first, self.word("SOBRENOME", rng), surname),
+            _ => {
+                let second = self.word(if female { "PRENOME_F" } else { "PRENOME_M" }, rng);
+                if second == first {
+                    format!("{} {}", first, surname)
+                } else {
+                    format!("{} {} {}", first, second, surname)
+                }
+            }
+        }
+    }
+
+    // A street name such as "Rua Doutor Antônio Ferreira", "Avenida Sete de Setembro"
+    // or "Travessa das Flores"
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> String {
+        let kind = self.word("TIPO", rng);
+        let female = rng.gen_bool(0.3);
+        let name = match self.kinds.sample(rng) {
+            0 => format!(
+                "{} {}",
+                self.word(if female { "TITULO_F" } else { "TITULO_M" }, rng),
+                self.person(female, rng)
+            ),
+            1 => self.person(female, rng),
+            2 => self.word("HOMENAGEM", rng).to_string(),
+            3 => self.word("DATA", rng).to_string(),
+            _ => self.word("TEMA", rng).to_string(),
+        };
+        format!("{} {}", kind, name)
+    }
+}
+
+// Highest building number of a street: a share of the longest street of its type,
+// fixed by the street's ID so every address on it agrees
+fn street_length(street: &T_RHSTU_LOGRADOURO) -> u64 {
+    let longest = STREET_LENGTHS
+        .iter()
+        .find(|(kind, _)| {
+            street
+                .NM_LOGRADOURO
+                .strip_prefix(kind)
+                .is_some_and(|rest| rest.starts_with(' '))
+        })
+        .map_or(DEFAULT_LENGTH, |(_, length)| *length);
+    let hash = street.ID_LOGRADOURO.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 11;
+    let share = SHORTEST_STREET + (1.0 - SHORTEST_STREET) * hash as f64 / (1u64 << 53) as f64;
+    ((longest as f64 * share) as u64).max(1)
+}
+
+// A building number within the length of the street
+pub(crate) fn building_number<R: Rng + ?Sized>(street: &T_RHSTU_LOGRADOURO, rng: &mut R) -> u64 {
+    rng.gen_range(1..=street_length(street))
+}
+
+// Complement of a home: nothing or the house of a shared lot ("Casa 2", "Fundos"), or
+// an apartment ("Apto 12", "Apto 304, Bloco B")
+pub(crate) fn home_complement<R: Rng + ?Sized>(rng: &mut R) -> String {
+    if rng.gen_bool(APARTMENT_SHARE) {
+        let apartment = format!("Apto {}{}", rng.gen_range(1..=20), rng.gen_range(1..=8));
+        if rng.gen_bool(BLOCK_SHARE) {
+            format!("{}, Bloco {}", apartment, block(rng))
+        } else {
+            apartment
+        }
+    } else {
+        match rng.gen_range(0..10) {
+            0..=5 => String::new(),
+            6..=8 => format!("Casa {}", rng.gen_range(1..=4)),
+            _ => "Fundos".to_string(),
+        }
+    }
+}
+
+// Complement of a hospital building: usually none, otherwise its block or tower
+pub(crate) fn building_complement<R: Rng + ?Sized>(rng: &mut R) -> String {
+    match rng.gen_range(0..10) {
+        0..=5 => String::new(),
+        6..=7 => format!("Bloco {}", block(rng)),
+        8 => format!("Torre {}", block(rng)),
+        _ => "Anexo".to_string(),
+    }
+}
+
+fn block<R: Rng + ?Sized>(rng: &mut R) -> char {
+    *['A', 'B', 'C', 'D', 'E'].choose(rng).unwrap()
+}
+
+// A landmark near the address, e.g. "Próximo ao mercado" or "Em frente à farmácia", or
+// nothing when the address goes without one
+pub(crate) fn reference_point<R: Rng + ?Sized>(rng: &mut R) -> String {
+    if !rng.gen_bool(REFERENCE_SHARE) {
+        return String::new();
+    }
+    let (landmark, female) = LANDMARKS.choose(rng).unwrap();
+    let (a, de) = if *female { ("à", "da") } else { ("ao", "do") };
+    match rng.gen_range(0..4) {
+        0 => format!("Próximo {} {}", a, landmark),
+        1 => format!("Em frente {} {}", a, landmark),
+        2 => format!("Ao lado {} {}", de, landmark),
+        _ => format!("Atrás {} {}", de, landmark),
+    }
+}